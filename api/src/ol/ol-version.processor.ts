@@ -326,9 +326,9 @@ export class OlVersionProcessor extends WorkerHost implements OnModuleInit {
     }
 
     const parquetDest = await this.transformerService.transform([transactionsFile]);
-    const files = await fs.promises.readdir(parquetDest);
+    const files = await this.transformerService.parquetFiles(parquetDest);
     for (const file of files) {
-      await this.clickhouseService.insertParquetFile(pathUtil.join(parquetDest, file));
+      await this.clickhouseService.insertParquetFile(file);
     }
 
     await fs.promises.rm(parquetDest, { recursive: true, force: true });
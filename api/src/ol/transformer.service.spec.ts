@@ -0,0 +1,23 @@
+import os from 'node:os';
+import pathUtil from 'node:path';
+import fs from 'node:fs';
+
+import { TransformerService } from './transformer.service.js';
+
+describe('transformer-service', () => {
+  it('only lists the parquet files of the output', async () => {
+    const dest = await fs.promises.mkdtemp(pathUtil.join(os.tmpdir(), 'transformer-spec-'));
+    const written = ['coin_balance.parquet', 'event.parquet', 'manifest.json'];
+    for (const file of written) {
+      await fs.promises.writeFile(pathUtil.join(dest, file), '');
+    }
+
+    const files = await new TransformerService().parquetFiles(dest);
+    expect(files.sort()).toEqual([
+      pathUtil.join(dest, 'coin_balance.parquet'),
+      pathUtil.join(dest, 'event.parquet'),
+    ]);
+
+    await fs.promises.rm(dest, { recursive: true, force: true });
+  });
+});
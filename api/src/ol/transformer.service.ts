@@ -161,6 +161,8 @@ export class TransformerService {
       // Add environment variable to get backtrace on panic
       const env = { ...process.env, RUST_BACKTRACE: '1' };
 
+      // Keeps the default `--on-error=fail`: the callers record the versions
+      // as ingested afterwards, so a skipped transaction would never be retried.
       const proc = spawn(bin, [...validFiles, dest], {
         stdio: 'pipe', // Capture output instead of inheriting
         env
@@ -195,4 +197,15 @@ export class TransformerService {
 
     return dest;
   }
+
+  /**
+   * Paths of the parquet files written to `dest` by `transform`, leaving out
+   * the other files of the run such as `manifest.json`.
+   */
+  public async parquetFiles(dest: string): Promise<string[]> {
+    const files = await fs.promises.readdir(dest);
+    return files
+      .filter((file) => file.endsWith('.parquet'))
+      .map((file) => pathUtil.join(dest, file));
+  }
 }
@@ -0,0 +1,530 @@
+//! Inserts collections straight into ClickHouse through its HTTP interface,
+//! instead of writing parquet files for the API to upload.
+//!
+//! Every batch is encoded as parquet in memory and sent with the insert query
+//! of its collection. With `--clickhouse-queries`, the queries of the API
+//! (`api/src/clickhouse/queries/{collection}.sql`) are used, so columns are
+//! converted exactly like `ClickhouseService.insertParquetFile` does.
+//! Otherwise batches go to `INSERT INTO "{table}" FORMAT Parquet`.
+//!
+//! Requests are blocking: the transformer calls sinks from a blocking section
+//! of the runtime, see `Transformer::run`.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::Path,
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use arrow_array::{cast::AsArray, types::UInt64Type, RecordBatch};
+use parquet::{arrow::arrow_writer::ArrowWriter, file::properties::WriterProperties};
+
+use crate::{
+    error::{Quarantine, TransformError},
+    manifest::Manifest,
+    metrics, models,
+    sink::{Sink, ERRORS},
+};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Table the versions of committed documents are recorded in.
+pub const INGESTED_VERSIONS: &str = "ingested_versions";
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(about = None, long_about = None)]
+pub struct ClickhouseOptions {
+    /// Insert into ClickHouse instead of writing files, e.g.
+    /// `http://localhost:8123`. The destination directory only receives
+    /// `errors.ndjson`.
+    #[arg(long = "clickhouse-url", value_name = "URL")]
+    pub url: Option<String>,
+
+    #[arg(
+        long = "clickhouse-database",
+        env = "CLICKHOUSE_DATABASE",
+        default_value = "default"
+    )]
+    pub database: String,
+
+    #[arg(
+        long = "clickhouse-username",
+        env = "CLICKHOUSE_USERNAME",
+        default_value = "default"
+    )]
+    pub username: String,
+
+    #[arg(
+        long = "clickhouse-password",
+        env = "CLICKHOUSE_PASSWORD",
+        hide_env_values = true
+    )]
+    pub password: Option<String>,
+
+    /// Directory of `{collection}.sql` insert queries, ending with
+    /// `FORMAT Parquet`. A query takes precedence over `--clickhouse-table`.
+    #[arg(long = "clickhouse-queries", value_name = "DIR")]
+    pub queries: Option<String>,
+
+    /// Insert a collection into another table, `collection=table`. Also
+    /// applies to `ingested_versions`. Can be repeated.
+    #[arg(long = "clickhouse-table", value_name = "COLLECTION=TABLE")]
+    pub tables: Vec<String>,
+
+    /// Attempts after a failed insert, waiting twice as long each time.
+    #[arg(long = "clickhouse-retries", default_value_t = 3)]
+    pub retries: u32,
+
+    /// Seconds to wait for ClickHouse to read an insert or to answer it.
+    #[arg(
+        long = "clickhouse-timeout",
+        value_name = "SECONDS",
+        default_value_t = 300
+    )]
+    pub timeout: u64,
+
+    /// Don't record the versions of committed documents in `ingested_versions`.
+    /// Required with `--only` or `--exclude`.
+    #[arg(long = "no-ingested-versions")]
+    pub no_ingested_versions: bool,
+}
+
+/// Sends every batch to ClickHouse as soon as it's written. Once all the
+/// batches of a chunk of a document are in, the versions they hold are
+/// inserted into `ingested_versions`, so a version is only marked ingested
+/// when all of its rows are.
+///
+/// Since the API never transforms an ingested version again, versions with a
+/// skipped item are left out of `ingested_versions`, and runs limited to some
+/// collections are refused unless `--no-ingested-versions` is given.
+///
+/// Inserts are retried on connection errors and 5xx responses. Since a failed
+/// insert may still have been applied, tables should deduplicate (the
+/// `ReplicatedMergeTree` tables do for identical blocks).
+pub struct ClickhouseSink {
+    agent: ureq::Agent,
+    url: String,
+    database: String,
+    username: String,
+    password: Option<String>,
+    retries: u32,
+    queries: HashMap<String, String>,
+    tables: HashMap<String, String>,
+    ingested_versions: bool,
+    properties: WriterProperties,
+
+    /// Versions of the batches written since the last commit.
+    versions: BTreeSet<u64>,
+
+    /// Versions of the items quarantined since the last commit.
+    skipped: BTreeSet<u64>,
+    quarantine: Option<Quarantine>,
+
+    /// Set once the run is finished.
+    manifest: Option<Manifest>,
+}
+
+impl ClickhouseSink {
+    /// `dest` receives `errors.ndjson`. Fails if `options.url` isn't set.
+    pub fn new(dest: &str, options: &ClickhouseOptions) -> Result<ClickhouseSink> {
+        let url = options
+            .url
+            .clone()
+            .ok_or_else(|| anyhow!("missing ClickHouse url"))?;
+
+        let mut tables = HashMap::new();
+        for table in options.tables.iter() {
+            let (collection, table) = table
+                .split_once('=')
+                .ok_or_else(|| anyhow!("invalid table {}, expected collection=table", table))?;
+            tables.insert(collection.to_string(), table.to_string());
+        }
+
+        let queries = match &options.queries {
+            Some(dir) => load_queries(dir)?,
+            None => HashMap::new(),
+        };
+
+        std::fs::create_dir_all(dest).with_context(|| format!("failed to create {}", dest))?;
+
+        let timeout = Duration::from_secs(options.timeout);
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(CONNECT_TIMEOUT)
+            .timeout_read(timeout)
+            .timeout_write(timeout)
+            .build();
+
+        Ok(ClickhouseSink {
+            agent,
+            url,
+            database: options.database.clone(),
+            username: options.username.clone(),
+            password: options.password.clone(),
+            retries: options.retries,
+            queries,
+            tables,
+            ingested_versions: !options.no_ingested_versions,
+            properties: WriterProperties::builder().build(),
+            versions: BTreeSet::new(),
+            skipped: BTreeSet::new(),
+            quarantine: Some(Quarantine::new(format!("{}/{}", dest, ERRORS))),
+            manifest: None,
+        })
+    }
+
+    /// The manifest of the run, once finished. It isn't written anywhere.
+    pub fn manifest(&self) -> Option<&Manifest> {
+        self.manifest.as_ref()
+    }
+
+    fn table<'a>(&'a self, collection: &'a str) -> &'a str {
+        self.tables
+            .get(collection)
+            .map(|it| it.as_str())
+            .unwrap_or(collection)
+    }
+
+    fn insert_query(&self, collection: &str) -> String {
+        match self.queries.get(collection) {
+            Some(query) => query.clone(),
+            None => format!("INSERT INTO \"{}\" FORMAT Parquet", self.table(collection)),
+        }
+    }
+
+    /// Posts `body` with `query`, retrying on connection errors and 5xx.
+    fn execute(&self, query: &str, body: &[u8]) -> Result<()> {
+        let mut delay = Duration::from_secs(1);
+        let mut attempt = 0;
+
+        loop {
+            let mut request = self
+                .agent
+                .post(&self.url)
+                .query("database", &self.database)
+                .query("query", query)
+                .set("X-ClickHouse-User", &self.username);
+            if let Some(password) = &self.password {
+                request = request.set("X-ClickHouse-Key", password);
+            }
+
+            let error = match request.send_bytes(body) {
+                Ok(_) => return Ok(()),
+                Err(ureq::Error::Status(status, response)) => {
+                    let message = response.into_string().unwrap_or_default();
+                    if status < 500 {
+                        bail!("ClickHouse returned {}: {}", status, message.trim());
+                    }
+                    anyhow!("ClickHouse returned {}: {}", status, message.trim())
+                }
+                Err(err) => anyhow!(err),
+            };
+
+            if attempt >= self.retries {
+                return Err(error.context(format!("failed after {} attempts", attempt + 1)));
+            }
+            attempt += 1;
+            tracing::warn!("{:#}, retrying in {}s", error, delay.as_secs());
+            thread::sleep(delay);
+            delay *= 2;
+        }
+    }
+
+    fn encode(&self, batch: &RecordBatch) -> Result<Vec<u8>> {
+        let mut body = Vec::new();
+        let mut writer =
+            ArrowWriter::try_new(&mut body, batch.schema(), Some(self.properties.clone()))?;
+        writer.write(batch)?;
+        writer.close()?;
+        Ok(body)
+    }
+}
+
+impl Sink for ClickhouseSink {
+    fn start(&mut self, manifest: &Manifest) -> Result<()> {
+        if !self.ingested_versions {
+            return Ok(());
+        }
+
+        let missing: Vec<String> = models::registry()
+            .iter()
+            .map(|it| it.name().to_string())
+            .filter(|it| !manifest.collections.contains_key(it))
+            .collect();
+        if !missing.is_empty() {
+            bail!(
+                "refusing to record {} without {}, pass --no-ingested-versions",
+                INGESTED_VERSIONS,
+                missing.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, collection: &str, batch: &RecordBatch) -> Result<()> {
+        let body = self.encode(batch)?;
+        self.execute(&self.insert_query(collection), &body)
+            .with_context(|| format!("failed to insert {}", collection))?;
+        metrics::BYTES_WRITTEN
+            .with_label_values(&[collection])
+            .inc_by(body.len() as u64);
+
+        if let Some(version) = batch.column_by_name("version") {
+            let version = version
+                .as_primitive_opt::<UInt64Type>()
+                .ok_or_else(|| anyhow!("{} has a version column that isn't UInt64", collection))?;
+            self.versions.extend(version.iter().flatten());
+        }
+
+        Ok(())
+    }
+
+    fn quarantine(&mut self, error: &TransformError) -> Result<()> {
+        self.skipped.insert(error.version);
+        match &mut self.quarantine {
+            Some(quarantine) => quarantine.write(error),
+            None => Ok(()),
+        }
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let mut versions = std::mem::take(&mut self.versions);
+        let skipped = std::mem::take(&mut self.skipped);
+        if !self.ingested_versions {
+            return Ok(());
+        }
+
+        let before = versions.len();
+        versions.retain(|it| !skipped.contains(it));
+        if versions.len() < before {
+            tracing::warn!(
+                "leaving {} versions with skipped items out of {}",
+                before - versions.len(),
+                INGESTED_VERSIONS
+            );
+        }
+        if versions.is_empty() {
+            return Ok(());
+        }
+
+        let body: String = versions.iter().map(|it| format!("{}\n", it)).collect();
+        let query = format!(
+            "INSERT INTO \"{}\" (\"version\") FORMAT TSV",
+            self.table(INGESTED_VERSIONS)
+        );
+        self.execute(&query, body.as_bytes())
+            .with_context(|| format!("failed to insert {}", INGESTED_VERSIONS))
+    }
+
+    fn finish(&mut self, manifest: &Manifest) -> Result<()> {
+        self.commit()?;
+        if let Some(quarantine) = self.quarantine.take() {
+            quarantine.close()?;
+        }
+        self.manifest = Some(manifest.clone());
+        Ok(())
+    }
+
+    /// Rows of the document being written may already be in their tables,
+    /// but its versions aren't recorded as ingested.
+    fn abort(&mut self) -> Result<()> {
+        self.versions.clear();
+        self.skipped.clear();
+        if let Some(quarantine) = self.quarantine.take() {
+            quarantine.close()?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the `{collection}.sql` files of `dir`.
+fn load_queries(dir: &str) -> Result<HashMap<String, String>> {
+    let mut queries = HashMap::new();
+    let entries =
+        std::fs::read_dir(dir).with_context(|| format!("failed to read queries in {}", dir))?;
+
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|it| it.to_str()) != Some("sql") {
+            continue;
+        }
+
+        let Some(collection) = path.file_stem().and_then(|it| it.to_str()) else {
+            continue;
+        };
+        let query = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", Path::display(&path)))?;
+        queries.insert(collection.to_string(), query);
+    }
+
+    Ok(queries)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::UInt64Array;
+    use clap::Parser;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        error::OnError,
+        test_utils::{
+            block, block_metadata, documents, slow_wallet, state_checkpoint, temp_dir,
+            write_resource, MockServer,
+        },
+        Transformer,
+    };
+
+    fn targets(server: &MockServer) -> Vec<String> {
+        server.requests().into_iter().map(|it| it.target).collect()
+    }
+
+    /// Bodies of the inserts into `ingested_versions`.
+    fn ingested(server: &MockServer) -> Vec<String> {
+        server
+            .requests()
+            .into_iter()
+            .filter(|it| it.target.contains(INGESTED_VERSIONS))
+            .map(|it| it.body)
+            .collect()
+    }
+
+    #[derive(Parser)]
+    struct Options {
+        #[command(flatten)]
+        clickhouse: ClickhouseOptions,
+    }
+
+    fn sink(test: &str, server: &MockServer, args: &[&str]) -> ClickhouseSink {
+        let options = Options::parse_from(
+            [
+                "test",
+                "--clickhouse-url",
+                &server.url,
+                "--clickhouse-retries",
+                "1",
+            ]
+            .iter()
+            .chain(args),
+        )
+        .clickhouse;
+        ClickhouseSink::new(&temp_dir(test), &options).unwrap()
+    }
+
+    fn versions_batch() -> RecordBatch {
+        RecordBatch::try_from_iter([("version", Arc::new(UInt64Array::from(vec![1, 2])) as _)])
+            .unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn versions_are_ingested_once_inserted() {
+        let server = MockServer::with_responses(vec![]);
+        let (documents, input) = documents(vec![block(1, 3)]);
+        Transformer::builder()
+            .documents(documents, input)
+            .sink(sink("clickhouse-ingested", &server, &[]))
+            .build()
+            .unwrap()
+            .run()
+            .await
+            .unwrap();
+
+        let targets = targets(&server);
+        assert!(targets.iter().any(|it| it.contains("slow_wallet")));
+        assert!(targets.last().unwrap().contains(INGESTED_VERSIONS));
+        assert_eq!(ingested(&server), ["1\n2\n3\n"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn skipped_versions_are_not_ingested() {
+        let server = MockServer::with_responses(vec![]);
+        let invalid = write_resource(
+            "0x1",
+            "0x1::slow_wallet::SlowWallet",
+            json!({ "unlocked": "many", "transferred": "0" }),
+        );
+        let (documents, input) = documents(vec![vec![
+            block_metadata(1, vec![slow_wallet("0x101", 1)]),
+            block_metadata(2, vec![invalid]),
+            state_checkpoint(3),
+        ]]);
+        let sink = Transformer::builder()
+            .documents(documents, input)
+            .on_error(OnError::Skip)
+            .sink(sink("clickhouse-skipped", &server, &[]))
+            .build()
+            .unwrap()
+            .run()
+            .await
+            .unwrap();
+
+        assert_eq!(sink.manifest().unwrap().skipped, 1);
+        assert_eq!(ingested(&server), ["1\n3\n"]);
+    }
+
+    #[tokio::test]
+    async fn subsets_of_the_collections_need_no_ingested_versions() {
+        let server = MockServer::with_responses(vec![]);
+        let run = |sink| {
+            let (documents, input) = documents(vec![block(1, 3)]);
+            Transformer::builder()
+                .documents(documents, input)
+                .collections(["slow_wallet"])
+                .sink(sink)
+                .build()
+                .unwrap()
+                .run()
+        };
+
+        let err = run(sink("clickhouse-subset", &server, &[]))
+            .await
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string().contains("--no-ingested-versions"),
+            "{}",
+            err
+        );
+        assert!(targets(&server).is_empty());
+
+        run(sink(
+            "clickhouse-subset",
+            &server,
+            &["--no-ingested-versions"],
+        ))
+        .await
+        .unwrap();
+        assert_eq!(targets(&server).len(), 1);
+        assert!(ingested(&server).is_empty());
+    }
+
+    #[test]
+    fn inserts_are_retried_on_server_errors() {
+        let server = MockServer::with_responses(vec![(503, "too many parts")]);
+        let mut sink = sink("clickhouse-retried", &server, &[]);
+        sink.write("slow_wallet", &versions_batch()).unwrap();
+
+        let targets = targets(&server);
+        assert_eq!(targets.len(), 2);
+        assert_eq!(targets[0], targets[1]);
+        assert!(targets[0].contains("slow_wallet"));
+    }
+
+    #[test]
+    fn client_errors_are_not_retried() {
+        let server = MockServer::with_responses(vec![(400, "Syntax error")]);
+        let mut sink = sink("clickhouse-rejected", &server, &[]);
+        let err = sink.write("slow_wallet", &versions_batch()).err().unwrap();
+
+        assert!(
+            format!("{:#}", err).contains("400: Syntax error"),
+            "{:#}",
+            err
+        );
+        assert_eq!(targets(&server).len(), 1);
+    }
+}
@@ -0,0 +1,59 @@
+use anyhow::Result;
+use arrow_array::RecordBatch;
+use arrow_schema::Schema;
+use diem_api_types::{MoveStructTag, Transaction, WriteSetChange};
+
+/// A collection accumulates rows extracted from transactions and hands them
+/// out as record batches, which the `Sink` appends to its output.
+///
+/// Collections are registered in `models::registry()`. The main loop feeds
+/// every transaction to `on_transaction` and every write set change of that
+/// transaction to `on_write_set_change`, so a collection only has to pick out
+/// the data it cares about. Every worker owns its own set of collections, one
+/// input file at a time, and drains them with `take_batch` at the end of it.
+pub trait Collection {
+    /// Name of the collection. Used to name the output, e.g. `{name}.parquet`.
+    fn name(&self) -> &str;
+
+    /// Revision of the columns. Bump it whenever a column is added, removed or
+    /// changes type, it's recorded in the output files (see `manifest`).
+    fn schema_version(&self) -> u32 {
+        1
+    }
+
+    /// Columns of the batches returned by `take_batch`, in the same order.
+    /// Used to generate the ClickHouse tables (see `ddl`), so it must be kept
+    /// in sync with `take_batch`.
+    fn schema(&self) -> Schema;
+
+    /// Called once for every transaction.
+    ///
+    /// An error skips the transaction for this collection only, it must not
+    /// leave partially pushed rows behind.
+    fn on_transaction(&mut self, _transaction: &Transaction) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called for every write set change of a committed transaction. Errors
+    /// are handled like in `on_transaction`.
+    fn on_write_set_change(
+        &mut self,
+        _version: u64,
+        _change_index: u64,
+        _change: &WriteSetChange,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Tells whether `on_write_set_change` extracts resources of type `typ`.
+    /// Collections reading resources must override it, resources written
+    /// that no collection extracts are counted as unknown (see `metrics`).
+    fn extracts_resource(&self, _typ: &MoveStructTag) -> bool {
+        false
+    }
+
+    /// Moves the collected rows out of the collection. Returns `None` when
+    /// there is nothing buffered, and an error when the rows can't be turned
+    /// into arrays (e.g. an address that isn't 32 bytes).
+    fn take_batch(&mut self) -> Result<Option<RecordBatch>>;
+}
@@ -0,0 +1,23 @@
+use std::str::FromStr;
+
+use diem_api_types::HexEncodedBytes;
+use lazy_static::lazy_static;
+
+lazy_static! {
+    pub static ref LIBRA_COIN_HANDLE: HexEncodedBytes = HexEncodedBytes::from_str(
+        "FC074A2B7638A50BA678CE381A2350A28264F4DA004603ADB8DC36D125750108"
+    )
+    .unwrap();
+    pub static ref LIBRA_COIN_KEY: HexEncodedBytes = HexEncodedBytes::from_str(
+        "A7E1AF6D61E958DBEFE8F35550AAB562F8923634CD7F438BC5190E99CA5FB07C"
+    )
+    .unwrap();
+    pub static ref ROOT_ACCOUNT_ADDRESS: HexEncodedBytes = HexEncodedBytes::from_str(
+        "0000000000000000000000000000000000000000000000000000000000000001"
+    )
+    .unwrap();
+    pub static ref NULL_ADDRESS: HexEncodedBytes = HexEncodedBytes::from_str(
+        "0000000000000000000000000000000000000000000000000000000000000000"
+    )
+    .unwrap();
+}
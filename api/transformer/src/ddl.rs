@@ -0,0 +1,255 @@
+//! ClickHouse tables of the collections, generated from their Arrow schemas
+//! (`Collection::schema`), and a check of `api/tables.sql` against them.
+//!
+//! Types follow what the insert queries of the API read from the parquet
+//! files: 16 and 32 byte binaries are `UInt128` and `UInt256`, other binaries
+//! are `String`.
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Context, Result};
+use arrow_schema::{DataType, Field, Schema};
+use regex::Regex;
+
+/// Schema languages `transformer schema` can generate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Ddl {
+    Clickhouse,
+}
+
+/// ClickHouse type of an Arrow field.
+pub fn clickhouse_type(field: &Field) -> Result<String> {
+    let typ = match field.data_type() {
+        DataType::Boolean => "Boolean".to_string(),
+        DataType::UInt8 => "UInt8".to_string(),
+        DataType::UInt16 => "UInt16".to_string(),
+        DataType::UInt32 => "UInt32".to_string(),
+        DataType::UInt64 => "UInt64".to_string(),
+        DataType::Int8 => "Int8".to_string(),
+        DataType::Int16 => "Int16".to_string(),
+        DataType::Int32 => "Int32".to_string(),
+        DataType::Int64 => "Int64".to_string(),
+        DataType::Float32 => "Float32".to_string(),
+        DataType::Float64 => "Float64".to_string(),
+        DataType::Utf8 | DataType::LargeUtf8 | DataType::Binary | DataType::LargeBinary => {
+            "String".to_string()
+        }
+        DataType::FixedSizeBinary(16) => "UInt128".to_string(),
+        DataType::FixedSizeBinary(32) => "UInt256".to_string(),
+        DataType::FixedSizeBinary(size) => format!("FixedString({})", size),
+        DataType::List(item) | DataType::LargeList(item) => {
+            format!("Array({})", clickhouse_type(item)?)
+        }
+        typ => bail!("{} has no ClickHouse equivalent ({})", field.name(), typ),
+    };
+
+    if field.is_nullable() {
+        Ok(format!("Nullable({})", typ))
+    } else {
+        Ok(typ)
+    }
+}
+
+/// `CREATE TABLE` statement of a collection, in the layout of `tables.sql`.
+/// With a cluster, the table is a `ReplicatedMergeTree` created on it,
+/// otherwise a `MergeTree` like in `tables_local.sql`. Rows are ordered by
+/// `(version, change_index)`, or the first column when there is no version.
+pub fn create_table(name: &str, schema: &Schema, cluster: Option<&str>) -> Result<String> {
+    let mut ddl = match cluster {
+        Some(cluster) => format!("CREATE TABLE \"{}\" ON CLUSTER \"{}\" (\n", name, cluster),
+        None => format!("CREATE TABLE \"{}\" (\n", name),
+    };
+
+    let columns = schema
+        .fields()
+        .iter()
+        .map(|field| {
+            Ok(format!(
+                "  \"{}\" {}",
+                field.name(),
+                clickhouse_type(field)?
+            ))
+        })
+        .collect::<Result<Vec<_>>>()
+        .with_context(|| format!("failed to generate the table of {}", name))?;
+    ddl.push_str(&columns.join(",\n"));
+    ddl.push_str("\n)\n");
+
+    ddl.push_str(match cluster {
+        Some(_) => "ENGINE = ReplicatedMergeTree\n",
+        None => "ENGINE = MergeTree\n",
+    });
+
+    let order_by: Vec<&str> = ["version", "change_index"]
+        .into_iter()
+        .filter(|it| schema.column_with_name(it).is_some())
+        .collect();
+    let order_by = match order_by.as_slice() {
+        [] => match schema.fields().first() {
+            Some(field) => format!("\"{}\"", field.name()),
+            None => "tuple()".to_string(),
+        },
+        [column] => format!("\"{}\"", column),
+        columns => format!("(\"{}\")", columns.join("\", \"")),
+    };
+    ddl.push_str(&format!("ORDER BY {};\n", order_by));
+
+    Ok(ddl)
+}
+
+/// Columns of the tables created by a SQL file, as name and type, by table.
+/// Commented out columns are left out.
+pub fn parse_tables(sql: &str) -> Result<BTreeMap<String, Vec<(String, String)>>> {
+    let sql: String = sql
+        .lines()
+        .map(|line| match line.find("--") {
+            Some(comment) => &line[..comment],
+            None => line,
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let create_table = Regex::new(
+        r#"(?i)CREATE\s+TABLE\s+(?:IF\s+NOT\s+EXISTS\s+)?(?:[`"]?\w+[`"]?\.)?[`"]?(\w+)[`"]?[^(]*\("#,
+    )
+    .unwrap();
+
+    let mut tables = BTreeMap::new();
+    for captures in create_table.captures_iter(&sql) {
+        let name = captures[1].to_string();
+        let start = captures.get(0).unwrap().end();
+
+        // Columns end at the parenthesis closing the one of the statement.
+        let mut depth = 1;
+        let mut end = None;
+        for (index, char) in sql[start..].char_indices() {
+            match char {
+                '(' => depth += 1,
+                ')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        end = Some(start + index);
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        let end = end.with_context(|| format!("unterminated column list in table {}", name))?;
+
+        let mut columns = Vec::new();
+        for column in split_columns(&sql[start..end]) {
+            let column = column.trim();
+            let Some((column, typ)) = column.split_once(char::is_whitespace) else {
+                continue;
+            };
+            if ["INDEX", "PROJECTION", "CONSTRAINT"]
+                .iter()
+                .any(|it| column.eq_ignore_ascii_case(it))
+            {
+                continue;
+            }
+
+            let column = column.trim_matches(|it| it == '"' || it == '`');
+            columns.push((column.to_string(), normalize_type(typ)));
+        }
+
+        tables.insert(name, columns);
+    }
+
+    Ok(tables)
+}
+
+/// Splits a column list on the commas that aren't inside a type.
+fn split_columns(columns: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (index, char) in columns.char_indices() {
+        match char {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&columns[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&columns[start..]);
+    parts
+        .into_iter()
+        .filter(|it| !it.trim().is_empty())
+        .collect()
+}
+
+/// Type of a column definition, without its default value or codec and with
+/// `Bool` spelled `Boolean`.
+fn normalize_type(definition: &str) -> String {
+    let mut typ = String::new();
+    let mut depth = 0;
+    for char in definition.trim().chars() {
+        match char {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            char if char.is_whitespace() && depth == 0 => break,
+            char if char.is_whitespace() => continue,
+            _ => {}
+        }
+        typ.push(char);
+    }
+
+    if typ == "Bool" {
+        "Boolean".to_string()
+    } else {
+        typ.replace("(Bool)", "(Boolean)")
+    }
+}
+
+/// Differences between the table of every collection and the tables of a
+/// SQL file: missing tables, missing or extra columns and mismatched types.
+/// Tables of the file that no collection produces are ignored.
+pub fn check_tables(
+    collections: &[(String, Schema)],
+    tables: &BTreeMap<String, Vec<(String, String)>>,
+) -> Result<Vec<String>> {
+    let mut problems = Vec::new();
+
+    for (name, schema) in collections.iter() {
+        let Some(columns) = tables.get(name) else {
+            problems.push(format!("{}: missing table", name));
+            continue;
+        };
+
+        for field in schema.fields().iter() {
+            let expected = clickhouse_type(field)?;
+            match columns.iter().find(|(column, _)| column == field.name()) {
+                None => problems.push(format!(
+                    "{}.{}: missing column, expected {}",
+                    name,
+                    field.name(),
+                    expected
+                )),
+                Some((_, typ)) if *typ != expected => problems.push(format!(
+                    "{}.{}: type is {}, the collection has {}",
+                    name,
+                    field.name(),
+                    typ,
+                    expected
+                )),
+                Some(_) => {}
+            }
+        }
+
+        for (column, typ) in columns.iter() {
+            if schema.column_with_name(column).is_none() {
+                problems.push(format!(
+                    "{}.{}: {} column isn't in the collection",
+                    name, column, typ
+                ));
+            }
+        }
+    }
+
+    Ok(problems)
+}
@@ -0,0 +1,142 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use diem_api_types::WriteSetChange;
+use serde::Serialize;
+
+/// A transaction, or one of its write set changes, that couldn't be transformed.
+#[derive(Clone, Debug, Serialize)]
+pub struct TransformError {
+    pub version: u64,
+
+    /// Set when the error comes from a write set change.
+    pub change_index: Option<u64>,
+
+    /// Type of the resource being written or deleted, if any.
+    pub move_type: Option<String>,
+
+    /// Set when a collection rejected the item. Other collections still got it.
+    pub collection: Option<String>,
+
+    pub reason: String,
+}
+
+impl TransformError {
+    pub fn transaction(version: u64, reason: String) -> TransformError {
+        TransformError {
+            version,
+            change_index: None,
+            move_type: None,
+            collection: None,
+            reason,
+        }
+    }
+
+    pub fn change(
+        version: u64,
+        change_index: u64,
+        change: &WriteSetChange,
+        reason: String,
+    ) -> TransformError {
+        let move_type = match change {
+            WriteSetChange::WriteResource(change) => Some(change.data.typ.to_string()),
+            WriteSetChange::DeleteResource(change) => Some(change.resource.to_string()),
+            _ => None,
+        };
+
+        TransformError {
+            version,
+            change_index: Some(change_index),
+            move_type,
+            collection: None,
+            reason,
+        }
+    }
+
+    pub fn with_collection(mut self, collection: &str) -> TransformError {
+        self.collection = Some(collection.to_string());
+        self
+    }
+}
+
+impl fmt::Display for TransformError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "version {}", self.version)?;
+        if let Some(change_index) = self.change_index {
+            write!(f, ", change {}", change_index)?;
+        }
+        if let Some(move_type) = &self.move_type {
+            write!(f, " ({})", move_type)?;
+        }
+        if let Some(collection) = &self.collection {
+            write!(f, " in {}", collection)?;
+        }
+        write!(f, ": {}", self.reason)
+    }
+}
+
+impl std::error::Error for TransformError {}
+
+/// What to do when a transaction can't be transformed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OnError {
+    /// Stop at the first error.
+    Fail,
+
+    /// Leave the item out of the output and record it in `errors.ndjson`.
+    Skip,
+}
+
+/// Lines of `errors.ndjson`, one `TransformError` per line. The file is only
+/// created once there is something to write.
+pub struct Quarantine {
+    path: String,
+    file: Option<BufWriter<File>>,
+    count: usize,
+}
+
+impl Quarantine {
+    pub fn new(path: String) -> Quarantine {
+        Quarantine {
+            path,
+            file: None,
+            count: 0,
+        }
+    }
+
+    pub fn write(&mut self, error: &TransformError) -> Result<()> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                let file = File::create(&self.path)
+                    .with_context(|| format!("failed to create {}", self.path))?;
+                self.file.insert(BufWriter::new(file))
+            }
+        };
+
+        serde_json::to_writer(&mut *file, error)?;
+        file.write_all(b"\n")?;
+        self.count += 1;
+
+        Ok(())
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Flushes the file and tells how many items were skipped, if any.
+    pub fn close(self) -> Result<()> {
+        if let Some(mut file) = self.file {
+            file.flush()
+                .with_context(|| format!("failed to write {}", self.path))?;
+            tracing::warn!("skipped {} items, see {}", self.count, self.path);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,596 @@
+//! Pulls transactions from the REST API of a node (`/v1/transactions`), so a
+//! run doesn't need transaction files. Pages go straight to the collections,
+//! as documents of up to `--page-size` transactions.
+//!
+//! The pages can also be saved in the layout of the `transactions/` archives
+//! uploaded by `ol-version-batch.processor.ts`, see `Archiver`.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Read},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use flate2::{write::GzEncoder, Compression};
+use serde::de::IgnoredAny;
+use serde_json::value::RawValue;
+use sha2::{Digest, Sha256};
+
+use crate::{input::Document, manifest::InputManifest};
+
+/// Headers of the ledger version (and oldest version kept) of the node that
+/// served a response. Aptos nodes use the `X-Aptos-` prefix.
+const LEDGER_VERSION_HEADERS: [&str; 2] = ["X-Diem-Ledger-Version", "X-Aptos-Ledger-Version"];
+const OLDEST_VERSION_HEADERS: [&str; 2] = [
+    "X-Diem-Ledger-Oldest-Version",
+    "X-Aptos-Ledger-Oldest-Version",
+];
+
+/// Versions per archive and per file of an archive, like
+/// `OlVersionBatchProcessor.BATCH_SIZE * TRANSACTIONS_PER_REQUEST` and
+/// `TRANSACTIONS_PER_REQUEST`.
+pub const ARCHIVE_VERSIONS: u64 = 10_000;
+pub const ARCHIVE_FILE_VERSIONS: u64 = 100;
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(about = None, long_about = None)]
+pub struct FetchOptions {
+    /// URL of the node, e.g. `http://localhost:8080`.
+    #[arg(long, env = "NODE_URL")]
+    pub node: String,
+
+    /// First version to fetch.
+    #[arg(long, default_value_t = 0)]
+    pub start: u64,
+
+    /// Number of versions to fetch. Up to the ledger version of the node when
+    /// not set.
+    #[arg(long)]
+    pub limit: Option<u64>,
+
+    /// Transactions per request. Nodes return at most 100.
+    #[arg(long, default_value_t = 100)]
+    pub page_size: u64,
+
+    /// Attempts after a failed request, waiting twice as long each time.
+    #[arg(
+        id = "fetch_retries",
+        long = "fetch-retries",
+        value_name = "RETRIES",
+        default_value_t = 5
+    )]
+    pub retries: u32,
+
+    /// Timeout of a request, in seconds.
+    #[arg(long = "fetch-timeout", value_name = "SECONDS", default_value_t = 300)]
+    pub timeout: u64,
+
+    /// Also save the transactions in this directory, as `{from}-{from+9900}.tgz`
+    /// archives of 10 000 versions like the `transactions/` of the API. Only
+    /// complete archives are saved.
+    #[arg(long, value_name = "DIR")]
+    pub save_archive: Option<String>,
+}
+
+/// One response of `/v1/transactions`.
+pub struct Page {
+    /// The request, e.g. `http://localhost:8080/v1/transactions?start=0&limit=100`.
+    pub name: String,
+
+    /// The transactions, as returned.
+    pub body: Vec<u8>,
+
+    /// Number of transactions in `body`.
+    pub transactions: u64,
+
+    pub ledger_version: Option<u64>,
+    pub oldest_version: Option<u64>,
+}
+
+pub struct Fetcher {
+    options: FetchOptions,
+    node: String,
+    url: String,
+    agent: ureq::Agent,
+}
+
+impl Fetcher {
+    pub fn new(options: &FetchOptions) -> Result<Fetcher> {
+        if options.page_size == 0 {
+            bail!("page size must be at least 1");
+        }
+        if options.limit == Some(0) {
+            bail!("limit must be at least 1");
+        }
+
+        let node = options.node.trim_end_matches('/');
+        let node = node.strip_suffix("/v1").unwrap_or(node);
+
+        Ok(Fetcher {
+            options: options.clone(),
+            node: node.to_string(),
+            url: format!("{}/v1/transactions", node),
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(options.timeout))
+                .build(),
+        })
+    }
+
+    /// `/v1/transactions` of the node.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Name of the input in the manifest.
+    pub fn name(&self) -> String {
+        match self.options.limit {
+            Some(limit) => format!("{}?start={}&limit={}", self.url, self.options.start, limit),
+            None => format!("{}?start={}", self.url, self.options.start),
+        }
+    }
+
+    /// Fetches every page and hands them to `f` as documents, in version
+    /// order. Stops early at the ledger version of the node. Returns the size
+    /// and checksum of the pages, as fetched.
+    pub fn fetch(&self, mut f: impl FnMut(Document) -> Result<()>) -> Result<InputManifest> {
+        let mut start = self.options.start;
+        let end = self.options.limit.map(|limit| start.saturating_add(limit));
+        let mut archiver = self.options.save_archive.as_deref().map(Archiver::new);
+
+        let mut hasher = Sha256::new();
+        let mut bytes = 0;
+        let mut ledger_end = None;
+        loop {
+            let mut limit = self.options.page_size;
+            for end in [end, ledger_end].into_iter().flatten() {
+                limit = limit.min(end.saturating_sub(start));
+            }
+            if limit == 0 {
+                break;
+            }
+
+            let page = self.page(start, limit)?;
+            if let Some(oldest) = page.oldest_version {
+                if start < oldest {
+                    bail!("versions before {} are pruned on {}", oldest, self.url);
+                }
+            }
+            if let Some(ledger_version) = page.ledger_version {
+                if ledger_end.is_none() && start > ledger_version {
+                    bail!(
+                        "version {} is after the ledger version {} of {}",
+                        start,
+                        ledger_version,
+                        self.url
+                    );
+                }
+                ledger_end = Some(ledger_version + 1);
+            }
+            if page.transactions == 0 {
+                break;
+            }
+
+            let last = start + page.transactions - 1;
+            if let Some(archiver) = &mut archiver {
+                archiver.add(start, &page.body)?;
+            }
+            hasher.update(&page.body);
+            bytes += page.body.len() as u64;
+            f(Document::from_bytes(page.name, page.body))?;
+
+            start = last + 1;
+        }
+
+        if let Some(end) = end {
+            if start < end {
+                tracing::warn!(
+                    "stopped at version {}, the ledger version of {}",
+                    start.saturating_sub(1),
+                    self.url
+                );
+            }
+        }
+        if let Some(archiver) = archiver {
+            archiver.finish()?;
+        }
+
+        Ok(InputManifest {
+            path: self.name(),
+            bytes,
+            sha256: hex::encode(hasher.finalize()),
+        })
+    }
+
+    /// Current ledger version of the node, from `/v1`.
+    pub fn ledger_version(&self) -> Result<u64> {
+        #[derive(serde::Deserialize)]
+        struct LedgerInfo {
+            ledger_version: String,
+        }
+
+        let url = format!("{}/v1", self.node);
+        let info: LedgerInfo = serde_json::from_reader(self.get(&url, &[])?.into_reader())
+            .with_context(|| format!("invalid ledger info from {}", url))?;
+        info.ledger_version
+            .parse()
+            .with_context(|| format!("invalid ledger version from {}", url))
+    }
+
+    /// Requests `limit` transactions from `start`.
+    pub fn page(&self, start: u64, limit: u64) -> Result<Page> {
+        let query = [("start", start.to_string()), ("limit", limit.to_string())];
+        let response = self
+            .get(&self.url, &query)
+            .with_context(|| format!("failed to fetch version {}", start))?;
+
+        let ledger_version = header(&response, &LEDGER_VERSION_HEADERS)?;
+        let oldest_version = header(&response, &OLDEST_VERSION_HEADERS)?;
+
+        let mut body = Vec::new();
+        response
+            .into_reader()
+            .read_to_end(&mut body)
+            .with_context(|| format!("failed to read {}", self.url))?;
+        let transactions: Vec<IgnoredAny> = serde_json::from_slice(&body)
+            .with_context(|| format!("expected an array of transactions from {}", self.url))?;
+
+        Ok(Page {
+            name: format!("{}?start={}&limit={}", self.url, start, limit),
+            body,
+            transactions: transactions.len() as u64,
+            ledger_version,
+            oldest_version,
+        })
+    }
+
+    /// GETs `url`, retrying on connection errors, 429 and 5xx. `Retry-After`
+    /// is honored when the node sets it.
+    fn get(&self, url: &str, query: &[(&str, String)]) -> Result<ureq::Response> {
+        let mut delay = Duration::from_secs(1);
+        let mut attempt = 0;
+
+        loop {
+            let mut request = self.agent.get(url);
+            for (name, value) in query.iter() {
+                request = request.query(name, value);
+            }
+
+            let (error, retry_after) = match request.call() {
+                Ok(response) => return Ok(response),
+                Err(ureq::Error::Status(status, response)) => {
+                    let retry_after = response
+                        .header("Retry-After")
+                        .and_then(|it| it.parse::<u64>().ok())
+                        .map(Duration::from_secs);
+                    let message = response.into_string().unwrap_or_default();
+                    if status < 500 && status != 429 {
+                        bail!("{} returned {}: {}", url, status, message.trim());
+                    }
+                    (
+                        anyhow!("{} returned {}: {}", url, status, message.trim()),
+                        retry_after,
+                    )
+                }
+                Err(err) => (anyhow!(err), None),
+            };
+
+            if attempt >= self.options.retries {
+                return Err(error.context(format!("failed after {} attempts", attempt + 1)));
+            }
+            attempt += 1;
+            let wait = retry_after.unwrap_or(delay);
+            tracing::warn!("{:#}, retrying in {}s", error, wait.as_secs());
+            thread::sleep(wait);
+            delay *= 2;
+        }
+    }
+}
+
+/// First of `names` set on the response, as a version.
+fn header(response: &ureq::Response, names: &[&str]) -> Result<Option<u64>> {
+    let Some(value) = names.iter().find_map(|name| response.header(name)) else {
+        return Ok(None);
+    };
+    let version = value
+        .parse()
+        .with_context(|| format!("invalid {} header: {}", names[0], value))?;
+    Ok(Some(version))
+}
+
+/// Saves transactions in the layout of `ol-version-batch.processor.ts`: an
+/// archive of every 10 000 versions from a multiple of 10 000, named
+/// `{from}-{to}.tgz` after the first versions of its first and last files,
+/// holding `{first}-{last}.json` files of 100 versions each.
+///
+/// Pages of any size are split or joined into those files. Only complete
+/// archives are saved, so an archive is never named after versions it
+/// doesn't hold: versions before the first multiple of 10 000 and after the
+/// last one filled are left out.
+pub(crate) struct Archiver {
+    dir: String,
+
+    /// Archive being filled, with the first version of its next file.
+    archive: Option<(Archive, u64)>,
+
+    /// Transactions of the next file.
+    file: Vec<Box<RawValue>>,
+
+    /// Version after the last one added.
+    next: Option<u64>,
+
+    /// Set once versions before the first archive were left out.
+    skipped: bool,
+}
+
+impl Archiver {
+    pub(crate) fn new(dir: &str) -> Archiver {
+        Archiver {
+            dir: dir.to_string(),
+            archive: None,
+            file: Vec::new(),
+            next: None,
+            skipped: false,
+        }
+    }
+
+    /// Adds the transactions of a page, starting at version `first`. Pages
+    /// must follow each other.
+    pub(crate) fn add(&mut self, first: u64, body: &[u8]) -> Result<()> {
+        if let Some(next) = self.next {
+            if first != next {
+                bail!("expected version {} in the archive, got {}", next, first);
+            }
+        }
+        let transactions: Vec<Box<RawValue>> = serde_json::from_slice(body)
+            .with_context(|| format!("expected an array of transactions at {}", first))?;
+
+        for (version, transaction) in (first..).zip(transactions) {
+            self.next = Some(version + 1);
+            if self.archive.is_none() {
+                if version % ARCHIVE_VERSIONS != 0 {
+                    if !self.skipped {
+                        tracing::warn!(
+                            "archives start at multiples of {}, version {} and the next ones \
+                             until then aren't saved",
+                            ARCHIVE_VERSIONS,
+                            version
+                        );
+                        self.skipped = true;
+                    }
+                    continue;
+                }
+                self.archive = Some((Archive::create(&self.dir, version)?, version));
+            }
+
+            self.file.push(transaction);
+            if self.file.len() as u64 == ARCHIVE_FILE_VERSIONS {
+                self.flush_file()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends the next file to the archive, and saves the archive once full.
+    fn flush_file(&mut self) -> Result<()> {
+        let Some((archive, first)) = &mut self.archive else {
+            return Ok(());
+        };
+
+        let last = *first + ARCHIVE_FILE_VERSIONS - 1;
+        let content = serde_json::to_vec(&self.file)?;
+        archive.append(&format!("{}-{}.json", first, last), &content)?;
+        self.file.clear();
+        *first = last + 1;
+
+        if *first % ARCHIVE_VERSIONS == 0 {
+            let (archive, _) = self.archive.take().unwrap();
+            let path = archive.finish(last + 1 - ARCHIVE_FILE_VERSIONS)?;
+            tracing::info!("saved {}", path);
+        }
+        Ok(())
+    }
+
+    /// Drops the archive being filled, if any.
+    pub(crate) fn finish(self) -> Result<()> {
+        let Some((archive, _)) = self.archive else {
+            return Ok(());
+        };
+        tracing::warn!(
+            "versions {} to {} don't fill an archive, they aren't saved",
+            archive.start,
+            self.next.unwrap_or(archive.start).saturating_sub(1)
+        );
+        archive.discard()
+    }
+}
+
+/// `{dir}/{start}-{end}.tgz`, written as `{start}.tgz.partial` until the end
+/// is known.
+struct Archive {
+    dir: String,
+    start: u64,
+    builder: tar::Builder<GzEncoder<BufWriter<File>>>,
+}
+
+impl Archive {
+    fn create(dir: &str, start: u64) -> Result<Archive> {
+        std::fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir))?;
+        let path = Archive::partial_path(dir, start);
+        let file = File::create(&path).with_context(|| format!("failed to create {}", path))?;
+
+        Ok(Archive {
+            dir: dir.to_string(),
+            start,
+            builder: tar::Builder::new(GzEncoder::new(
+                BufWriter::new(file),
+                Compression::default(),
+            )),
+        })
+    }
+
+    fn partial_path(dir: &str, start: u64) -> String {
+        format!("{}/{}.tgz.partial", dir, start)
+    }
+
+    fn append(&mut self, name: &str, content: &[u8]) -> Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|it| it.as_secs())
+                .unwrap_or_default(),
+        );
+        header.set_cksum();
+        self.builder
+            .append_data(&mut header, name, content)
+            .with_context(|| format!("failed to add {} to the archive", name))
+    }
+
+    /// Closes the archive and names it `{start}-{end}.tgz`.
+    fn finish(self, end: u64) -> Result<String> {
+        let partial = Archive::partial_path(&self.dir, self.start);
+        self.builder
+            .into_inner()
+            .and_then(|encoder| encoder.finish())
+            .and_then(|mut writer| std::io::Write::flush(&mut writer))
+            .with_context(|| format!("failed to write {}", partial))?;
+
+        let path = format!("{}/{}-{}.tgz", self.dir, self.start, end);
+        std::fs::rename(&partial, &path)
+            .with_context(|| format!("failed to rename {} to {}", partial, path))?;
+        Ok(path)
+    }
+
+    /// Removes an archive that won't be complete.
+    fn discard(self) -> Result<()> {
+        let partial = Archive::partial_path(&self.dir, self.start);
+        drop(self.builder);
+        std::fs::remove_file(&partial).with_context(|| format!("failed to remove {}", partial))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use clap::Parser;
+    use flate2::read::GzDecoder;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::test_utils::{node, temp_dir, MockServer};
+
+    #[derive(Parser)]
+    struct Options {
+        #[command(flatten)]
+        fetch: FetchOptions,
+    }
+
+    fn options(server: &MockServer, args: &[&str]) -> FetchOptions {
+        Options::parse_from(["test", "--node", &server.url].iter().chain(args)).fetch
+    }
+
+    /// `(start, limit)` of the pages fetched.
+    fn fetch(options: &FetchOptions) -> Result<Vec<(u64, u64)>> {
+        let mut pages = Vec::new();
+        Fetcher::new(options)?.fetch(|document| {
+            let (_, query) = document.name.split_once("?start=").unwrap();
+            let (start, limit) = query.split_once("&limit=").unwrap();
+            pages.push((start.parse()?, limit.parse()?));
+            Ok(())
+        })?;
+        Ok(pages)
+    }
+
+    #[test]
+    fn pages_stop_at_the_ledger_version() {
+        let server = node(249, 0, 0);
+        let pages = fetch(&options(&server, &[])).unwrap();
+        assert_eq!(pages, [(0, 100), (100, 100), (200, 50)]);
+
+        let pages = fetch(&options(&server, &["--start", "50", "--limit", "120"])).unwrap();
+        assert_eq!(pages, [(50, 100), (150, 20)]);
+    }
+
+    #[test]
+    fn failed_requests_are_retried() {
+        let server = node(99, 0, 2);
+        let pages = fetch(&options(&server, &["--fetch-retries", "2"])).unwrap();
+        assert_eq!(pages, [(0, 100)]);
+        assert_eq!(server.requests().len(), 3);
+
+        let server = node(99, 0, 2);
+        let err = fetch(&options(&server, &["--fetch-retries", "1"])).unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("failed after 2 attempts"),
+            "{:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn versions_out_of_the_ledger_are_errors() {
+        let server = node(249, 100, 0);
+        let err = fetch(&options(&server, &[])).unwrap_err();
+        assert!(
+            err.to_string().contains("versions before 100 are pruned"),
+            "{}",
+            err
+        );
+
+        let err = fetch(&options(&server, &["--start", "300"])).unwrap_err();
+        assert!(
+            err.to_string().contains("after the ledger version 249"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn archives_follow_the_api_layout() {
+        let dir = temp_dir("fetch-archives");
+        let server = node(25_049, 0, 0);
+        let options = options(
+            &server,
+            &[
+                "--start",
+                "9950",
+                "--page-size",
+                "70",
+                "--save-archive",
+                &dir,
+            ],
+        );
+        fetch(&options).unwrap();
+
+        let files: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|it| it.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(files, ["10000-19900.tgz"]);
+
+        let file = File::open(format!("{}/10000-19900.tgz", dir)).unwrap();
+        let mut archive = tar::Archive::new(GzDecoder::new(file));
+        let mut names = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().to_string_lossy().to_string();
+            let mut content = String::new();
+            entry.read_to_string(&mut content).unwrap();
+
+            let transactions: Vec<Value> = serde_json::from_str(&content).unwrap();
+            let (first, last) = name.trim_end_matches(".json").split_once('-').unwrap();
+            assert_eq!(transactions.len(), 100);
+            assert_eq!(transactions[0]["version"], first);
+            assert_eq!(transactions[99]["version"], last);
+            names.push(name);
+        }
+        assert_eq!(names.len(), 100);
+        assert_eq!(names[0], "10000-10099.json");
+        assert_eq!(names[99], "19900-19999.json");
+    }
+}
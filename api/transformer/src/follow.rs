@@ -0,0 +1,339 @@
+//! `transformer follow`: tails the chain from a node and transforms new
+//! versions in micro-batches, instead of a run per version like
+//! `ol-version.processor.ts`.
+//!
+//! A batch is flushed once it holds `--flush-rows` transactions, or
+//! `--flush-interval` seconds after its first page arrived, whichever comes
+//! first. Once a batch is written, its last version is saved to the checkpoint
+//! file and a restarted follower resumes after it. A batch interrupted before
+//! that is fetched and transformed again.
+//!
+//! Every batch is a run of its own, written to `{dest}/{first}-{last}`. With
+//! `--clickhouse-url` or `--s3-bucket` the directory is removed once inserted
+//! or uploaded, unless items were skipped: it's kept, with their
+//! `errors.ndjson`, until they're transformed again. Otherwise a long-running
+//! follower leaves a directory per batch, small ones once caught up with the
+//! chain: raise `--flush-rows` and `--flush-interval`, and compact the files
+//! downstream, e.g. with `COPY (SELECT * FROM
+//! read_parquet('{dest}/*/coin_balance.parquet') ORDER BY version) TO
+//! 'coin_balance.parquet'` in DuckDB.
+//!
+//! With `--save-archive`, the transactions are archived like `fetch` does,
+//! across batches. A restarted follower only archives again from the next
+//! multiple of 10 000 versions.
+//!
+//! With `--metrics-listen`, the `metrics` are served at `/metrics`, along with
+//! the last version written.
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    fetch::{Archiver, FetchOptions, Fetcher},
+    input::Document,
+    manifest::InputManifest,
+    metrics,
+};
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(about = None, long_about = None)]
+pub struct FollowOptions {
+    /// File holding the last version written, read on start (`--start` is
+    /// ignored when it exists) and saved after every batch.
+    #[arg(long, value_name = "FILE")]
+    pub checkpoint: String,
+
+    /// Flush a batch once it holds this many transactions.
+    #[arg(long, default_value_t = 10_000)]
+    pub flush_rows: u64,
+
+    /// Flush a batch this many seconds after its first transaction arrived.
+    #[arg(long, value_name = "SECONDS", default_value_t = 10)]
+    pub flush_interval: u64,
+
+    /// Time between two polls of the ledger version once caught up, in
+    /// seconds.
+    #[arg(long, value_name = "SECONDS", default_value_t = 1)]
+    pub poll_interval: u64,
+
+    /// Serve the Prometheus metrics at `/metrics` on this address, e.g.
+    /// `127.0.0.1:9090`.
+    #[arg(long, value_name = "ADDRESS")]
+    pub metrics_listen: Option<String>,
+}
+
+/// Content of the checkpoint file.
+#[derive(Serialize, Deserialize)]
+struct Checkpoint {
+    version: u64,
+}
+
+/// Transactions to transform together.
+pub struct Batch {
+    pub first: u64,
+    pub last: u64,
+
+    /// One document per page.
+    pub documents: Vec<Document>,
+
+    /// The pages, as an input of the manifest.
+    pub manifest: InputManifest,
+}
+
+impl Batch {
+    /// `{first}-{last}`, the versions of the batch.
+    pub fn name(&self) -> String {
+        format!("{}-{}", self.first, self.last)
+    }
+}
+
+pub struct Follower {
+    fetcher: Fetcher,
+    options: FollowOptions,
+    page_size: u64,
+    archiver: Option<Archiver>,
+
+    /// Next version to fetch.
+    cursor: u64,
+
+    /// Version after the last one to fetch, with `--limit`.
+    end: Option<u64>,
+
+    /// Last ledger version of the node seen.
+    ledger_version: Option<u64>,
+
+    /// Last version of the batch returned by `next_batch`, until committed.
+    pending: Option<u64>,
+}
+
+impl Follower {
+    /// Resumes after the version of the checkpoint file, or starts at
+    /// `--start`. `--limit` counts from there.
+    pub fn new(fetch: &FetchOptions, options: &FollowOptions) -> Result<Follower> {
+        if options.flush_rows == 0 {
+            bail!("flush rows must be at least 1");
+        }
+
+        let cursor = match read_checkpoint(&options.checkpoint)? {
+            Some(version) => version + 1,
+            None => fetch.start,
+        };
+
+        Ok(Follower {
+            fetcher: Fetcher::new(fetch)?,
+            options: options.clone(),
+            page_size: fetch.page_size,
+            archiver: fetch.save_archive.as_deref().map(Archiver::new),
+            cursor,
+            end: fetch.limit.map(|limit| cursor.saturating_add(limit)),
+            ledger_version: None,
+            pending: None,
+        })
+    }
+
+    /// Next version to fetch.
+    pub fn cursor(&self) -> u64 {
+        self.cursor
+    }
+
+    /// Waits for the next batch. `None` once `--limit` versions were fetched.
+    pub fn next_batch(&mut self) -> Result<Option<Batch>> {
+        let first = self.cursor;
+        let poll_interval = Duration::from_secs(self.options.poll_interval);
+        let mut deadline: Option<Instant> = None;
+
+        let mut documents = Vec::new();
+        let mut hasher = Sha256::new();
+        let mut bytes = 0;
+        let mut rows = 0;
+        loop {
+            let mut limit = self.page_size.min(self.options.flush_rows - rows);
+            if let Some(end) = self.end {
+                limit = limit.min(end.saturating_sub(self.cursor));
+            }
+            if limit == 0 {
+                break;
+            }
+            if let Some(deadline) = deadline {
+                if Instant::now() >= deadline {
+                    break;
+                }
+            }
+
+            let ledger_version = match self.ledger_version {
+                Some(version) if version >= self.cursor => version,
+                _ => self.fetcher.ledger_version()?,
+            };
+            self.ledger_version = Some(ledger_version);
+
+            let page = if ledger_version >= self.cursor {
+                let limit = limit.min(ledger_version - self.cursor + 1);
+                Some(self.fetcher.page(self.cursor, limit)?)
+            } else {
+                None
+            };
+            let page = match page {
+                Some(page) if page.transactions > 0 => page,
+                _ => {
+                    // Caught up with the node.
+                    let wait = match deadline {
+                        Some(deadline) => {
+                            poll_interval.min(deadline.saturating_duration_since(Instant::now()))
+                        }
+                        None => poll_interval,
+                    };
+                    thread::sleep(wait);
+                    self.ledger_version = None;
+                    continue;
+                }
+            };
+
+            if let Some(oldest) = page.oldest_version {
+                if self.cursor < oldest {
+                    bail!(
+                        "versions before {} are pruned on {}",
+                        oldest,
+                        self.fetcher.url()
+                    );
+                }
+            }
+            if page.ledger_version.is_some() {
+                self.ledger_version = page.ledger_version;
+            }
+            deadline.get_or_insert_with(|| {
+                Instant::now() + Duration::from_secs(self.options.flush_interval)
+            });
+
+            let last = self.cursor + page.transactions - 1;
+            if let Some(archiver) = &mut self.archiver {
+                archiver.add(self.cursor, &page.body)?;
+            }
+            hasher.update(&page.body);
+            bytes += page.body.len() as u64;
+            rows += page.transactions;
+            documents.push(Document::from_bytes(page.name, page.body));
+
+            self.cursor = last + 1;
+        }
+
+        if documents.is_empty() {
+            if let Some(archiver) = self.archiver.take() {
+                archiver.finish()?;
+            }
+            return Ok(None);
+        }
+
+        let last = self.cursor - 1;
+        self.pending = Some(last);
+
+        Ok(Some(Batch {
+            first,
+            last,
+            documents,
+            manifest: InputManifest {
+                path: format!("{}?start={}&limit={}", self.fetcher.url(), first, rows),
+                bytes,
+                sha256: hex::encode(hasher.finalize()),
+            },
+        }))
+    }
+
+    /// Saves the last version of the batch returned by `next_batch` to the
+    /// checkpoint file, once the batch is written.
+    pub fn commit(&mut self) -> Result<()> {
+        let Some(version) = self.pending.take() else {
+            return Ok(());
+        };
+
+        // Written next to the checkpoint then renamed, so a crash never
+        // leaves a truncated file.
+        let path = &self.options.checkpoint;
+        let temporary = format!("{}.tmp", path);
+        std::fs::write(&temporary, serde_json::to_vec(&Checkpoint { version })?)
+            .with_context(|| format!("failed to write {}", temporary))?;
+        std::fs::rename(&temporary, path)
+            .with_context(|| format!("failed to rename {} to {}", temporary, path))?;
+        metrics::FOLLOW_VERSION.set(version as i64);
+        Ok(())
+    }
+}
+
+/// Last version written, `None` when the checkpoint file doesn't exist.
+fn read_checkpoint(path: &str) -> Result<Option<u64>> {
+    let content = match std::fs::read(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).with_context(|| format!("failed to read {}", path)),
+    };
+
+    let checkpoint: Checkpoint =
+        serde_json::from_slice(&content).with_context(|| format!("invalid checkpoint {}", path))?;
+    Ok(Some(checkpoint.version))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::test_utils::{node, temp_dir};
+
+    #[derive(Parser)]
+    struct Options {
+        #[command(flatten)]
+        fetch: FetchOptions,
+
+        #[command(flatten)]
+        follow: FollowOptions,
+    }
+
+    #[test]
+    fn batches_are_archived_across_flushes() {
+        let dir = temp_dir("follow-archives");
+        let server = node(20_049, 0, 0);
+        let checkpoint = format!("{}/checkpoint.json", dir);
+        let archives = format!("{}/archives", dir);
+        let options = Options::parse_from([
+            "test",
+            "--node",
+            &server.url,
+            "--checkpoint",
+            &checkpoint,
+            "--start",
+            "9990",
+            "--limit",
+            "10060",
+            "--flush-rows",
+            "3000",
+            "--save-archive",
+            &archives,
+        ]);
+
+        let mut follower = Follower::new(&options.fetch, &options.follow).unwrap();
+        let mut batches = Vec::new();
+        while let Some(batch) = follower.next_batch().unwrap() {
+            batches.push(batch.name());
+            follower.commit().unwrap();
+        }
+        assert_eq!(
+            batches,
+            ["9990-12989", "12990-15989", "15990-18989", "18990-20049"]
+        );
+
+        // Versions 20000 to 20049 don't fill an archive.
+        let files: Vec<String> = std::fs::read_dir(&archives)
+            .unwrap()
+            .map(|it| it.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(files, ["10000-19900.tgz"]);
+
+        let follower = Follower::new(&options.fetch, &options.follow).unwrap();
+        assert_eq!(follower.cursor(), 20_050);
+    }
+}
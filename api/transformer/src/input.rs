@@ -0,0 +1,464 @@
+//! Input files. Besides plain JSON, inputs can be compressed with gzip or
+//! zstd, or be whole tar archives of JSON files (as stored in the
+//! `transactions/{start}-{end}.tgz` archives). Archives are read as a stream,
+//! nothing is extracted to disk, but each JSON entry is held in memory whole:
+//! entries are meant to be small files, like the pages of 100 transactions of
+//! the archives.
+//!
+//! Newline-delimited JSON (`.ndjson`, `.jsonl` and stdin, `-`) is split in
+//! chunks of lines, so a producer can pipe transactions in as they come. A
+//! JSON array on stdin is split in chunks of elements.
+
+use std::{
+    fmt,
+    fs::File,
+    io::{BufRead, BufReader, Read},
+};
+
+use anyhow::{Context, Result};
+use diem_api_types::Transaction;
+use flate2::read::MultiGzDecoder;
+use serde::{
+    de::{value::MapAccessDeserializer, DeserializeSeed, Error, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use serde_json::value::RawValue;
+
+use crate::manifest::{ChecksumReader, InputManifest};
+
+/// Path of the standard input.
+pub const STDIN: &str = "-";
+
+/// Maximum number of lines in a document read from newline-delimited JSON.
+const NDJSON_CHUNK_LINES: usize = 1000;
+
+/// Maximum number of transactions in a document read from a JSON array.
+const ARRAY_CHUNK_ELEMENTS: usize = 1000;
+
+/// The content of one JSON file: a transaction, an array of transactions or
+/// a chunk of newline-delimited transactions.
+pub struct Document {
+    /// The input path, followed by the entry path for archives.
+    pub name: String,
+    content: Content,
+}
+
+enum Content {
+    /// A JSON document held in memory.
+    Json(Vec<u8>),
+
+    /// Lines of newline-delimited JSON, the first one being `first_line`.
+    Ndjson { lines: Vec<u8>, first_line: usize },
+
+    /// A JSON file, only read when the document is transformed.
+    File {
+        path: String,
+        compression: Compression,
+    },
+}
+
+impl Document {
+    /// A JSON document: a transaction or an array of transactions.
+    pub fn from_bytes(name: String, content: Vec<u8>) -> Document {
+        Document {
+            name,
+            content: Content::Json(content),
+        }
+    }
+
+    /// Deserializes the transactions of the document and hands them to `f`,
+    /// one at a time. Only one transaction is held in memory, arrays are
+    /// never deserialized as a whole.
+    pub fn for_each_transaction(&self, mut f: impl FnMut(Transaction) -> Result<()>) -> Result<()> {
+        match &self.content {
+            Content::Json(content) => {
+                let mut deserializer = serde_json::Deserializer::from_slice(content);
+                visit_transactions(&self.name, &mut deserializer, &mut f)
+            }
+            Content::Ndjson { lines, first_line } => {
+                for (index, line) in lines.split(|it| *it == b'\n').enumerate() {
+                    if line.iter().all(|it| it.is_ascii_whitespace()) {
+                        continue;
+                    }
+
+                    let name = format!("{} line {}", self.name, first_line + index);
+                    let mut deserializer = serde_json::Deserializer::from_slice(line);
+                    visit_transactions(&name, &mut deserializer, &mut f)?;
+                }
+                Ok(())
+            }
+            Content::File { path, compression } => {
+                let file = File::open(path).with_context(|| format!("failed to open {}", path))?;
+                let reader = BufReader::new(decompress(BufReader::new(file), *compression)?);
+                let mut deserializer = serde_json::Deserializer::from_reader(reader);
+                visit_transactions(&self.name, &mut deserializer, &mut f)
+            }
+        }
+    }
+}
+
+/// Visits a transaction or an array of transactions, then checks nothing but
+/// whitespace follows. Errors returned by `f` are passed through unchanged.
+fn visit_transactions<'de, R: serde_json::de::Read<'de>>(
+    name: &str,
+    deserializer: &mut serde_json::Deserializer<R>,
+    f: &mut impl FnMut(Transaction) -> Result<()>,
+) -> Result<()> {
+    let mut stopped = None;
+    let mut visit = |transaction| {
+        f(transaction).map_err(|err| {
+            stopped = Some(err);
+        })
+    };
+
+    let result = TransactionsVisitor(&mut visit)
+        .deserialize(&mut *deserializer)
+        .and_then(|_| deserializer.end());
+
+    if let Some(err) = stopped {
+        return Err(err);
+    }
+    result.with_context(|| format!("invalid transactions in {}", name))
+}
+
+/// Deserializes either a single transaction or the elements of an array,
+/// calling the wrapped function for each of them.
+struct TransactionsVisitor<'a, F>(&'a mut F);
+
+impl<'de, 'a, F: FnMut(Transaction) -> Result<(), ()>> DeserializeSeed<'de>
+    for TransactionsVisitor<'a, F>
+{
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de, 'a, F: FnMut(Transaction) -> Result<(), ()>> Visitor<'de> for TransactionsVisitor<'a, F> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a transaction or an array of transactions")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(transaction) = seq.next_element::<Transaction>()? {
+            (self.0)(transaction).map_err(|_| A::Error::custom("stopped"))?;
+        }
+        Ok(())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<(), A::Error> {
+        let transaction = Transaction::deserialize(MapAccessDeserializer::new(map))?;
+        (self.0)(transaction).map_err(|_| A::Error::custom("stopped"))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    Json(Compression),
+    Ndjson(Compression),
+    Tar(Compression),
+    Stdin,
+}
+
+impl Format {
+    fn from_path(path: &str) -> Format {
+        if path == STDIN {
+            return Format::Stdin;
+        }
+
+        let path = path.to_lowercase();
+        if path.ends_with(".tgz") || path.ends_with(".tar.gz") {
+            return Format::Tar(Compression::Gzip);
+        } else if path.ends_with(".tzst") || path.ends_with(".tar.zst") {
+            return Format::Tar(Compression::Zstd);
+        } else if path.ends_with(".tar") {
+            return Format::Tar(Compression::None);
+        }
+
+        let (path, compression) = if let Some(path) = path.strip_suffix(".gz") {
+            (path, Compression::Gzip)
+        } else if let Some(path) = path.strip_suffix(".zst") {
+            (path, Compression::Zstd)
+        } else {
+            (path.as_str(), Compression::None)
+        };
+
+        if path.ends_with(".ndjson") || path.ends_with(".jsonl") {
+            Format::Ndjson(compression)
+        } else {
+            Format::Json(compression)
+        }
+    }
+}
+
+fn decompress<'a, R: Read + 'a>(reader: R, compression: Compression) -> Result<Box<dyn Read + 'a>> {
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(reader)?),
+    })
+}
+
+/// Reads the JSON documents of `path` and hands them to `f`, one at a time.
+/// Archive entries are visited in archive order, only `.json` files are read.
+/// Returns the size and checksum of the input.
+pub fn read_documents(
+    path: &str,
+    mut f: impl FnMut(Document) -> Result<()>,
+) -> Result<InputManifest> {
+    let format = Format::from_path(path);
+    if format == Format::Stdin {
+        let mut input = ChecksumReader::new(std::io::stdin());
+        let mut reader = BufReader::with_capacity(1 << 20, &mut input);
+
+        // A JSON array can't be split in lines, split it in elements.
+        if first_byte(&mut reader)? == Some(b'[') {
+            read_array("stdin", reader, f)?;
+        } else {
+            read_ndjson("stdin", reader, f)?;
+        }
+        return Ok(input.finish(path)?);
+    }
+
+    let file = File::open(path).with_context(|| format!("failed to open {}", path))?;
+    let mut input = ChecksumReader::new(file);
+    let reader = BufReader::new(&mut input);
+
+    match format {
+        Format::Stdin => unreachable!(),
+        Format::Ndjson(compression) => read_ndjson(
+            path,
+            BufReader::with_capacity(1 << 20, decompress(reader, compression)?),
+            f,
+        )?,
+        // The file is only read (and hashed) here, workers open it again.
+        Format::Json(compression) => f(Document {
+            name: path.to_string(),
+            content: Content::File {
+                path: path.to_string(),
+                compression,
+            },
+        })?,
+        Format::Tar(compression) => {
+            let mut archive = tar::Archive::new(decompress(reader, compression)?);
+            let entries = archive
+                .entries()
+                .with_context(|| format!("failed to read archive {}", path))?;
+
+            for entry in entries {
+                let mut entry =
+                    entry.with_context(|| format!("failed to read archive {}", path))?;
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+
+                let entry_path = entry.path()?.to_string_lossy().to_string();
+                let file_name = entry_path.rsplit('/').next().unwrap_or_default();
+                if !file_name.ends_with(".json") || file_name.starts_with("._") {
+                    continue;
+                }
+
+                let name = format!("{}/{}", path, entry_path);
+                let mut content = Vec::with_capacity(entry.size() as usize);
+                entry
+                    .read_to_end(&mut content)
+                    .with_context(|| format!("failed to read {}", name))?;
+
+                f(Document::from_bytes(name, content))?;
+            }
+        }
+    }
+
+    input
+        .finish(path)
+        .with_context(|| format!("failed to read {}", path))
+}
+
+/// Skips leading whitespace and returns the next byte, without consuming it.
+fn first_byte<R: Read>(reader: &mut BufReader<R>) -> Result<Option<u8>> {
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            return Ok(None);
+        }
+
+        match buffer.iter().position(|it| !it.is_ascii_whitespace()) {
+            Some(position) => {
+                let byte = buffer[position];
+                reader.consume(position);
+                return Ok(Some(byte));
+            }
+            None => {
+                let len = buffer.len();
+                reader.consume(len);
+            }
+        }
+    }
+}
+
+/// Splits a JSON array in documents of up to `ARRAY_CHUNK_ELEMENTS`
+/// transactions, reading one element at a time. Elements are only parsed as
+/// JSON here, the workers deserialize the transactions.
+fn read_array<R: Read>(
+    name: &str,
+    reader: R,
+    mut f: impl FnMut(Document) -> Result<()>,
+) -> Result<()> {
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let mut stopped = None;
+
+    let result = deserializer
+        .deserialize_seq(ArrayChunks {
+            name,
+            f: &mut f,
+            stopped: &mut stopped,
+        })
+        .and_then(|_| deserializer.end());
+
+    if let Some(err) = stopped {
+        return Err(err);
+    }
+    result.with_context(|| format!("invalid JSON array in {}", name))
+}
+
+/// Hands the elements of an array to the wrapped function, as documents of
+/// up to `ARRAY_CHUNK_ELEMENTS` elements named after their indexes. An error
+/// of the function is kept in `stopped`.
+struct ArrayChunks<'a, F> {
+    name: &'a str,
+    f: &'a mut F,
+    stopped: &'a mut Option<anyhow::Error>,
+}
+
+impl<'de, 'a, F: FnMut(Document) -> Result<()>> Visitor<'de> for ArrayChunks<'a, F> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of transactions")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let mut elements: Vec<Box<RawValue>> = Vec::new();
+        let mut first = 0;
+
+        loop {
+            let element = seq.next_element::<Box<RawValue>>()?;
+            let done = element.is_none();
+            elements.extend(element);
+
+            if !elements.is_empty() && (done || elements.len() >= ARRAY_CHUNK_ELEMENTS) {
+                let last = first + elements.len() - 1;
+                let content: Vec<&str> = elements.iter().map(|it| it.get()).collect();
+                let document = Document::from_bytes(
+                    format!("{}[{}-{}]", self.name, first, last),
+                    format!("[{}]", content.join(",")).into_bytes(),
+                );
+                if let Err(err) = (self.f)(document) {
+                    *self.stopped = Some(err);
+                    return Err(A::Error::custom("stopped"));
+                }
+
+                elements.clear();
+                first = last + 1;
+            }
+
+            if done {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Splits newline-delimited JSON in documents of up to `NDJSON_CHUNK_LINES`
+/// lines. A document is also handed out as soon as no more input is buffered,
+/// so a slow producer doesn't have to fill a whole chunk.
+fn read_ndjson<R: Read>(
+    name: &str,
+    mut reader: BufReader<R>,
+    mut f: impl FnMut(Document) -> Result<()>,
+) -> Result<()> {
+    let mut content = Vec::new();
+    let mut first_line = 1;
+    let mut line = 0;
+
+    loop {
+        let read = reader
+            .read_until(b'\n', &mut content)
+            .with_context(|| format!("failed to read {}", name))?;
+        if read > 0 {
+            line += 1;
+        }
+
+        let done = read == 0;
+        if done || line - first_line + 1 >= NDJSON_CHUNK_LINES || reader.buffer().is_empty() {
+            if content.iter().any(|it| !it.is_ascii_whitespace()) {
+                f(Document {
+                    name: format!("{}:{}-{}", name, first_line, line),
+                    content: Content::Ndjson {
+                        lines: std::mem::take(&mut content),
+                        first_line,
+                    },
+                })?;
+            }
+            content.clear();
+            first_line = line + 1;
+        }
+
+        if done {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    #[test]
+    fn arrays_are_split_in_chunks_of_elements() {
+        let elements: Vec<Value> = (0..ARRAY_CHUNK_ELEMENTS + 2)
+            .map(|version| json!({ "version": version.to_string() }))
+            .collect();
+        let content = serde_json::to_vec(&elements).unwrap();
+
+        let mut documents = Vec::new();
+        read_array("stdin", content.as_slice(), |document| {
+            documents.push(document);
+            Ok(())
+        })
+        .unwrap();
+
+        let names: Vec<&str> = documents.iter().map(|it| it.name.as_str()).collect();
+        assert_eq!(names, ["stdin[0-999]", "stdin[1000-1001]"]);
+        let Content::Json(last) = &documents[1].content else {
+            panic!("not a JSON document");
+        };
+        let last: Vec<Value> = serde_json::from_slice(last).unwrap();
+        assert_eq!(last, elements[1000..]);
+    }
+
+    #[test]
+    fn truncated_arrays_are_errors() {
+        let mut documents = 0;
+        let err = read_array("stdin", r#"[{"version": "0"}, {"vers"#.as_bytes(), |_| {
+            documents += 1;
+            Ok(())
+        })
+        .unwrap_err();
+
+        assert_eq!(documents, 0);
+        assert_eq!(err.to_string(), "invalid JSON array in stdin");
+    }
+}
@@ -0,0 +1,42 @@
+//! Extracts the collections of the 0L explorer from the transactions returned
+//! by the node API.
+//!
+//! `Transformer` runs transaction files through every registered collection
+//! (`models::registry()`) and sends the rows to a `Sink`. The `transformer`
+//! binary is a thin command line wrapper around it.
+
+pub mod clickhouse;
+pub mod collection;
+pub mod constants;
+pub mod ddl;
+pub mod error;
+pub mod fetch;
+pub mod follow;
+pub mod input;
+pub mod manifest;
+pub mod mapping;
+pub mod metrics;
+pub mod models;
+pub mod move_resource;
+pub mod partition;
+pub mod process;
+pub mod s3;
+pub mod serve;
+pub mod sink;
+#[cfg(test)]
+mod test_utils;
+mod to_array_data;
+pub mod transformer;
+mod utils;
+pub mod verify;
+pub mod versions;
+pub mod writer;
+
+pub use clickhouse::ClickhouseSink;
+pub use collection::Collection;
+pub use error::{OnError, TransformError};
+pub use manifest::Manifest;
+pub use process::{process_changes, process_transaction};
+pub use s3::S3Sink;
+pub use sink::{FileSink, MemorySink, Sink};
+pub use transformer::{Transformer, TransformerBuilder};
@@ -1,842 +1,401 @@
-use std::str::FromStr;
-
-use clap::Parser;
-use diem_api_types::{HexEncodedBytes, IdentifierWrapper, MoveType, Transaction, WriteSetChange};
-use lazy_static::lazy_static;
-
-mod models;
-mod to_array_data;
-mod utils;
-
-use models::{
-    AncestryCollection, BeneficiaryPolicyCollection, BlockMetadataTransactionCollection,
-    BoundaryStatusCollection, BurnCounterCollection, BurnTrackerCollection, CoinBalanceCollection,
-    ConsensusRewardCollection, DonorVoiceRegistryCollection, EpochFeeMakerRegistryCollection,
-    EventCollection, GenesisTransactionCollection, MultiActionCollection,
-    MultisigAccountOwnersCollection, ScriptCollection, SlowWalletCollection,
-    SlowWalletListCollection, StateCheckpointTransactionCollection, TotalSupplyCollection,
-    TowerListCollection, UserTransactionCollection, VdfDifficultyCollection,
+use std::io::IsTerminal;
+
+use anyhow::{anyhow, bail, Context};
+use clap::{Parser, Subcommand};
+use tracing_subscriber::EnvFilter;
+
+use transformer::{
+    clickhouse::ClickhouseOptions,
+    ddl::{self, Ddl},
+    fetch::FetchOptions,
+    follow::{FollowOptions, Follower},
+    mapping::MappingConfig,
+    metrics, models,
+    partition::PartitionBy,
+    s3::S3Options,
+    serve::ServeOptions,
+    transformer::{default_jobs, TransformerBuilder},
+    versions::VersionCheck,
+    writer::{OutputFormat, ParquetOptions},
+    ClickhouseSink, FileSink, Manifest, OnError, S3Sink, Transformer,
 };
-use serde_json::Value;
 
 #[derive(Parser, Debug)]
-struct Args {
-    // input files
+#[command(
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    subcommand_precedence_over_arg = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    // without a subcommand, transforms files
+    #[command(flatten)]
+    transform: TransformArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the ClickHouse tables of the collections, or check a SQL file against them
+    Schema(SchemaArgs),
+
+    /// Transform transactions fetched from the REST API of a node
+    Fetch(Box<FetchArgs>),
+
+    /// Tail a node, transforming new versions in micro-batches
+    Follow(Box<FollowArgs>),
+
+    /// Serve the transformer over HTTP, see /docs for the API
+    Serve(Box<ServeArgs>),
+}
+
+#[derive(clap::Args, Debug)]
+struct TransformArgs {
+    // input files: .json or .ndjson/.jsonl, optionally .gz or .zst compressed, .tgz/.tar.zst
+    // archives of JSON files (each entry is read whole into memory), or - to read
+    // newline-delimited JSON (or a JSON array, split in chunks of elements) from stdin
     #[arg(required = true)]
     files: Vec<String>,
 
-    // output directory
+    // output directory, only used for errors.ndjson with --clickhouse-url
+    // (an Option so that subcommands parse without it, clap still requires it)
     #[arg(required = true)]
+    dest: Option<String>,
+
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct FetchArgs {
+    #[command(flatten)]
+    fetch: FetchOptions,
+
+    // output directory, only used for errors.ndjson with --clickhouse-url
     dest: String,
+
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct FollowArgs {
+    #[command(flatten)]
+    fetch: FetchOptions,
+
+    #[command(flatten)]
+    follow: FollowOptions,
+
+    // output directory, every batch goes to {dest}/{first}-{last}, removed once
+    // inserted or uploaded unless items were skipped; compact the files
+    // otherwise, see the follow module
+    dest: String,
+
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct ServeArgs {
+    #[command(flatten)]
+    serve: ServeOptions,
+
+    // TOML file describing extra resources to extract, see `mapping.rs`
+    #[arg(long)]
+    mapping: Option<String>,
+}
+
+// what to produce and where, with or without a subcommand
+#[derive(clap::Args, Debug)]
+struct OutputArgs {
+    // TOML file describing extra resources to extract, see `mapping.rs`
+    #[arg(long)]
+    mapping: Option<String>,
+
+    // only produce these collections, e.g. --only burn_tracker,burn_counter
+    #[arg(long, value_name = "COLLECTION", value_delimiter = ',')]
+    only: Vec<String>,
+
+    // produce every collection but these
+    #[arg(long, value_name = "COLLECTION", value_delimiter = ',')]
+    exclude: Vec<String>,
+
+    // file format of the collections
+    #[arg(long, value_enum, default_value_t = OutputFormat::Parquet)]
+    format: OutputFormat,
+
+    #[command(flatten)]
+    parquet: ParquetOptions,
+
+    // refuse to write next to parquet files of another schema version
+    #[arg(long)]
+    require_schema: bool,
+
+    #[command(flatten)]
+    clickhouse: ClickhouseOptions,
+
+    #[command(flatten)]
+    s3: S3Options,
+
+    // split the collections into Hive-style partitions, e.g. coin_balance/epoch=123/part-0.parquet
+    #[arg(long, value_name = "epoch|version:<N>|day")]
+    partition_by: Option<PartitionBy>,
+
+    // leave out the transactions before this version
+    #[arg(long)]
+    from_version: Option<u64>,
+
+    // leave out the transactions after this version
+    #[arg(long)]
+    to_version: Option<u64>,
+
+    // what to do about gaps, duplicates and inputs out of version order (the output is then only
+    // sorted per chunk of 8192 transactions), listed in manifest.json
+    #[arg(long, value_enum, default_value_t = VersionCheck::Warn)]
+    check_versions: VersionCheck,
+
+    // recompute the hashes of block metadata and state checkpoint transactions
+    // and empty event roots, a mismatch is handled like any other error (see
+    // --on-error); user and genesis transactions, state_change_hash and events
+    // can't be checked and are counted as unverifiable
+    #[arg(long)]
+    verify: bool,
+
+    // number of input files transformed concurrently
+    #[arg(long, default_value_t = default_jobs())]
+    jobs: usize,
+
+    // what to do with transactions that can't be transformed
+    #[arg(long, value_enum, default_value_t = OnError::Fail)]
+    on_error: OnError,
+}
+
+#[derive(clap::Args, Debug)]
+struct SchemaArgs {
+    // schema language to generate
+    #[arg(long, value_enum, default_value_t = Ddl::Clickhouse)]
+    ddl: Ddl,
+
+    // create replicated tables on this cluster, e.g. olfyi
+    #[arg(long)]
+    cluster: Option<String>,
+
+    // compare the tables of a SQL file (e.g. api/tables.sql) with the collections instead
+    #[arg(long, value_name = "FILE")]
+    check: Option<String>,
+
+    // TOML file describing extra resources to extract, see `mapping.rs`
+    #[arg(long)]
+    mapping: Option<String>,
 }
 
-lazy_static! {
-    static ref LIBRA_COIN_HANDLE: HexEncodedBytes = HexEncodedBytes::from_str(
-        "FC074A2B7638A50BA678CE381A2350A28264F4DA004603ADB8DC36D125750108"
-    )
-    .unwrap();
-    static ref LIBRA_COIN_KEY: HexEncodedBytes = HexEncodedBytes::from_str(
-        "A7E1AF6D61E958DBEFE8F35550AAB562F8923634CD7F438BC5190E99CA5FB07C"
-    )
-    .unwrap();
-    static ref ROOT_ACCOUNT_ADDRESS: HexEncodedBytes = HexEncodedBytes::from_str(
-        "0000000000000000000000000000000000000000000000000000000000000001"
-    )
-    .unwrap();
-    static ref NULL_ADDRESS: HexEncodedBytes = HexEncodedBytes::from_str(
-        "0000000000000000000000000000000000000000000000000000000000000000"
-    )
-    .unwrap();
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    // Logs go to stderr, stdout only gets the output of the command (e.g. the
+    // run summary). RUST_LOG=debug adds the document and transaction spans.
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
+    match cli.command {
+        Some(Command::Schema(args)) => schema(args),
+        Some(Command::Fetch(args)) => fetch(*args).await,
+        Some(Command::Follow(args)) => follow(*args).await,
+        Some(Command::Serve(args)) => {
+            let mapping = args.mapping.as_deref().map(load_mapping).transpose()?;
+            transformer::serve::serve(args.serve, mapping).await
+        }
+        None => transform(cli.transform).await,
+    }
 }
 
-/**
- * 0x1::tower_state::TowerList : XX
- * 0x1::ol_account::BurnTracker: XX
- * 0x1::burn::BurnCounter: XX
- * 0x1::slow_wallet::SlowWallet: XX
- * 0x1::pledge_accounts::BeneficiaryPolicy: XX
- * 0x1::fee_maker::EpochFeeMakerRegistry: X
- * 0x1::slow_wallet::SlowWalletList: X
- * 0x1::tower_state::VDFDifficulty: X
- * 0x1::proof_of_fee::ConsensusReward: X
- * 0x1::epoch_boundary::BoundaryStatus: X
- *
- */
-
-fn process_changes(
-    total_supply_collection: &mut TotalSupplyCollection,
-    coin_balance_collection: &mut CoinBalanceCollection,
-
-    beneficiary_policy_collection: &mut BeneficiaryPolicyCollection,
-    tower_list_collection: &mut TowerListCollection,
-    burn_tracker_collection: &mut BurnTrackerCollection,
-    burn_counter_collection: &mut BurnCounterCollection,
-    slow_wallet_collection: &mut SlowWalletCollection,
-    epoch_fee_maker_registry_collection: &mut EpochFeeMakerRegistryCollection,
-    slow_wallet_list_collection: &mut SlowWalletListCollection,
-    vdf_difficulty_collection: &mut VdfDifficultyCollection,
-    consensus_reward_collection: &mut ConsensusRewardCollection,
-    boundary_status_collection: &mut BoundaryStatusCollection,
-    ancestry_collection: &mut AncestryCollection,
-    multisig_account_owners_collection: &mut MultisigAccountOwnersCollection,
-    multi_action_collection: &mut MultiActionCollection,
-    donor_voice_registry_collection: &mut DonorVoiceRegistryCollection,
-
-    version: u64,
-    changes: &Vec<WriteSetChange>,
-) {
-    for (change_index, change) in changes.iter().enumerate() {
-        let change_index = change_index as u64;
-
-        match change {
-            // diem_api_types::WriteSetChange::DeleteModule(_) => todo!(),
-            // diem_api_types::WriteSetChange::DeleteResource(_) => todo!(),
-            // diem_api_types::WriteSetChange::DeleteTableItem(_) => todo!(),
-            // diem_api_types::WriteSetChange::WriteModule(_) => todo!(),
-            diem_api_types::WriteSetChange::WriteResource(change) => {
-                let address = change.address.inner().to_vec();
-                let type_address = change.data.typ.address.inner().to_vec();
-                let type_module = change.data.typ.module.as_str();
-                let type_name = change.data.typ.name.as_str();
-                let type_generic_type_params_len = change.data.typ.generic_type_params.len();
-
-                // coin_balance
-                if type_address == ROOT_ACCOUNT_ADDRESS.inner()
-                    && type_module == "coin"
-                    && type_name == "CoinStore"
-                    && type_generic_type_params_len == 1
-                {
-                    let param = &change.data.typ.generic_type_params[0];
-
-                    if let MoveType::Struct(param) = param {
-                        let coin_identifier = IdentifierWrapper::from_str("coin").unwrap();
-
-                        if let Some(Value::Object(coin)) = change.data.data.0.get(&coin_identifier)
-                        {
-                            if let Some(Value::String(value)) = coin.get("value") {
-                                if let Ok(balance) = value.parse::<u64>() {
-                                    let address = change.address.inner().to_vec();
-                                    let coin_address = param.address.inner().to_vec();
-                                    let coin_module = param.module.as_str();
-                                    let coin_name = param.name.as_str();
-                                    coin_balance_collection.push(
-                                        address,
-                                        balance,
-                                        version,
-                                        change_index,
-                                        coin_address,
-                                        coin_module.to_string(),
-                                        coin_name.to_string(),
-                                    );
-                                }
-                            }
-                        }
-                    }
-                }
-
-                // 0x1::donor_voice::Registry
-                if address == ROOT_ACCOUNT_ADDRESS.inner()
-                    && type_address == ROOT_ACCOUNT_ADDRESS.inner()
-                    && type_module == "donor_voice"
-                    && type_name == "Registry"
-                    && type_generic_type_params_len == 0
-                {
-                    let data = &change.data.data.0;
-                    let list = IdentifierWrapper::from_str("list").unwrap();
-
-                    let registry: Vec<_> = if let Some(Value::Array(value)) = data.get(&list) {
-                        value
-                            .iter()
-                            .filter_map(|v| {
-                                v.as_str().map(|s| {
-                                    utils::parse_addr(s)
-                                        .unwrap_or_else(|err| {
-                                            panic!("Failed to parse address: {}", err)
-                                        })
-                                        .0
-                                })
-                            })
-                            .collect()
-                    } else {
-                        panic!("No 'owners' array found in data");
-                    };
-
-                    donor_voice_registry_collection.push(version, change_index, registry);
-                }
-
-                // 0x1::pledge_accounts::BeneficiaryPolicy
-                if address == ROOT_ACCOUNT_ADDRESS.inner()
-                    && type_address == ROOT_ACCOUNT_ADDRESS.inner()
-                    && type_module == "pledge_accounts"
-                    && type_name == "BeneficiaryPolicy"
-                    && type_generic_type_params_len == 0
-                {
-                    let data = &change.data.data.0;
-                    let amount_available: String = serde_json::from_value(
-                        data.get(&IdentifierWrapper::from_str("amount_available").unwrap())
-                            .unwrap()
-                            .clone(),
-                    )
-                    .unwrap();
-                    let amount_available = amount_available.parse::<u64>().unwrap();
-
-                    let lifetime_pledged: String = serde_json::from_value(
-                        data.get(&IdentifierWrapper::from_str("lifetime_pledged").unwrap())
-                            .unwrap()
-                            .clone(),
-                    )
-                    .unwrap();
-                    let lifetime_pledged = lifetime_pledged.parse::<u64>().unwrap();
-
-                    let lifetime_withdrawn: String = serde_json::from_value(
-                        data.get(&IdentifierWrapper::from_str("lifetime_withdrawn").unwrap())
-                            .unwrap()
-                            .clone(),
-                    )
-                    .unwrap();
-                    let lifetime_withdrawn = lifetime_withdrawn.parse::<u64>().unwrap();
-
-                    let pledgers: Vec<String> = serde_json::from_value(
-                        data.get(&IdentifierWrapper::from_str("pledgers").unwrap())
-                            .unwrap()
-                            .clone(),
-                    )
-                    .unwrap();
-                    let pledgers_count = pledgers.len() as u64;
-
-                    beneficiary_policy_collection.push(
-                        version,
-                        change_index,
-                        lifetime_pledged,
-                        lifetime_withdrawn,
-                        amount_available,
-                        pledgers_count,
-                    );
-                }
-
-                // 0x1::multisig_account::MultisigAccount
-                if type_address == ROOT_ACCOUNT_ADDRESS.inner()
-                    && type_module == "multisig_account"
-                    && type_name == "MultisigAccount"
-                    && type_generic_type_params_len == 0
-                {
-                    let data = &change.data.data.0;
-                    let owners_id = IdentifierWrapper::from_str("owners").unwrap();
-
-                    let owners: Vec<_> = if let Some(Value::Array(value)) = data.get(&owners_id) {
-                        value
-                            .iter()
-                            .filter_map(|v| {
-                                v.as_str().map(|s| {
-                                    utils::parse_addr(s)
-                                        .unwrap_or_else(|err| {
-                                            panic!("Failed to parse address: {}", err)
-                                        })
-                                        .0
-                                })
-                            })
-                            .collect()
-                    } else {
-                        panic!("No 'owners' array found in data");
-                    };
-
-                    multisig_account_owners_collection.push(
-                        version,
-                        change_index,
-                        address.clone(),
-                        owners,
-                    );
-                }
-
-                // 0x1::slow_wallet::SlowWallet
-                if type_address == ROOT_ACCOUNT_ADDRESS.inner()
-                    && type_module == "slow_wallet"
-                    && type_name == "SlowWallet"
-                    && type_generic_type_params_len == 0
-                {
-                    let data = &change.data.data.0;
-
-                    let transferred: String = serde_json::from_value(
-                        data.get(&IdentifierWrapper::from_str("transferred").unwrap())
-                            .unwrap()
-                            .clone(),
-                    )
-                    .unwrap();
-
-                    let unlocked: String = serde_json::from_value(
-                        data.get(&IdentifierWrapper::from_str("unlocked").unwrap())
-                            .unwrap()
-                            .clone(),
-                    )
-                    .unwrap();
-                    let unlocked = unlocked.parse::<u64>().unwrap();
-                    let transferred = transferred.parse::<u64>().unwrap();
-
-                    slow_wallet_collection.push(
-                        version,
-                        change_index,
-                        address.clone(),
-                        unlocked,
-                        transferred,
-                    );
-                }
-
-                // 0x1::burn::BurnCounter
-                if address == ROOT_ACCOUNT_ADDRESS.inner()
-                    && type_address == ROOT_ACCOUNT_ADDRESS.inner()
-                    && type_module == "burn"
-                    && type_name == "BurnCounter"
-                    && type_generic_type_params_len == 0
-                {
-                    let data = &change.data.data.0;
-
-                    let lifetime_burned: String = serde_json::from_value(
-                        data.get(&IdentifierWrapper::from_str("lifetime_burned").unwrap())
-                            .unwrap()
-                            .clone(),
-                    )
-                    .unwrap();
-
-                    let lifetime_recycled: String = serde_json::from_value(
-                        data.get(&IdentifierWrapper::from_str("lifetime_recycled").unwrap())
-                            .unwrap()
-                            .clone(),
-                    )
-                    .unwrap();
-
-                    let lifetime_burned = lifetime_burned.parse::<u64>().unwrap();
-                    let lifetime_recycled = lifetime_recycled.parse::<u64>().unwrap();
-
-                    burn_counter_collection.push(
-                        version,
-                        change_index,
-                        lifetime_burned,
-                        lifetime_recycled,
-                    );
-                }
-
-                // 0x1::ol_account::BurnTracker
-                if type_address == ROOT_ACCOUNT_ADDRESS.inner()
-                    && type_module == "ol_account"
-                    && type_name == "BurnTracker"
-                    && type_generic_type_params_len == 0
-                {
-                    let data = &change.data.data.0;
-
-                    let burn_at_last_calc: String = serde_json::from_value(
-                        data.get(&IdentifierWrapper::from_str("burn_at_last_calc").unwrap())
-                            .unwrap()
-                            .clone(),
-                    )
-                    .unwrap();
-                    let burn_at_last_calc = burn_at_last_calc.parse::<u64>().unwrap();
-
-                    let cumu_burn: String = serde_json::from_value(
-                        data.get(&IdentifierWrapper::from_str("cumu_burn").unwrap())
-                            .unwrap()
-                            .clone(),
-                    )
-                    .unwrap();
-                    let cumu_burn = cumu_burn.parse::<u64>().unwrap();
-
-                    let prev_balance: String = serde_json::from_value(
-                        data.get(&IdentifierWrapper::from_str("prev_balance").unwrap())
-                            .unwrap()
-                            .clone(),
-                    )
-                    .unwrap();
-                    let prev_balance = prev_balance.parse::<u64>().unwrap();
-
-                    let prev_supply: String = serde_json::from_value(
-                        data.get(&IdentifierWrapper::from_str("prev_balance").unwrap())
-                            .unwrap()
-                            .clone(),
-                    )
-                    .unwrap();
-                    let prev_supply = prev_supply.parse::<u64>().unwrap();
-
-                    burn_tracker_collection.push(
-                        version,
-                        change_index,
-                        address.clone(),
-                        burn_at_last_calc,
-                        cumu_burn,
-                        prev_balance,
-                        prev_supply,
-                    );
-                }
-
-                // 0x1::tower_state:TowerList
-                if address == ROOT_ACCOUNT_ADDRESS.inner()
-                    && type_address == ROOT_ACCOUNT_ADDRESS.inner()
-                    && type_module == "tower_state"
-                    && type_name == "TowerList"
-                    && type_generic_type_params_len == 0
-                {
-                    let list: Vec<String> = serde_json::from_value(
-                        change
-                            .data
-                            .data
-                            .0
-                            .get(&IdentifierWrapper::from_str("list").unwrap())
-                            .unwrap()
-                            .clone(),
-                    )
-                    .unwrap();
-
-                    tower_list_collection.push(version, change_index, list.len() as u64);
-                }
-
-                // 0x1::fee_maker::EpochFeeMakerRegistry
-                if address == ROOT_ACCOUNT_ADDRESS.inner()
-                    && type_address == ROOT_ACCOUNT_ADDRESS.inner()
-                    && type_module == "fee_maker"
-                    && type_name == "EpochFeeMakerRegistry"
-                    && type_generic_type_params_len == 0
-                {
-                    let epoch_fees_made: String = serde_json::from_value(
-                        change
-                            .data
-                            .data
-                            .0
-                            .get(&IdentifierWrapper::from_str("epoch_fees_made").unwrap())
-                            .unwrap()
-                            .clone(),
-                    )
-                    .unwrap();
-                    let epoch_fees_made = epoch_fees_made.parse::<u64>().unwrap();
-
-                    epoch_fee_maker_registry_collection.push(
-                        version,
-                        change_index,
-                        epoch_fees_made,
-                    );
-                }
-
-                // 0x1::slow_wallet::SlowWalletList
-                if address == ROOT_ACCOUNT_ADDRESS.inner()
-                    && type_address == ROOT_ACCOUNT_ADDRESS.inner()
-                    && type_module == "slow_wallet"
-                    && type_name == "SlowWalletList"
-                    && type_generic_type_params_len == 0
-                {
-                    let list: Vec<String> = serde_json::from_value(
-                        change
-                            .data
-                            .data
-                            .0
-                            .get(&IdentifierWrapper::from_str("list").unwrap())
-                            .unwrap()
-                            .clone(),
-                    )
-                    .unwrap();
-
-                    slow_wallet_list_collection.push(version, change_index, list.len() as u64);
-                }
-
-                // 0x1::tower_state::VDFDifficulty
-                if address == ROOT_ACCOUNT_ADDRESS.inner()
-                    && type_address == ROOT_ACCOUNT_ADDRESS.inner()
-                    && type_module == "tower_state"
-                    && type_name == "VDFDifficulty"
-                    && type_generic_type_params_len == 0
-                {
-                    let difficulty: String = serde_json::from_value(
-                        change
-                            .data
-                            .data
-                            .0
-                            .get(&IdentifierWrapper::from_str("difficulty").unwrap())
-                            .unwrap()
-                            .clone(),
-                    )
-                    .unwrap();
-                    let difficulty = difficulty.parse::<u64>().unwrap();
-
-                    vdf_difficulty_collection.push(version, change_index, difficulty);
-                }
-
-                // 0x1::multi_action::Action<0x1::donor_voice_txs::Payment>
-                if type_address == ROOT_ACCOUNT_ADDRESS.inner()
-                    && type_module == "multi_action"
-                    && type_name == "Action"
-                    && type_generic_type_params_len == 1
-                {
-                    if let MoveType::Struct(tally_type) =
-                        change.data.typ.generic_type_params[0].clone()
-                    {
-                        multi_action_collection.push(
-                            version,
-                            change_index,
-                            address.clone(),
-                            tally_type,
-                            serde_json::to_string(&change.data).unwrap(),
-                        );
-                    }
-                }
-
-                // 0x1::proof_of_fee::ConsensusReward
-                if address == ROOT_ACCOUNT_ADDRESS.inner()
-                    && type_address == ROOT_ACCOUNT_ADDRESS.inner()
-                    && type_module == "proof_of_fee"
-                    && type_name == "ConsensusReward"
-                    && type_generic_type_params_len == 0
-                {
-                    let data = &change.data.data.0;
-
-                    let clearing_bid: String = serde_json::from_value(
-                        data.get(&IdentifierWrapper::from_str("clearing_bid").unwrap())
-                            .unwrap()
-                            .clone(),
-                    )
-                    .unwrap();
-                    let clearing_bid = clearing_bid.parse::<u64>().unwrap();
-
-                    let entry_fee: String = serde_json::from_value(
-                        data.get(&IdentifierWrapper::from_str("entry_fee").unwrap())
-                            .unwrap()
-                            .clone(),
-                    )
-                    .unwrap();
-                    let entry_fee = entry_fee.parse::<u64>().unwrap();
-
-                    let median_history: Vec<String> = serde_json::from_value(
-                        data.get(&IdentifierWrapper::from_str("median_history").unwrap())
-                            .unwrap()
-                            .clone(),
-                    )
-                    .unwrap();
-                    let median_history = median_history
-                        .iter()
-                        .map(|value| value.parse::<u64>().unwrap())
-                        .collect();
-
-                    let median_win_bid: String = serde_json::from_value(
-                        data.get(&IdentifierWrapper::from_str("median_win_bid").unwrap())
-                            .unwrap()
-                            .clone(),
-                    )
-                    .unwrap();
-                    let median_win_bid = median_win_bid.parse::<u64>().unwrap();
-
-                    let net_reward: String = serde_json::from_value(
-                        data.get(&IdentifierWrapper::from_str("net_reward").unwrap())
-                            .unwrap()
-                            .clone(),
-                    )
-                    .unwrap();
-                    let net_reward = net_reward.parse::<u64>().unwrap();
-
-                    let nominal_reward: String = serde_json::from_value(
-                        data.get(&IdentifierWrapper::from_str("nominal_reward").unwrap())
-                            .unwrap()
-                            .clone(),
-                    )
-                    .unwrap();
-                    let nominal_reward = nominal_reward.parse::<u64>().unwrap();
-
-                    consensus_reward_collection.push(
-                        version,
-                        change_index,
-                        nominal_reward,
-                        net_reward,
-                        entry_fee,
-                        clearing_bid,
-                        median_win_bid,
-                        median_history,
-                    );
-                }
-
-                // 0x1::epoch_boundary::BoundaryStatus
-                if address == ROOT_ACCOUNT_ADDRESS.inner()
-                    && type_address == ROOT_ACCOUNT_ADDRESS.inner()
-                    && type_module == "epoch_boundary"
-                    && type_name == "BoundaryStatus"
-                    && type_generic_type_params_len == 0
-                {
-                    let data = &change.data.data.0;
-
-                    let incoming_fees: String = serde_json::from_value(
-                        data.get(&IdentifierWrapper::from_str("incoming_fees").unwrap())
-                            .unwrap()
-                            .clone(),
-                    )
-                    .unwrap();
-                    let incoming_fees = incoming_fees.parse::<u64>().unwrap();
-
-                    let outgoing_nominal_reward_to_vals: String = serde_json::from_value(
-                        data.get(
-                            &IdentifierWrapper::from_str("outgoing_nominal_reward_to_vals")
-                                .unwrap(),
-                        )
-                        .unwrap()
-                        .clone(),
-                    )
-                    .unwrap();
-                    let outgoing_nominal_reward_to_vals =
-                        outgoing_nominal_reward_to_vals.parse::<u64>().unwrap();
-
-                    let outgoing_total_reward: String = serde_json::from_value(
-                        data.get(&IdentifierWrapper::from_str("outgoing_total_reward").unwrap())
-                            .unwrap()
-                            .clone(),
-                    )
-                    .unwrap();
-                    let outgoing_total_reward = outgoing_total_reward.parse::<u64>().unwrap();
-
-                    let system_fees_collected: String = serde_json::from_value(
-                        data.get(&IdentifierWrapper::from_str("system_fees_collected").unwrap())
-                            .unwrap()
-                            .clone(),
-                    )
-                    .unwrap();
-                    let system_fees_collected = system_fees_collected.parse::<u64>().unwrap();
-
-                    boundary_status_collection.push(
-                        version,
-                        change_index,
-                        incoming_fees,
-                        outgoing_nominal_reward_to_vals,
-                        outgoing_total_reward,
-                        system_fees_collected,
-                    );
-                }
-
-                if type_address == ROOT_ACCOUNT_ADDRESS.inner()
-                    && type_module == "ancestry"
-                    && type_name == "Ancestry"
-                    && type_generic_type_params_len == 0
-                {
-                    let data = &change.data.data.0;
-
-                    let tree: Vec<String> = serde_json::from_value(
-                        data.get(&IdentifierWrapper::from_str("tree").unwrap())
-                            .unwrap()
-                            .clone(),
-                    )
-                    .unwrap();
-
-                    let tree = tree
-                        .iter()
-                        .map(|addr| utils::parse_addr(addr).unwrap().0)
-                        .collect::<Vec<_>>();
-                    ancestry_collection.push(address.clone(), tree);
-                }
-            }
-            diem_api_types::WriteSetChange::WriteTableItem(change) => {
-                if change.handle.eq(&LIBRA_COIN_HANDLE) && change.key.eq(&LIBRA_COIN_KEY) {
-                    let libra_coin_total_supply_change = change;
-
-                    // let value: u128 = from_bytes(&libra_coin_total_supply_change.value.0).unwrap();
-                    total_supply_collection.push(
-                        version,
-                        libra_coin_total_supply_change.value.0.clone(),
-                        change_index as u64,
-                    );
-                }
-            }
-            _ => {}
+fn load_mapping(path: &str) -> anyhow::Result<MappingConfig> {
+    let built_in = models::registry();
+    let reserved: Vec<&str> = built_in.iter().map(|it| it.name()).collect();
+    MappingConfig::load(path, &reserved)
+}
+
+fn schema(args: SchemaArgs) -> anyhow::Result<()> {
+    let mut collections = models::registry();
+    if let Some(path) = &args.mapping {
+        collections.extend(load_mapping(path)?.collections()?);
+    }
+    let schemas: Vec<(String, _)> = collections
+        .iter()
+        .map(|it| (it.name().to_string(), it.schema()))
+        .collect();
+
+    if let Some(path) = &args.check {
+        let sql = std::fs::read_to_string(path)?;
+        let problems = ddl::check_tables(&schemas, &ddl::parse_tables(&sql)?)?;
+        for problem in problems.iter() {
+            println!("{}", problem);
+        }
+        if !problems.is_empty() {
+            bail!("{} doesn't match the collections", path);
+        }
+        eprintln!("{} matches the collections", path);
+        return Ok(());
+    }
+
+    match args.ddl {
+        Ddl::Clickhouse => {
+            let tables = schemas
+                .iter()
+                .map(|(name, schema)| ddl::create_table(name, schema, args.cluster.as_deref()))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            print!("{}", tables.join("\n"));
         }
     }
+
+    Ok(())
 }
 
-#[tokio::main]
-async fn main() {
-    let args = Args::parse();
-
-    let mut block_metadata_transaction_collection = BlockMetadataTransactionCollection::new();
-    let mut state_checkpoint_transaction_collection = StateCheckpointTransactionCollection::new();
-    let mut user_transaction_collection = UserTransactionCollection::new();
-    let mut genesis_transaction_collection = GenesisTransactionCollection::new();
-    let mut ancestry_collection = AncestryCollection::new();
-    let mut script_collection = ScriptCollection::new();
-    let mut total_supply_collection = TotalSupplyCollection::new();
-    let mut coin_balance_collection = CoinBalanceCollection::new();
-    let mut multisig_account_owners_collection = MultisigAccountOwnersCollection::new();
-    let mut multi_action_collection = MultiActionCollection::new();
-    let mut donor_voice_registry_collection = DonorVoiceRegistryCollection::new();
-
-    let mut beneficiary_policy_collection = BeneficiaryPolicyCollection::new();
-    let mut tower_list_collection = TowerListCollection::new();
-    let mut burn_tracker_collection = BurnTrackerCollection::new();
-    let mut burn_counter_collection = BurnCounterCollection::new();
-    let mut slow_wallet_collection = SlowWalletCollection::new();
-    let mut epoch_fee_maker_registry_collection = EpochFeeMakerRegistryCollection::new();
-    let mut slow_wallet_list_collection = SlowWalletListCollection::new();
-    let mut vdf_difficulty_collection = VdfDifficultyCollection::new();
-    let mut consensus_reward_collection = ConsensusRewardCollection::new();
-    let mut boundary_status_collection = BoundaryStatusCollection::new();
-
-    std::fs::create_dir_all(&args.dest).unwrap();
-
-    let mut event_collection = EventCollection::new();
-
-    for file in args.files.iter() {
-        let content = std::fs::read_to_string(file).unwrap();
-        let value: serde_json::Value = serde_json::from_str(&content).unwrap();
-
-        let transactions: Vec<diem_api_types::transaction::Transaction> = match value {
-            serde_json::Value::Array(transactions) => {
-                serde_json::from_value(serde_json::Value::Array(transactions)).unwrap()
-            }
-            serde_json::Value::Object(transaction) => {
-                vec![serde_json::from_value(serde_json::Value::Object(transaction)).unwrap()]
-            }
-            _ => {
-                panic!("invalid transaction json file");
-            }
+async fn transform(args: TransformArgs) -> anyhow::Result<()> {
+    let dest = args.dest.clone().unwrap_or_default();
+    let output = &args.output;
+    let archive_name = output.s3.archive_name(&args.files).or_else(|| {
+        let (first, last) = (output.from_version?, output.to_version?);
+        Some(output.s3.range_archive_name(first, last))
+    });
+    let builder = Transformer::builder().inputs(args.files.iter());
+    let manifest = run(builder, &dest, &args.output, archive_name).await?;
+    print_summary(&manifest)
+}
+
+async fn fetch(args: FetchArgs) -> anyhow::Result<()> {
+    let archive_name = args.fetch.limit.filter(|limit| *limit > 0).map(|limit| {
+        let start = args.fetch.start;
+        args.output.s3.range_archive_name(start, start + limit - 1)
+    });
+    let archive_name = archive_name.or_else(|| args.output.s3.name.clone());
+    let builder = Transformer::builder().fetch(args.fetch);
+    let manifest = run(builder, &args.dest, &args.output, archive_name).await?;
+    print_summary(&manifest)
+}
+
+// the manifest of a one-shot run, on a single line of stdout for the caller to log
+fn print_summary(manifest: &Manifest) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string(manifest)?);
+    Ok(())
+}
+
+async fn follow(args: FollowArgs) -> anyhow::Result<()> {
+    let mut follower = Follower::new(&args.fetch, &args.follow)?;
+    if let Some(listen) = &args.follow.metrics_listen {
+        metrics::spawn(listen).await?;
+    }
+    tracing::info!(
+        "following {} from version {}",
+        args.fetch.node,
+        follower.cursor()
+    );
+
+    loop {
+        let Some(batch) = tokio::task::block_in_place(|| follower.next_batch())? else {
+            return Ok(());
         };
 
-        let it = transactions.iter();
-        for transaction in it {
-            event_collection.push(transaction);
-
-            match transaction {
-                Transaction::PendingTransaction(_) => {}
-                Transaction::UserTransaction(user_transaction) => {
-                    let info = &user_transaction.info;
-
-                    process_changes(
-                        &mut total_supply_collection,
-                        &mut coin_balance_collection,
-                        &mut beneficiary_policy_collection,
-                        &mut tower_list_collection,
-                        &mut burn_tracker_collection,
-                        &mut burn_counter_collection,
-                        &mut slow_wallet_collection,
-                        &mut epoch_fee_maker_registry_collection,
-                        &mut slow_wallet_list_collection,
-                        &mut vdf_difficulty_collection,
-                        &mut consensus_reward_collection,
-                        &mut boundary_status_collection,
-                        &mut ancestry_collection,
-                        &mut multisig_account_owners_collection,
-                        &mut multi_action_collection,
-                        &mut donor_voice_registry_collection,
-                        info.version.into(),
-                        &info.changes,
-                    );
-
-                    let request = &user_transaction.request;
-                    let payload = &request.payload;
-
-                    match payload {
-                        diem_api_types::TransactionPayload::EntryFunctionPayload(_) => {
-                            user_transaction_collection.push(&user_transaction);
-                        }
-
-                        diem_api_types::TransactionPayload::ScriptPayload(_) => {
-                            script_collection.push(&user_transaction);
-                        }
-
-                        // Deprecated. Will be removed in the future.
-                        diem_api_types::TransactionPayload::ModuleBundlePayload(_) => {
-                            panic!("unsupported diem_api_types::TransactionPayload::ModuleBundlePayload");
-                        }
-                        diem_api_types::TransactionPayload::MultisigPayload(_) => {
-                            panic!("diem_api_types::TransactionPayload::MultisigPayload");
-                        }
-                    }
-                }
-                Transaction::GenesisTransaction(genesis_transaction) => {
-                    let info = &genesis_transaction.info;
-
-                    genesis_transaction_collection.push(genesis_transaction);
-
-                    process_changes(
-                        &mut total_supply_collection,
-                        &mut coin_balance_collection,
-                        &mut beneficiary_policy_collection,
-                        &mut tower_list_collection,
-                        &mut burn_tracker_collection,
-                        &mut burn_counter_collection,
-                        &mut slow_wallet_collection,
-                        &mut epoch_fee_maker_registry_collection,
-                        &mut slow_wallet_list_collection,
-                        &mut vdf_difficulty_collection,
-                        &mut consensus_reward_collection,
-                        &mut boundary_status_collection,
-                        &mut ancestry_collection,
-                        &mut multisig_account_owners_collection,
-                        &mut multi_action_collection,
-                        &mut donor_voice_registry_collection,
-                        info.version.into(),
-                        &info.changes,
-                    );
-                }
-                Transaction::BlockMetadataTransaction(block_metadata_transaction) => {
-                    let info = &block_metadata_transaction.info;
-
-                    assert_eq!(info.vm_status, "Executed successfully");
-                    assert_eq!(info.gas_used, diem_api_types::U64(0));
-                    assert_eq!(info.success, true);
-
-                    block_metadata_transaction_collection.push(block_metadata_transaction);
-
-                    process_changes(
-                        &mut total_supply_collection,
-                        &mut coin_balance_collection,
-                        &mut beneficiary_policy_collection,
-                        &mut tower_list_collection,
-                        &mut burn_tracker_collection,
-                        &mut burn_counter_collection,
-                        &mut slow_wallet_collection,
-                        &mut epoch_fee_maker_registry_collection,
-                        &mut slow_wallet_list_collection,
-                        &mut vdf_difficulty_collection,
-                        &mut consensus_reward_collection,
-                        &mut boundary_status_collection,
-                        &mut ancestry_collection,
-                        &mut multisig_account_owners_collection,
-                        &mut multi_action_collection,
-                        &mut donor_voice_registry_collection,
-                        info.version.into(),
-                        &info.changes,
-                    );
-                }
-                Transaction::StateCheckpointTransaction(state_checkpoint_transaction) => {
-                    let info = &state_checkpoint_transaction.info;
-
-                    assert_eq!(info.vm_status, "Executed successfully");
-                    assert_eq!(info.gas_used, diem_api_types::U64(0));
-                    assert_eq!(info.success, true);
-                    assert_eq!(info.changes.len(), 0);
-
-                    state_checkpoint_transaction_collection.push(state_checkpoint_transaction);
-                }
-            }
+        let name = batch.name();
+        let dest = format!("{}/{}", args.dest, name);
+        let builder = Transformer::builder().documents(batch.documents, batch.manifest);
+        let manifest = run(builder, &dest, &args.output, Some(name.clone())).await?;
+        follower.commit()?;
+
+        // Inserted or uploaded batches are removed, unless items were skipped:
+        // their errors.ndjson is kept until they're transformed again.
+        let clickhouse = args.output.clickhouse.url.is_some();
+        let s3 = args.output.s3.bucket.is_some();
+        if (clickhouse || s3) && manifest.skipped > 0 {
+            tracing::warn!(
+                "skipped {} items, see {}/errors.ndjson",
+                manifest.skipped,
+                dest
+            );
+        } else if clickhouse {
+            std::fs::remove_dir(&dest).with_context(|| format!("failed to remove {}", dest))?;
+        } else if s3 {
+            std::fs::remove_dir_all(&dest).with_context(|| format!("failed to remove {}", dest))?;
         }
+        tracing::info!("transformed versions {}", name);
+    }
+}
+
+async fn run(
+    builder: TransformerBuilder<()>,
+    dest: &str,
+    args: &OutputArgs,
+    archive_name: Option<String>,
+) -> anyhow::Result<Manifest> {
+    let mut builder = builder
+        .jobs(args.jobs)
+        .on_error(args.on_error)
+        .check_versions(args.check_versions)
+        .verify(args.verify);
+
+    if let Some(path) = &args.mapping {
+        builder = builder.mapping(load_mapping(path)?);
+    }
+    if !args.only.is_empty() {
+        builder = builder.collections(args.only.iter());
+    }
+    builder = builder.exclude(args.exclude.iter());
+    if let Some(partition_by) = args.partition_by {
+        builder = builder.partition_by(partition_by);
+    }
+    if let Some(version) = args.from_version {
+        builder = builder.from_version(version);
+    }
+    if let Some(version) = args.to_version {
+        builder = builder.to_version(version);
     }
 
-    event_collection.to_parquet(format!("{}/event.parquet", &args.dest));
-    user_transaction_collection.to_parquet(format!("{}/user_transaction.parquet", &args.dest));
-    genesis_transaction_collection
-        .to_parquet(format!("{}/genesis_transaction.parquet", &args.dest));
-    ancestry_collection.to_parquet(format!("{}/ancestry.parquet", &args.dest));
-    block_metadata_transaction_collection
-        .to_parquet(format!("{}/block_metadata_transaction.parquet", &args.dest));
-    state_checkpoint_transaction_collection.to_parquet(format!(
-        "{}/state_checkpoint_transaction.parquet",
-        &args.dest
-    ));
-    total_supply_collection.to_parquet(format!("{}/total_supply.parquet", &args.dest));
-    coin_balance_collection.to_parquet(format!("{}/coin_balance.parquet", &args.dest));
-    script_collection.to_parquet(format!("{}/script.parquet", &args.dest));
-    beneficiary_policy_collection.to_parquet(format!("{}/beneficiary_policy.parquet", &args.dest));
-
-    tower_list_collection.to_parquet(format!("{}/tower_list.parquet", &args.dest));
-    burn_tracker_collection.to_parquet(format!("{}/burn_tracker.parquet", &args.dest));
-    burn_counter_collection.to_parquet(format!("{}/burn_counter.parquet", &args.dest));
-    slow_wallet_collection.to_parquet(format!("{}/slow_wallet.parquet", &args.dest));
-    epoch_fee_maker_registry_collection
-        .to_parquet(format!("{}/epoch_fee_maker_registry.parquet", &args.dest));
-    slow_wallet_list_collection.to_parquet(format!("{}/slow_wallet_list.parquet", &args.dest));
-    vdf_difficulty_collection.to_parquet(format!("{}/vdf_difficulty.parquet", &args.dest));
-    consensus_reward_collection.to_parquet(format!("{}/consensus_reward.parquet", &args.dest));
-    boundary_status_collection.to_parquet(format!("{}/boundary_status.parquet", &args.dest));
-    multisig_account_owners_collection
-        .to_parquet(format!("{}/multisig_account_owners.parquet", &args.dest));
-    multi_action_collection.to_parquet(format!("{}/multi_action.parquet", &args.dest));
-    donor_voice_registry_collection
-        .to_parquet(format!("{}/donor_voice_registry.parquet", &args.dest));
+    let manifest = if args.clickhouse.url.is_some() {
+        builder
+            .sink(ClickhouseSink::new(dest, &args.clickhouse)?)
+            .build()?
+            .run()
+            .await?
+            .manifest()
+            .cloned()
+    } else if args.s3.bucket.is_some() {
+        // Named after the versions requested, so a rerun replaces the archive.
+        let archive_name = archive_name.ok_or_else(|| {
+            anyhow!(
+                "the inputs don't tell which versions they hold, name the archive with --s3-name"
+            )
+        })?;
+        builder
+            .sink(S3Sink::new(
+                FileSink::new(dest, args.format, &args.parquet)?
+                    .require_schema(args.require_schema),
+                &args.s3,
+                archive_name,
+            )?)
+            .build()?
+            .run()
+            .await?
+            .manifest()
+            .cloned()
+    } else {
+        builder
+            .sink(
+                FileSink::new(dest, args.format, &args.parquet)?
+                    .require_schema(args.require_schema),
+            )
+            .build()?
+            .run()
+            .await?
+            .manifest()
+            .cloned()
+    };
+
+    manifest.ok_or_else(|| anyhow!("the run didn't finish"))
 }
@@ -0,0 +1,272 @@
+//! `manifest.json`: what a run read and produced, so consumers can check an
+//! output is complete. Collections without rows are listed too, with no file.
+//!
+//! Parquet files also carry their own provenance in the key-value metadata
+//! of their footer, see `Manifest::file_metadata`.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Read},
+};
+
+use anyhow::{anyhow, Result};
+use arrow::compute::{max, min};
+use arrow_array::{cast::AsArray, types::UInt64Type, Array, RecordBatch};
+use arrow_schema::Schema;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{verify::VerifyReport, versions::VersionReport};
+
+pub const TRANSFORMER_VERSION_KEY: &str = "transformer.version";
+pub const COLLECTION_KEY: &str = "transformer.collection";
+pub const SCHEMA_VERSION_KEY: &str = "transformer.schema_version";
+pub const MIN_VERSION_KEY: &str = "transformer.min_version";
+pub const MAX_VERSION_KEY: &str = "transformer.max_version";
+pub const INPUTS_KEY: &str = "transformer.inputs";
+
+/// Set to `true` on the files closed by `Sink::abort`: they hold the rows
+/// written before the run failed, not all the rows of their versions.
+pub const ABORTED_KEY: &str = "transformer.aborted";
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Manifest {
+    /// Version of the transformer crate.
+    pub transformer_version: String,
+
+    pub inputs: Vec<InputManifest>,
+
+    /// Paths of the inputs, known before they're read, unlike `inputs`. The
+    /// footers of the files closed before the end of the run list them.
+    #[serde(skip)]
+    pub input_paths: Vec<String>,
+
+    /// Every collection produced by the run, by name.
+    pub collections: BTreeMap<String, CollectionManifest>,
+
+    /// Items left out with `OnError::Skip`, see `errors.ndjson`.
+    pub skipped: usize,
+
+    /// Gaps and duplicates in the versions transformed, unless checks are
+    /// off (see `versions`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub versions: Option<VersionReport>,
+
+    /// Hashes checked with `--verify`, see `verify`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verification: Option<VerifyReport>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct InputManifest {
+    pub path: String,
+    pub bytes: u64,
+
+    /// Hex encoded SHA-256 of the file, as stored (compressed or not).
+    pub sha256: String,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct CollectionManifest {
+    /// Output file, relative to the destination. Set by sinks writing files,
+    /// `None` when the collection has no rows or is partitioned.
+    pub file: Option<String>,
+
+    /// Files of the partitions, relative to the destination, with
+    /// `--partition-by`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub partitions: Vec<String>,
+
+    /// See `Collection::schema_version`.
+    pub schema_version: u32,
+
+    pub rows: u64,
+    pub min_version: Option<u64>,
+    pub max_version: Option<u64>,
+    pub min_change_index: Option<u64>,
+    pub max_change_index: Option<u64>,
+
+    /// See `schema_hash`. `None` when the collection has no rows.
+    pub schema_hash: Option<String>,
+
+    /// Whether the rows are sorted by `(version, change_index)` as a whole.
+    /// Rows are only sorted within each chunk of `BATCH_SIZE` transactions,
+    /// chunks are written in input order and never merged. `None` when the
+    /// collection has no rows or no version column.
+    pub sorted: Option<bool>,
+
+    /// Sort key of the last row written, see `sort_keys`.
+    #[serde(skip)]
+    last_key: Option<(u64, u64)>,
+}
+
+impl Manifest {
+    /// An empty manifest for `collections`, pairs of name and schema version.
+    pub fn new<I: IntoIterator<Item = (String, u32)>>(collections: I) -> Manifest {
+        Manifest {
+            transformer_version: env!("CARGO_PKG_VERSION").to_string(),
+            inputs: Vec::new(),
+            input_paths: Vec::new(),
+            collections: collections
+                .into_iter()
+                .map(|(name, schema_version)| {
+                    let manifest = CollectionManifest {
+                        schema_version,
+                        ..Default::default()
+                    };
+                    (name, manifest)
+                })
+                .collect(),
+            skipped: 0,
+            versions: None,
+            verification: None,
+        }
+    }
+
+    /// Provenance of a file of `collection` holding `versions` (first and
+    /// last), stored as key-value metadata in its footer (see the `*_KEY`
+    /// constants). The inputs of the run are listed by file name, as a JSON
+    /// array, their checksums are in `manifest.json`.
+    pub fn file_metadata(
+        &self,
+        collection: &str,
+        versions: Option<(u64, u64)>,
+    ) -> Vec<(String, String)> {
+        let mut metadata = vec![
+            (
+                TRANSFORMER_VERSION_KEY.to_string(),
+                self.transformer_version.clone(),
+            ),
+            (COLLECTION_KEY.to_string(), collection.to_string()),
+        ];
+
+        if let Some(manifest) = self.collections.get(collection) {
+            metadata.push((
+                SCHEMA_VERSION_KEY.to_string(),
+                manifest.schema_version.to_string(),
+            ));
+        }
+        if let Some((min, max)) = versions {
+            metadata.push((MIN_VERSION_KEY.to_string(), min.to_string()));
+            metadata.push((MAX_VERSION_KEY.to_string(), max.to_string()));
+        }
+
+        let inputs: Vec<&str> = self
+            .input_paths
+            .iter()
+            .map(|it| it.rsplit('/').next().unwrap_or_default())
+            .collect();
+        metadata.push((
+            INPUTS_KEY.to_string(),
+            serde_json::to_string(&inputs).unwrap_or_default(),
+        ));
+
+        metadata
+    }
+
+    /// Accounts for a batch written to `collection`.
+    pub fn add_batch(&mut self, collection: &str, batch: &RecordBatch) -> Result<()> {
+        let manifest = self.collections.entry(collection.to_string()).or_default();
+        manifest.rows += batch.num_rows() as u64;
+        manifest.schema_hash = Some(schema_hash(&batch.schema()));
+
+        if let Some((low, high)) = range(batch, "version")? {
+            manifest.min_version = Some(manifest.min_version.map_or(low, |it| it.min(low)));
+            manifest.max_version = Some(manifest.max_version.map_or(high, |it| it.max(high)));
+        }
+        if let Some((low, high)) = range(batch, "change_index")? {
+            manifest.min_change_index =
+                Some(manifest.min_change_index.map_or(low, |it| it.min(low)));
+            manifest.max_change_index =
+                Some(manifest.max_change_index.map_or(high, |it| it.max(high)));
+        }
+        if let Some((first, last)) = sort_keys(batch) {
+            let sorted = manifest.last_key.is_none_or(|it| it <= first);
+            manifest.sorted = Some(manifest.sorted.unwrap_or(true) && sorted);
+            manifest.last_key = Some(last);
+        }
+
+        Ok(())
+    }
+}
+
+/// `(version, change_index)`, or the event `index`, of the first and last
+/// rows of a batch sorted by `sort_by_version`. `None` for empty batches and
+/// batches without a version column.
+fn sort_keys(batch: &RecordBatch) -> Option<((u64, u64), (u64, u64))> {
+    let column = |name| {
+        batch
+            .column_by_name(name)
+            .and_then(|it| it.as_primitive_opt::<UInt64Type>())
+    };
+    let versions = column("version").filter(|it| !it.is_empty())?;
+    let indexes = column("change_index").or_else(|| column("index"));
+
+    let key = |row| (versions.value(row), indexes.map_or(0, |it| it.value(row)));
+    Some((key(0), key(versions.len() - 1)))
+}
+
+/// Minimum and maximum of a UInt64 column, if the batch has it.
+pub(crate) fn range(batch: &RecordBatch, column: &str) -> Result<Option<(u64, u64)>> {
+    let Some(values) = batch.column_by_name(column) else {
+        return Ok(None);
+    };
+    let values = values
+        .as_primitive_opt::<UInt64Type>()
+        .ok_or_else(|| anyhow!("{} isn't a UInt64 column", column))?;
+
+    Ok(min(values).zip(max(values)))
+}
+
+/// Hex encoded SHA-256 of the column names, types and nullability, one
+/// `name: type` line per column. Two files with the same hash can be inserted
+/// with the same query.
+pub fn schema_hash(schema: &Schema) -> String {
+    let mut hasher = Sha256::new();
+    for field in schema.fields().iter() {
+        let nullable = if field.is_nullable() { " null" } else { "" };
+        hasher.update(format!(
+            "{}: {}{}\n",
+            field.name(),
+            field.data_type(),
+            nullable
+        ));
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Hashes everything read through it.
+pub struct ChecksumReader<R> {
+    inner: R,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl<R: Read> ChecksumReader<R> {
+    pub fn new(inner: R) -> ChecksumReader<R> {
+        ChecksumReader {
+            inner,
+            hasher: Sha256::new(),
+            bytes: 0,
+        }
+    }
+
+    /// Reads what's left of the input, then returns its manifest.
+    pub fn finish(mut self, path: &str) -> io::Result<InputManifest> {
+        io::copy(&mut self, &mut io::sink())?;
+        Ok(InputManifest {
+            path: path.to_string(),
+            bytes: self.bytes,
+            sha256: hex::encode(self.hasher.finalize()),
+        })
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        self.bytes += read as u64;
+        Ok(read)
+    }
+}
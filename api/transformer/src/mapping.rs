@@ -0,0 +1,593 @@
+use std::{collections::HashSet, path::Path, sync::Arc};
+
+use anyhow::{bail, Context, Result};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema};
+use diem_api_types::{MoveStructTag, WriteSetChange};
+use serde::Deserialize;
+
+use crate::{
+    collection::Collection,
+    move_resource::{field, field_len, parse_address, Column},
+};
+
+/// Declarative resource extraction, loaded from a TOML file.
+///
+/// ```toml
+/// [[resource]]
+/// move_type = "0x1::ol_account::BurnTracker"
+/// address = "0x1"
+/// name = "burn_tracker_by_config"
+///
+/// [[resource.field]]
+/// name = "cumu_burn"
+/// type = "u64"
+///
+/// [[resource.field]]
+/// name = "coin_value"
+/// path = "coin.value"
+/// type = "u64"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MappingConfig {
+    #[serde(default)]
+    pub resource: Vec<ResourceMapping>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResourceMapping {
+    /// Struct tag of the resource, e.g. `0x1::burn::BurnCounter`.
+    pub move_type: String,
+
+    /// Only extract the resource when it's stored under this address.
+    pub address: Option<String>,
+
+    /// Collection name, the output file is `{name}.parquet`.
+    pub name: String,
+
+    /// Add an `address` column with the account holding the resource.
+    #[serde(default)]
+    pub with_address: bool,
+
+    /// Revision of the columns, see `Collection::schema_version`.
+    #[serde(default = "default_schema_version")]
+    pub schema_version: u32,
+
+    #[serde(default)]
+    pub field: Vec<FieldMapping>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldMapping {
+    /// Column name.
+    pub name: String,
+
+    /// Dotted path of the Move field. Defaults to `name`.
+    pub path: Option<String>,
+
+    #[serde(rename = "type")]
+    pub typ: FieldType,
+}
+
+/// Column types a mapped field can be converted to.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldType {
+    /// `u64` encoded as a string, stored as UInt64.
+    U64,
+    /// `u128` encoded as a string, stored as little endian FixedSizeBinary(16).
+    U128,
+    /// Stored as Boolean.
+    Bool,
+    /// Stored as Utf8.
+    String,
+    /// Stored as FixedSizeBinary(32), short addresses like `0x1` are zero
+    /// padded.
+    Address,
+    /// `vector<address>`, stored as a List of LargeBinary.
+    AddressVector,
+    /// Length of a vector, stored as UInt64.
+    VectorLength,
+}
+
+impl FieldType {
+    fn data_type(&self, name: &str) -> DataType {
+        match self {
+            FieldType::U64 | FieldType::VectorLength => Vec::<u64>::data_type(name),
+            FieldType::U128 => Vec::<u128>::data_type(name),
+            FieldType::Bool => Vec::<bool>::data_type(name),
+            FieldType::String => Vec::<String>::data_type(name),
+            FieldType::Address => Vec::<Vec<u8>>::data_type(name),
+            FieldType::AddressVector => Vec::<Vec<Vec<u8>>>::data_type(name),
+        }
+    }
+}
+
+impl MappingConfig {
+    /// Loads and validates the mapping file. `reserved` are the names of the
+    /// built-in collections, which mapped collections can't reuse.
+    pub fn load(path: &str, reserved: &[&str]) -> Result<MappingConfig> {
+        let content = std::fs::read_to_string(Path::new(path))
+            .with_context(|| format!("failed to read mapping file {}", path))?;
+        let config: MappingConfig = toml::from_str(&content)
+            .with_context(|| format!("failed to parse mapping file {}", path))?;
+        config
+            .validate(reserved)
+            .with_context(|| format!("invalid mapping file {}", path))?;
+        Ok(config)
+    }
+
+    fn validate(&self, reserved: &[&str]) -> Result<()> {
+        let mut names: HashSet<&str> = reserved.iter().copied().collect();
+
+        for (index, resource) in self.resource.iter().enumerate() {
+            resource
+                .compile()
+                .with_context(|| format!("resource #{} ({})", index, resource.move_type))?;
+
+            if !names.insert(resource.name.as_str()) {
+                bail!(
+                    "resource #{} ({}): collection name {} is already used",
+                    index,
+                    resource.move_type,
+                    resource.name
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compiles every resource of the mapping, to build their collections
+    /// with `MappedCollection::new`.
+    pub fn compile(&self) -> Result<Vec<Arc<CompiledMapping>>> {
+        self.resource
+            .iter()
+            .map(|resource| {
+                let mapping = resource
+                    .compile()
+                    .with_context(|| format!("invalid mapping of {}", resource.move_type))?;
+                Ok(Arc::new(mapping))
+            })
+            .collect()
+    }
+
+    /// One collection per resource of the mapping.
+    pub fn collections(&self) -> Result<Vec<Box<dyn Collection>>> {
+        Ok(self
+            .compile()?
+            .into_iter()
+            .map(|mapping| Box::new(MappedCollection::new(mapping)) as Box<dyn Collection>)
+            .collect())
+    }
+}
+
+/// A validated `ResourceMapping`, ready to match write set changes.
+pub struct CompiledMapping {
+    name: String,
+    move_type: String,
+    type_address: Vec<u8>,
+    type_module: String,
+    type_name: String,
+    address: Option<Vec<u8>>,
+    with_address: bool,
+    schema_version: u32,
+    fields: Vec<(String, Vec<String>, FieldType)>,
+}
+
+impl ResourceMapping {
+    fn compile(&self) -> Result<CompiledMapping> {
+        if self.move_type.contains('<') {
+            bail!("generic types are not supported");
+        }
+
+        let parts: Vec<&str> = self.move_type.split("::").collect();
+        if parts.len() != 3 {
+            bail!("move_type must look like 0x1::module::Name");
+        }
+        let type_address = parse_address(parts[0])
+            .with_context(|| format!("invalid address in move_type: {}", parts[0]))?;
+        for identifier in &parts[1..] {
+            if !is_identifier(identifier) {
+                bail!("invalid identifier in move_type: {}", identifier);
+            }
+        }
+
+        let address = match &self.address {
+            Some(address) => Some(
+                parse_address(address)
+                    .with_context(|| format!("invalid address filter: {}", address))?,
+            ),
+            None => None,
+        };
+
+        if !is_identifier(&self.name) {
+            bail!("invalid collection name: {}", self.name);
+        }
+        if self.field.is_empty() {
+            bail!("at least one field is required");
+        }
+
+        let mut columns: HashSet<&str> = HashSet::from(["version", "change_index"]);
+        if self.with_address {
+            columns.insert("address");
+        }
+
+        let mut fields = Vec::new();
+        for field in self.field.iter() {
+            if !is_identifier(&field.name) {
+                bail!("invalid field name: {}", field.name);
+            }
+            if !columns.insert(field.name.as_str()) {
+                bail!("duplicate column: {}", field.name);
+            }
+
+            let path = field.path.as_deref().unwrap_or(&field.name);
+            let path: Vec<String> = path.split('.').map(|it| it.to_string()).collect();
+            if path.iter().any(|it| it.is_empty()) {
+                bail!("invalid path for field {}", field.name);
+            }
+
+            fields.push((field.name.clone(), path, field.typ));
+        }
+
+        Ok(CompiledMapping {
+            name: self.name.clone(),
+            move_type: self.move_type.clone(),
+            type_address,
+            type_module: parts[1].to_string(),
+            type_name: parts[2].to_string(),
+            address,
+            with_address: self.with_address,
+            schema_version: self.schema_version,
+            fields,
+        })
+    }
+}
+
+fn default_schema_version() -> u32 {
+    1
+}
+
+fn is_identifier(value: &str) -> bool {
+    !value.is_empty()
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !value.starts_with(|c: char| c.is_ascii_digit())
+}
+
+impl CompiledMapping {
+    fn matches(&self, typ: &MoveStructTag) -> bool {
+        typ.address.inner().to_vec() == self.type_address
+            && typ.module.as_str() == self.type_module
+            && typ.name.as_str() == self.type_name
+            && typ.generic_type_params.is_empty()
+    }
+}
+
+enum ColumnData {
+    U64(Vec<u64>),
+    U128(Vec<u128>),
+    Bool(Vec<bool>),
+    String(Vec<String>),
+    Address(Vec<Vec<u8>>),
+    AddressVector(Vec<Vec<Vec<u8>>>),
+}
+
+impl ColumnData {
+    fn new(typ: FieldType) -> ColumnData {
+        match typ {
+            FieldType::U64 | FieldType::VectorLength => ColumnData::U64(Vec::new()),
+            FieldType::U128 => ColumnData::U128(Vec::new()),
+            FieldType::Bool => ColumnData::Bool(Vec::new()),
+            FieldType::String => ColumnData::String(Vec::new()),
+            FieldType::Address => ColumnData::Address(Vec::new()),
+            FieldType::AddressVector => ColumnData::AddressVector(Vec::new()),
+        }
+    }
+
+    fn to_array(&self, name: &str) -> Result<ArrayRef> {
+        match self {
+            ColumnData::U64(values) => values.to_array(name),
+            ColumnData::U128(values) => values.to_array(name),
+            ColumnData::Bool(values) => values.to_array(name),
+            ColumnData::String(values) => values.to_array(name),
+            ColumnData::Address(values) => values.to_array(name),
+            ColumnData::AddressVector(values) => values.to_array(name),
+        }
+    }
+}
+
+/// Values of one row, parsed before anything is pushed so that a bad field
+/// doesn't leave the columns with different lengths.
+enum FieldValue {
+    U64(u64),
+    U128(u128),
+    Bool(bool),
+    String(String),
+    Address(Vec<u8>),
+    AddressVector(Vec<Vec<u8>>),
+}
+
+/// Collection built from a `ResourceMapping`.
+pub struct MappedCollection {
+    mapping: Arc<CompiledMapping>,
+    version: Vec<u64>,
+    change_index: Vec<u64>,
+    address: Vec<Vec<u8>>,
+    columns: Vec<ColumnData>,
+}
+
+impl MappedCollection {
+    pub fn new(mapping: Arc<CompiledMapping>) -> MappedCollection {
+        let columns = mapping
+            .fields
+            .iter()
+            .map(|(_, _, typ)| ColumnData::new(*typ))
+            .collect();
+
+        MappedCollection {
+            mapping,
+            version: Vec::new(),
+            change_index: Vec::new(),
+            address: Vec::new(),
+            columns,
+        }
+    }
+}
+
+impl Collection for MappedCollection {
+    fn name(&self) -> &str {
+        &self.mapping.name
+    }
+
+    fn schema_version(&self) -> u32 {
+        self.mapping.schema_version
+    }
+
+    fn schema(&self) -> Schema {
+        let mut fields = vec![
+            Field::new("version", DataType::UInt64, false),
+            Field::new("change_index", DataType::UInt64, false),
+        ];
+        if self.mapping.with_address {
+            fields.push(Field::new(
+                "address",
+                FieldType::Address.data_type("address"),
+                false,
+            ));
+        }
+        for (name, _, typ) in self.mapping.fields.iter() {
+            fields.push(Field::new(name, typ.data_type(name), false));
+        }
+
+        Schema::new(fields)
+    }
+
+    fn on_write_set_change(
+        &mut self,
+        version: u64,
+        change_index: u64,
+        change: &WriteSetChange,
+    ) -> Result<()> {
+        if let WriteSetChange::WriteResource(change) = change {
+            if !self.extracts_resource(&change.data.typ) {
+                return Ok(());
+            }
+
+            let mut address = change.address.inner().to_vec();
+            if let Some(filter) = &self.mapping.address {
+                if &address != filter {
+                    return Ok(());
+                }
+            }
+
+            let data = &change.data.data;
+            let row: Result<Vec<FieldValue>> = self
+                .mapping
+                .fields
+                .iter()
+                .map(|(_, path, typ)| {
+                    let path: Vec<&str> = path.iter().map(|it| it.as_str()).collect();
+                    Ok(match typ {
+                        FieldType::U64 => FieldValue::U64(field(data, &path)?),
+                        FieldType::U128 => FieldValue::U128(field(data, &path)?),
+                        FieldType::Bool => FieldValue::Bool(field(data, &path)?),
+                        FieldType::String => FieldValue::String(field(data, &path)?),
+                        FieldType::Address => FieldValue::Address(field(data, &path)?),
+                        FieldType::AddressVector => FieldValue::AddressVector(field(data, &path)?),
+                        FieldType::VectorLength => FieldValue::U64(field_len(data, &path)?),
+                    })
+                })
+                .collect();
+            let row = row.with_context(|| format!("failed to parse {}", self.mapping.move_type))?;
+
+            self.version.push(version);
+            self.change_index.push(change_index);
+
+            if self.mapping.with_address {
+                address.reverse();
+                self.address.push(address);
+            }
+
+            for (column, value) in self.columns.iter_mut().zip(row) {
+                match (column, value) {
+                    (ColumnData::U64(values), FieldValue::U64(value)) => values.push(value),
+                    (ColumnData::U128(values), FieldValue::U128(value)) => values.push(value),
+                    (ColumnData::Bool(values), FieldValue::Bool(value)) => values.push(value),
+                    (ColumnData::String(values), FieldValue::String(value)) => values.push(value),
+                    (ColumnData::Address(values), FieldValue::Address(value)) => values.push(value),
+                    (ColumnData::AddressVector(values), FieldValue::AddressVector(value)) => {
+                        values.push(value)
+                    }
+                    _ => unreachable!("column and value types are built from the same mapping"),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn extracts_resource(&self, typ: &MoveStructTag) -> bool {
+        self.mapping.matches(typ)
+    }
+
+    fn take_batch(&mut self) -> Result<Option<RecordBatch>> {
+        if self.version.is_empty() {
+            return Ok(None);
+        }
+
+        let rows = std::mem::replace(self, MappedCollection::new(self.mapping.clone()));
+
+        let version = arrow_array::UInt64Array::from(rows.version);
+        let change_index = arrow_array::UInt64Array::from(rows.change_index);
+
+        let mut columns = vec![
+            ("version", Arc::new(version) as ArrayRef),
+            ("change_index", Arc::new(change_index) as ArrayRef),
+        ];
+        if rows.mapping.with_address {
+            columns.push(("address", rows.address.to_array("address")?));
+        }
+        for ((name, _, _), column) in rows.mapping.fields.iter().zip(rows.columns.iter()) {
+            columns.push((name.as_str(), column.to_array(name)?));
+        }
+
+        Ok(Some(RecordBatch::try_from_iter(columns)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{cast::AsArray, Array};
+    use serde_json::json;
+
+    use super::*;
+
+    fn config(move_type: &str) -> MappingConfig {
+        toml::from_str(&format!(
+            r#"
+            [[resource]]
+            move_type = "{}"
+            name = "registry"
+
+            [[resource.field]]
+            name = "owner"
+            type = "address"
+
+            [[resource.field]]
+            name = "members"
+            type = "address_vector"
+            "#,
+            move_type
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn short_addresses_are_stored_as_32_bytes() {
+        let mut collections = config("0x1::registry::Registry").collections().unwrap();
+        let change: WriteSetChange = serde_json::from_value(json!({
+            "type": "write_resource",
+            "address": "0x1",
+            "state_key_hash": "0x00",
+            "data": {
+                "type": "0x1::registry::Registry",
+                "data": {
+                    "owner": "0x1",
+                    "members": ["0x2", format!("0x{}", "ab".repeat(32))],
+                },
+            },
+        }))
+        .unwrap();
+
+        collections[0].on_write_set_change(7, 0, &change).unwrap();
+        let batch = collections[0].take_batch().unwrap().unwrap();
+
+        let owner = batch
+            .column_by_name("owner")
+            .unwrap()
+            .as_fixed_size_binary();
+        let mut expected = vec![0; 32];
+        expected[0] = 1;
+        assert_eq!(owner.value(0), expected.as_slice());
+        assert_eq!(batch.column_by_name("members").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn batches_have_the_schema_of_their_collection() {
+        let config: MappingConfig = toml::from_str(
+            r#"
+            [[resource]]
+            move_type = "0x1::vault::Vault"
+            name = "vault"
+            with_address = true
+
+            [[resource.field]]
+            name = "balance"
+            path = "coin.value"
+            type = "u64"
+
+            [[resource.field]]
+            name = "supply"
+            type = "u128"
+
+            [[resource.field]]
+            name = "locked"
+            type = "bool"
+
+            [[resource.field]]
+            name = "label"
+            type = "string"
+
+            [[resource.field]]
+            name = "owner"
+            type = "address"
+
+            [[resource.field]]
+            name = "signers"
+            type = "address_vector"
+
+            [[resource.field]]
+            name = "history"
+            type = "vector_length"
+            "#,
+        )
+        .unwrap();
+        let mut collections = config.collections().unwrap();
+        let change: WriteSetChange = serde_json::from_value(json!({
+            "type": "write_resource",
+            "address": "0x2",
+            "state_key_hash": "0x00",
+            "data": {
+                "type": "0x1::vault::Vault",
+                "data": {
+                    "coin": { "value": "10" },
+                    "supply": "340282366920938463463374607431768211455",
+                    "locked": true,
+                    "label": "savings",
+                    "owner": "0x2",
+                    "signers": ["0x2", "0x3"],
+                    "history": ["1", "2", "3"],
+                },
+            },
+        }))
+        .unwrap();
+
+        collections[0].on_write_set_change(7, 0, &change).unwrap();
+        let batch = collections[0].take_batch().unwrap().unwrap();
+
+        assert_eq!(*batch.schema(), collections[0].schema());
+    }
+
+    #[test]
+    fn invalid_move_types_are_errors() {
+        assert!(config("0x1::registry").collections().is_err());
+        assert!(config("0x::registry::Registry").collections().is_err());
+        assert!(config("0x1::registry::Registry<u64>")
+            .collections()
+            .is_err());
+    }
+}
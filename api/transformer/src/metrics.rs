@@ -0,0 +1,118 @@
+//! Prometheus metrics of the transformer, served at `/metrics` by the long
+//! running modes (`transformer serve`, `transformer follow --metrics-listen`).
+//!
+//! Metrics live in the default registry for the lifetime of the process, so
+//! the counters of `serve` add up every request.
+
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use poem::{
+    get, handler,
+    http::StatusCode,
+    listener::{Listener, TcpListener},
+    IntoResponse, Response, Route, Server,
+};
+use prometheus::{
+    exponential_buckets, register_histogram, register_int_counter, register_int_counter_vec,
+    register_int_gauge, Encoder, Histogram, IntCounter, IntCounterVec, IntGauge, TextEncoder,
+};
+
+lazy_static! {
+    pub static ref DOCUMENTS: IntCounter = register_int_counter!(
+        "transformer_documents_total",
+        "Documents transformed (files, archive entries or pages)"
+    )
+    .unwrap();
+    pub static ref TRANSACTIONS: IntCounter = register_int_counter!(
+        "transformer_transactions_total",
+        "Transactions transformed"
+    )
+    .unwrap();
+    pub static ref ROWS: IntCounterVec = register_int_counter_vec!(
+        "transformer_rows_total",
+        "Rows sent to the sink, by collection",
+        &["collection"]
+    )
+    .unwrap();
+    pub static ref UNKNOWN_RESOURCES: IntCounterVec = register_int_counter_vec!(
+        "transformer_unknown_resources_total",
+        "Resources written that no collection of the run extracts, by framework module (0x1::module) or other",
+        &["module"]
+    )
+    .unwrap();
+    pub static ref ERRORS: IntCounterVec = register_int_counter_vec!(
+        "transformer_errors_total",
+        "Transactions or changes that couldn't be transformed, by collection (empty for the whole transaction)",
+        &["collection"]
+    )
+    .unwrap();
+    pub static ref BYTES_WRITTEN: IntCounterVec = register_int_counter_vec!(
+        "transformer_bytes_written_total",
+        "Bytes of the collection files written, or of the inserts sent to ClickHouse, by collection",
+        &["collection"]
+    )
+    .unwrap();
+    pub static ref HASHES: IntCounterVec = register_int_counter_vec!(
+        "transformer_hashes_total",
+        "Hashes checked with --verify, by result (verified, mismatched or unverifiable)",
+        &["result"]
+    )
+    .unwrap();
+    pub static ref DOCUMENT_SECONDS: Histogram = register_histogram!(
+        "transformer_document_seconds",
+        "Time spent transforming a document",
+        exponential_buckets(0.001, 4.0, 10).unwrap()
+    )
+    .unwrap();
+    pub static ref TRANSACTIONS_PER_SECOND: Histogram = register_histogram!(
+        "transformer_transactions_per_second",
+        "Throughput of the transformation of a document",
+        exponential_buckets(10.0, 4.0, 10).unwrap()
+    )
+    .unwrap();
+    pub static ref FOLLOW_VERSION: IntGauge = register_int_gauge!(
+        "transformer_follow_version",
+        "Last version written by transformer follow"
+    )
+    .unwrap();
+}
+
+/// Every metric, in the Prometheus text format.
+pub fn encode() -> Result<String> {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .context("failed to encode the metrics")?;
+    Ok(String::from_utf8(buffer)?)
+}
+
+/// `GET /metrics`.
+#[handler]
+pub fn endpoint() -> Response {
+    match encode() {
+        Ok(metrics) => metrics
+            .with_content_type(TextEncoder::new().format_type())
+            .into_response(),
+        Err(err) => format!("{:#}", err)
+            .with_status(StatusCode::INTERNAL_SERVER_ERROR)
+            .into_response(),
+    }
+}
+
+/// Serves `/metrics` on `listen` in the background. Fails if the address
+/// can't be bound.
+pub async fn spawn(listen: &str) -> Result<()> {
+    let acceptor = TcpListener::bind(listen)
+        .into_acceptor()
+        .await
+        .with_context(|| format!("failed to listen on {}", listen))?;
+    tracing::info!("serving metrics on http://{}/metrics", listen);
+
+    let app = Route::new().at("/metrics", get(endpoint));
+    tokio::spawn(async move {
+        if let Err(err) = Server::new_with_acceptor(acceptor).run(app).await {
+            tracing::error!("metrics server failed: {}", err);
+        }
+    });
+    Ok(())
+}
@@ -1,10 +1,16 @@
-use std::{fs::File, sync::Arc};
+use anyhow::{Context, Result};
+use std::{str::FromStr, sync::Arc};
 
 use arrow_array::{ArrayRef, FixedSizeBinaryArray, ListArray, RecordBatch};
-use parquet::{arrow::arrow_writer::ArrowWriter, file::properties::WriterProperties};
+use arrow_schema::{DataType, Field, Schema};
+use diem_api_types::{IdentifierWrapper, MoveStructTag, WriteSetChange};
 
-use crate::to_array_data::create_array_data_2d;
+use crate::{
+    collection::Collection, constants::ROOT_ACCOUNT_ADDRESS, to_array_data::create_array_data_2d,
+    utils,
+};
 
+#[derive(Default)]
 pub struct AncestryCollection {
     address: Vec<Vec<u8>>,
     tree: Vec<Vec<Vec<u8>>>,
@@ -29,26 +35,77 @@ impl AncestryCollection {
 
         self.tree.push(tree);
     }
+}
+
+impl Collection for AncestryCollection {
+    fn name(&self) -> &'static str {
+        "ancestry"
+    }
+
+    fn schema(&self) -> Schema {
+        Schema::new(vec![
+            Field::new("address", DataType::FixedSizeBinary(32), false),
+            Field::new(
+                "tree",
+                DataType::List(Arc::new(Field::new("tree", DataType::LargeBinary, false))),
+                false,
+            ),
+        ])
+    }
+
+    fn on_write_set_change(
+        &mut self,
+        _version: u64,
+        _change_index: u64,
+        change: &WriteSetChange,
+    ) -> Result<()> {
+        if let WriteSetChange::WriteResource(change) = change {
+            let address = change.address.inner().to_vec();
+            if !self.extracts_resource(&change.data.typ) {
+                return Ok(());
+            }
+
+            let data = &change.data.data.0;
 
-    pub fn to_parquet(&self, path: String) {
+            let tree = data
+                .get(&IdentifierWrapper::from_str("tree").unwrap())
+                .context("missing field tree")?;
+            let tree: Vec<String> =
+                serde_json::from_value(tree.clone()).context("invalid field tree")?;
+
+            let tree = tree
+                .iter()
+                .map(|addr| Ok(utils::parse_addr(addr)?.0))
+                .collect::<Result<Vec<_>>>()?;
+            self.push(address, tree);
+        }
+
+        Ok(())
+    }
+
+    fn extracts_resource(&self, typ: &MoveStructTag) -> bool {
+        // 0x1::ancestry::Ancestry
+        typ.address.inner().to_vec() == ROOT_ACCOUNT_ADDRESS.inner()
+            && typ.module.as_str() == "ancestry"
+            && typ.name.as_str() == "Ancestry"
+            && typ.generic_type_params.is_empty()
+    }
+
+    fn take_batch(&mut self) -> Result<Option<RecordBatch>> {
         if self.address.is_empty() {
-            return;
+            return Ok(None);
         }
 
-        let parquet_file = File::create(path).unwrap();
+        let rows = std::mem::replace(self, AncestryCollection::new());
 
-        let address = FixedSizeBinaryArray::try_from_iter(self.address.iter()).unwrap();
-        let tree = create_array_data_2d(&self.tree, "tree");
+        let address = FixedSizeBinaryArray::try_from_iter(rows.address.iter()).unwrap();
+        let tree = create_array_data_2d(&rows.tree, "tree");
 
         let batch = RecordBatch::try_from_iter(vec![
             ("address", Arc::new(address) as ArrayRef),
             ("tree", Arc::new(ListArray::from(tree)) as ArrayRef),
-        ])
-        .unwrap();
+        ])?;
 
-        let props = WriterProperties::builder().build();
-        let mut writer = ArrowWriter::try_new(parquet_file, batch.schema(), Some(props)).unwrap();
-        writer.write(&batch).expect("Writing batch");
-        writer.close().unwrap();
+        Ok(Some(batch))
     }
 }
@@ -1,11 +1,13 @@
-use std::{fs::File, sync::Arc};
+use anyhow::Result;
+use std::sync::Arc;
 
 use arrow_array::{ArrayRef, BinaryArray, FixedSizeBinaryArray, RecordBatch};
-use diem_api_types::BlockMetadataTransaction;
-use parquet::{arrow::arrow_writer::ArrowWriter, file::properties::WriterProperties};
+use arrow_schema::{DataType, Field, Schema};
+use diem_api_types::{BlockMetadataTransaction, Transaction};
 
-use crate::to_array_data::ToArrayData;
+use crate::{collection::Collection, to_array_data::ToArrayData};
 
+#[derive(Default)]
 pub struct BlockMetadataTransactionCollection {
     id: Vec<Vec<u8>>,
     version: Vec<u64>,
@@ -76,34 +78,70 @@ impl BlockMetadataTransactionCollection {
             }
         }
     }
+}
+
+impl Collection for BlockMetadataTransactionCollection {
+    fn name(&self) -> &'static str {
+        "block_metadata_transaction"
+    }
 
-    pub fn to_parquet(&self, path: String) {
+    fn schema(&self) -> Schema {
+        Schema::new(vec![
+            Field::new("version", DataType::UInt64, false),
+            Field::new("hash", DataType::FixedSizeBinary(32), false),
+            Field::new("state_change_hash", DataType::FixedSizeBinary(32), false),
+            Field::new("event_root_hash", DataType::FixedSizeBinary(32), false),
+            Field::new(
+                "accumulator_root_hash",
+                DataType::FixedSizeBinary(32),
+                false,
+            ),
+            Field::new("id", DataType::FixedSizeBinary(32), false),
+            Field::new("epoch", DataType::UInt64, false),
+            Field::new("round", DataType::UInt64, false),
+            Field::new("previous_block_votes_bitvec", DataType::Binary, false),
+            Field::new("proposer", DataType::FixedSizeBinary(32), false),
+            Field::new("failed_proposer_indices", DataType::Binary, false),
+            Field::new("timestamp", DataType::UInt64, false),
+            Field::new("state_checkpoint_hash", DataType::Binary, false),
+        ])
+    }
+
+    fn on_transaction(&mut self, transaction: &Transaction) -> Result<()> {
+        if let Transaction::BlockMetadataTransaction(block_metadata_transaction) = transaction {
+            self.push(block_metadata_transaction);
+        }
+
+        Ok(())
+    }
+
+    fn take_batch(&mut self) -> Result<Option<RecordBatch>> {
         if self.version.is_empty() {
-            return;
+            return Ok(None);
         }
 
-        let parquet_file = File::create(path).unwrap();
+        let rows = std::mem::replace(self, BlockMetadataTransactionCollection::new());
 
-        let id = FixedSizeBinaryArray::try_from_iter(self.id.iter()).unwrap();
-        let version = arrow_array::UInt64Array::from(self.version.clone());
-        let timestamp = arrow_array::UInt64Array::from(self.timestamp.clone());
-        let hash = FixedSizeBinaryArray::try_from_iter(self.hash.iter()).unwrap();
+        let id = FixedSizeBinaryArray::try_from_iter(rows.id.iter()).unwrap();
+        let version = arrow_array::UInt64Array::from(rows.version);
+        let timestamp = arrow_array::UInt64Array::from(rows.timestamp);
+        let hash = FixedSizeBinaryArray::try_from_iter(rows.hash.iter()).unwrap();
 
         let state_change_hash =
-            FixedSizeBinaryArray::try_from_iter(self.state_change_hash.iter()).unwrap();
+            FixedSizeBinaryArray::try_from_iter(rows.state_change_hash.iter()).unwrap();
         let event_root_hash =
-            FixedSizeBinaryArray::try_from_iter(self.event_root_hash.iter()).unwrap();
+            FixedSizeBinaryArray::try_from_iter(rows.event_root_hash.iter()).unwrap();
         let accumulator_root_hash =
-            FixedSizeBinaryArray::try_from_iter(self.accumulator_root_hash.iter()).unwrap();
-        let epoch = arrow_array::UInt64Array::from(self.epoch.clone());
-        let round = arrow_array::UInt64Array::from(self.round.clone());
+            FixedSizeBinaryArray::try_from_iter(rows.accumulator_root_hash.iter()).unwrap();
+        let epoch = arrow_array::UInt64Array::from(rows.epoch);
+        let round = arrow_array::UInt64Array::from(rows.round);
 
         let previous_block_votes_bitvec =
-            BinaryArray::from(self.previous_block_votes_bitvec.to_array_data());
-        let proposer = FixedSizeBinaryArray::try_from_iter(self.proposer.iter()).unwrap();
+            BinaryArray::from(rows.previous_block_votes_bitvec.to_array_data());
+        let proposer = FixedSizeBinaryArray::try_from_iter(rows.proposer.iter()).unwrap();
         let failed_proposer_indices =
-            BinaryArray::from(self.failed_proposer_indices.to_array_data());
-        let state_checkpoint_hash = BinaryArray::from(self.state_checkpoint_hash.to_array_data());
+            BinaryArray::from(rows.failed_proposer_indices.to_array_data());
+        let state_checkpoint_hash = BinaryArray::from(rows.state_checkpoint_hash.to_array_data());
 
         let batch = RecordBatch::try_from_iter(vec![
             ("version", Arc::new(version) as ArrayRef),
@@ -131,12 +169,8 @@ impl BlockMetadataTransactionCollection {
                 "state_checkpoint_hash",
                 Arc::new(state_checkpoint_hash) as ArrayRef,
             ),
-        ])
-        .unwrap();
+        ])?;
 
-        let props = WriterProperties::builder().build();
-        let mut writer = ArrowWriter::try_new(parquet_file, batch.schema(), Some(props)).unwrap();
-        writer.write(&batch).expect("Writing batch");
-        writer.close().unwrap();
+        Ok(Some(batch))
     }
 }
@@ -1,7 +1,13 @@
+use anyhow::Result;
 use arrow_array::{ArrayRef, FixedSizeBinaryArray, RecordBatch};
-use parquet::{arrow::arrow_writer::ArrowWriter, file::properties::WriterProperties};
-use std::{fs::File, sync::Arc};
+use arrow_schema::{DataType, Field, Schema};
+use diem_api_types::{IdentifierWrapper, MoveStructTag, MoveType, WriteSetChange};
+use serde_json::Value;
+use std::{str::FromStr, sync::Arc};
 
+use crate::{collection::Collection, constants::ROOT_ACCOUNT_ADDRESS};
+
+#[derive(Default)]
 pub struct CoinBalanceCollection {
     address: Vec<Vec<u8>>,
     balance: Vec<u64>,
@@ -25,6 +31,7 @@ impl CoinBalanceCollection {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn push(
         &mut self,
         address: Vec<u8>,
@@ -49,19 +56,88 @@ impl CoinBalanceCollection {
         self.coin_module.push(coin_module);
         self.coin_name.push(coin_name);
     }
+}
+
+impl Collection for CoinBalanceCollection {
+    fn name(&self) -> &'static str {
+        "coin_balance"
+    }
+
+    fn schema(&self) -> Schema {
+        Schema::new(vec![
+            Field::new("address", DataType::FixedSizeBinary(32), false),
+            Field::new("version", DataType::UInt64, false),
+            Field::new("balance", DataType::UInt64, false),
+            Field::new("change_index", DataType::UInt64, false),
+            Field::new("coin_address", DataType::FixedSizeBinary(32), false),
+            Field::new("coin_module", DataType::Utf8, false),
+            Field::new("coin_name", DataType::Utf8, false),
+        ])
+    }
+
+    fn on_write_set_change(
+        &mut self,
+        version: u64,
+        change_index: u64,
+        change: &WriteSetChange,
+    ) -> Result<()> {
+        if let WriteSetChange::WriteResource(change) = change {
+            if !self.extracts_resource(&change.data.typ) {
+                return Ok(());
+            }
+
+            let param = &change.data.typ.generic_type_params[0];
+
+            if let MoveType::Struct(param) = param {
+                let coin_identifier = IdentifierWrapper::from_str("coin").unwrap();
+
+                if let Some(Value::Object(coin)) = change.data.data.0.get(&coin_identifier) {
+                    if let Some(Value::String(value)) = coin.get("value") {
+                        if let Ok(balance) = value.parse::<u64>() {
+                            let address = change.address.inner().to_vec();
+                            let coin_address = param.address.inner().to_vec();
+                            let coin_module = param.module.as_str();
+                            let coin_name = param.name.as_str();
+                            self.push(
+                                address,
+                                balance,
+                                version,
+                                change_index,
+                                coin_address,
+                                coin_module.to_string(),
+                                coin_name.to_string(),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
 
-    pub fn to_parquet(&self, path: String) {
+    fn extracts_resource(&self, typ: &MoveStructTag) -> bool {
+        // 0x1::coin::CoinStore<T>
+        typ.address.inner().to_vec() == ROOT_ACCOUNT_ADDRESS.inner()
+            && typ.module.as_str() == "coin"
+            && typ.name.as_str() == "CoinStore"
+            && typ.generic_type_params.len() == 1
+    }
+
+    fn take_batch(&mut self) -> Result<Option<RecordBatch>> {
         if self.version.is_empty() {
-            return;
+            return Ok(None);
         }
 
-        let address = FixedSizeBinaryArray::try_from_iter(self.address.iter()).unwrap();
-        let balance = arrow_array::UInt64Array::from(self.balance.clone());
-        let version = arrow_array::UInt64Array::from(self.version.clone());
-        let change_index = arrow_array::UInt64Array::from(self.change_index.clone());
-        let coin_address = FixedSizeBinaryArray::try_from_iter(self.coin_address.iter()).unwrap();
-        let coin_module = arrow_array::StringArray::from(self.coin_module.clone());
-        let coin_name = arrow_array::StringArray::from(self.coin_name.clone());
+        let rows = std::mem::replace(self, CoinBalanceCollection::new());
+
+        let address = FixedSizeBinaryArray::try_from_iter(rows.address.iter()).unwrap();
+        let balance = arrow_array::UInt64Array::from(rows.balance);
+        let version = arrow_array::UInt64Array::from(rows.version);
+        let change_index = arrow_array::UInt64Array::from(rows.change_index);
+        let coin_address = FixedSizeBinaryArray::try_from_iter(rows.coin_address.iter()).unwrap();
+        let coin_module = arrow_array::StringArray::from(rows.coin_module);
+        let coin_name = arrow_array::StringArray::from(rows.coin_name);
 
         let batch = RecordBatch::try_from_iter(vec![
             ("address", Arc::new(address) as ArrayRef),
@@ -71,14 +147,8 @@ impl CoinBalanceCollection {
             ("coin_address", Arc::new(coin_address) as ArrayRef),
             ("coin_module", Arc::new(coin_module) as ArrayRef),
             ("coin_name", Arc::new(coin_name) as ArrayRef),
-        ])
-        .unwrap();
-
-        let parquet_file = File::create(path).unwrap();
-        let props = WriterProperties::builder().build();
+        ])?;
 
-        let mut writer = ArrowWriter::try_new(parquet_file, batch.schema(), Some(props)).unwrap();
-        writer.write(&batch).expect("Writing batch");
-        writer.close().unwrap();
+        Ok(Some(batch))
     }
 }
@@ -1,8 +1,12 @@
+use anyhow::{bail, Result};
 use arrow_array::{ArrayRef, FixedSizeBinaryArray, RecordBatch};
+use arrow_schema::{DataType, Field, Schema};
 use diem_api_types::{Event, Transaction};
-use parquet::{arrow::arrow_writer::ArrowWriter, file::properties::WriterProperties};
-use std::{fs::File, sync::Arc};
+use std::sync::Arc;
 
+use crate::collection::Collection;
+
+#[derive(Default)]
 pub struct EventCollection {
     version: Vec<u64>,
     creation_number: Vec<u64>,
@@ -30,30 +34,41 @@ impl EventCollection {
         }
     }
 
-    pub fn push(&mut self, transaction: &Transaction) {
+    pub fn push(&mut self, transaction: &Transaction) -> Result<()> {
         match transaction {
             Transaction::PendingTransaction(_) => {}
             Transaction::UserTransaction(user_transaction) => {
                 let info = &user_transaction.info;
                 let events = &user_transaction.events;
-                self.push_events(info.version.into(), events);
+                self.push_events(info.version.into(), events)?;
             }
             Transaction::GenesisTransaction(genesis_transaction) => {
                 let info = &genesis_transaction.info;
                 let events = &genesis_transaction.events;
-                self.push_events(info.version.into(), events);
+                self.push_events(info.version.into(), events)?;
             }
             Transaction::BlockMetadataTransaction(block_metadata_transaction) => {
                 let info = &block_metadata_transaction.info;
                 let events = &block_metadata_transaction.events;
-                self.push_events(info.version.into(), events);
+                self.push_events(info.version.into(), events)?;
             }
             Transaction::StateCheckpointTransaction(_) => {}
         }
+
+        Ok(())
     }
 
-    fn push_events(&mut self, version: u64, events: &Vec<Event>) {
-        for (index, event) in events.iter().enumerate() {
+    fn push_events(&mut self, version: u64, events: &[Event]) -> Result<()> {
+        // Check every event first so a bad one doesn't leave a partial transaction behind.
+        let mut types = Vec::with_capacity(events.len());
+        for event in events.iter() {
+            match &event.typ {
+                diem_api_types::MoveType::Struct(s) => types.push(s),
+                move_type => bail!("invalid event move type {:?}", move_type),
+            }
+        }
+
+        for (index, (event, s)) in events.iter().zip(types).enumerate() {
             self.index.push(index as u64);
             self.version.push(version);
             self.creation_number.push(event.guid.creation_number.into());
@@ -65,39 +80,60 @@ impl EventCollection {
             self.sequence_number.push(event.sequence_number.into());
             self.data.push(serde_json::to_string(&event.data).unwrap());
 
-            match &event.typ {
-                diem_api_types::MoveType::Struct(s) => {
-                    let mut module_address = s.address.inner().to_vec();
-                    module_address.reverse();
-                    self.module_address.push(module_address);
-
-                    self.module_name.push(s.module.0.to_string());
-                    self.struct_name.push(s.name.0.to_string());
-                }
-                move_type => {
-                    panic!("Invalid event move type {:?}", move_type);
-                }
-            }
+            let mut module_address = s.address.inner().to_vec();
+            module_address.reverse();
+            self.module_address.push(module_address);
+
+            self.module_name.push(s.module.0.to_string());
+            self.struct_name.push(s.name.0.to_string());
         }
+
+        Ok(())
     }
+}
 
-    pub fn to_parquet(&self, path: String) {
+impl Collection for EventCollection {
+    fn name(&self) -> &'static str {
+        "event"
+    }
+
+    fn schema(&self) -> Schema {
+        Schema::new(vec![
+            Field::new("version", DataType::UInt64, false),
+            Field::new("index", DataType::UInt64, false),
+            Field::new("creation_number", DataType::UInt64, false),
+            Field::new("account_address", DataType::FixedSizeBinary(32), false),
+            Field::new("sequence_number", DataType::UInt64, false),
+            Field::new("module_address", DataType::FixedSizeBinary(32), false),
+            Field::new("module_name", DataType::Utf8, false),
+            Field::new("struct_name", DataType::Utf8, false),
+            Field::new("data", DataType::Utf8, false),
+        ])
+    }
+
+    fn on_transaction(&mut self, transaction: &Transaction) -> Result<()> {
+        self.push(transaction)
+    }
+
+    fn take_batch(&mut self) -> Result<Option<RecordBatch>> {
         if self.version.is_empty() {
-            return;
+            return Ok(None);
         }
 
-        let version = arrow_array::UInt64Array::from(self.version.clone());
-        let index = arrow_array::UInt64Array::from(self.index.clone());
-        let creation_number = arrow_array::UInt64Array::from(self.creation_number.clone());
+        let rows = std::mem::replace(self, EventCollection::new());
+
+        let version = arrow_array::UInt64Array::from(rows.version);
+        let index = arrow_array::UInt64Array::from(rows.index);
+        let creation_number = arrow_array::UInt64Array::from(rows.creation_number);
         let account_address =
-            FixedSizeBinaryArray::try_from_iter(self.account_address.iter()).unwrap();
-        let sequence_number = arrow_array::UInt64Array::from(self.sequence_number.clone());
+            FixedSizeBinaryArray::try_from_iter(rows.account_address.iter()).unwrap();
+        let sequence_number = arrow_array::UInt64Array::from(rows.sequence_number);
         let module_address =
-            FixedSizeBinaryArray::try_from_iter(self.module_address.iter()).unwrap();
+            FixedSizeBinaryArray::try_from_iter(rows.module_address.iter()).unwrap();
 
-        let module_name = arrow_array::StringArray::from(self.module_name.clone());
-        let struct_name = arrow_array::StringArray::from(self.struct_name.clone());
-        let data = arrow_array::StringArray::from(self.data.clone());
+        let module_name = arrow_array::StringArray::from(rows.module_name);
+        let struct_name = arrow_array::StringArray::from(rows.struct_name);
+        let data = arrow_array::StringArray::from(rows.data);
 
         let batch = RecordBatch::try_from_iter(vec![
             ("version", Arc::new(version) as ArrayRef),
@@ -109,14 +145,8 @@ impl EventCollection {
             ("module_name", Arc::new(module_name) as ArrayRef),
             ("struct_name", Arc::new(struct_name) as ArrayRef),
             ("data", Arc::new(data) as ArrayRef),
-        ])
-        .unwrap();
-
-        let parquet_file = File::create(path).unwrap();
-        let props = WriterProperties::builder().build();
+        ])?;
 
-        let mut writer = ArrowWriter::try_new(parquet_file, batch.schema(), Some(props)).unwrap();
-        writer.write(&batch).expect("Writing batch");
-        writer.close().unwrap();
+        Ok(Some(batch))
     }
 }
@@ -1,9 +1,13 @@
-use std::{fs::File, sync::Arc};
+use anyhow::Result;
+use std::sync::Arc;
 
 use arrow_array::{ArrayRef, FixedSizeBinaryArray, RecordBatch};
-use diem_api_types::transaction::GenesisTransaction;
-use parquet::{arrow::arrow_writer::ArrowWriter, file::properties::WriterProperties};
+use arrow_schema::{DataType, Field, Schema};
+use diem_api_types::transaction::{GenesisTransaction, Transaction};
 
+use crate::collection::Collection;
+
+#[derive(Default)]
 pub struct GenesisTransactionCollection {
     version: Vec<u64>,
     hash: Vec<Vec<u8>>,
@@ -58,27 +62,65 @@ impl GenesisTransactionCollection {
             }
         }
     }
+}
 
-    pub fn to_parquet(&self, path: String) {
+impl Collection for GenesisTransactionCollection {
+    fn name(&self) -> &'static str {
+        "genesis_transaction"
+    }
+
+    fn schema(&self) -> Schema {
+        Schema::new(vec![
+            Field::new("version", DataType::UInt64, false),
+            Field::new("hash", DataType::FixedSizeBinary(32), false),
+            Field::new("state_change_hash", DataType::FixedSizeBinary(32), false),
+            Field::new("event_root_hash", DataType::FixedSizeBinary(32), false),
+            Field::new(
+                "state_checkpoint_hash",
+                DataType::FixedSizeBinary(32),
+                false,
+            ),
+            Field::new("gas_used", DataType::UInt64, false),
+            Field::new("success", DataType::Boolean, false),
+            Field::new("vm_status", DataType::Utf8, false),
+            Field::new(
+                "accumulator_root_hash",
+                DataType::FixedSizeBinary(32),
+                false,
+            ),
+        ])
+    }
+
+    fn on_transaction(&mut self, transaction: &Transaction) -> Result<()> {
+        if let Transaction::GenesisTransaction(genesis_transaction) = transaction {
+            self.push(genesis_transaction);
+        }
+
+        Ok(())
+    }
+
+    fn take_batch(&mut self) -> Result<Option<RecordBatch>> {
         if self.version.is_empty() {
-            return;
+            return Ok(None);
         }
 
-        let version = arrow_array::UInt64Array::from(self.version.clone());
-        let hash = FixedSizeBinaryArray::try_from_iter(self.hash.iter()).unwrap();
+        let rows = std::mem::replace(self, GenesisTransactionCollection::new());
+
+        let version = arrow_array::UInt64Array::from(rows.version);
+        let hash = FixedSizeBinaryArray::try_from_iter(rows.hash.iter()).unwrap();
         let state_change_hash =
-            FixedSizeBinaryArray::try_from_iter(self.state_change_hash.iter()).unwrap();
+            FixedSizeBinaryArray::try_from_iter(rows.state_change_hash.iter()).unwrap();
         let event_root_hash =
-            FixedSizeBinaryArray::try_from_iter(self.event_root_hash.iter()).unwrap();
+            FixedSizeBinaryArray::try_from_iter(rows.event_root_hash.iter()).unwrap();
         let state_checkpoint_hash =
-            FixedSizeBinaryArray::try_from_iter(self.state_checkpoint_hash.iter()).unwrap();
-        let gas_used = arrow_array::UInt64Array::from(self.gas_used.clone());
+            FixedSizeBinaryArray::try_from_iter(rows.state_checkpoint_hash.iter()).unwrap();
+        let gas_used = arrow_array::UInt64Array::from(rows.gas_used);
 
-        let success = arrow_array::BooleanArray::from(self.success.clone());
-        let vm_status = arrow_array::StringArray::from(self.vm_status.clone());
-        // let accumulator_root_hash = BinaryArray::from(self.accumulator_root_hash.to_array_data());
+        let success = arrow_array::BooleanArray::from(rows.success);
+        let vm_status = arrow_array::StringArray::from(rows.vm_status);
+        // let accumulator_root_hash = BinaryArray::from(rows.accumulator_root_hash.to_array_data());
         let accumulator_root_hash =
-            FixedSizeBinaryArray::try_from_iter(self.accumulator_root_hash.iter()).unwrap();
+            FixedSizeBinaryArray::try_from_iter(rows.accumulator_root_hash.iter()).unwrap();
 
         let batch = RecordBatch::try_from_iter(vec![
             ("version", Arc::new(version) as ArrayRef),
@@ -96,13 +138,8 @@ impl GenesisTransactionCollection {
                 "accumulator_root_hash",
                 Arc::new(accumulator_root_hash) as ArrayRef,
             ),
-        ])
-        .unwrap();
+        ])?;
 
-        let parquet_file = File::create(path).unwrap();
-        let props = WriterProperties::builder().build();
-        let mut writer = ArrowWriter::try_new(parquet_file, batch.schema(), Some(props)).unwrap();
-        writer.write(&batch).expect("Writing batch");
-        writer.close().unwrap();
+        Ok(Some(batch))
     }
 }
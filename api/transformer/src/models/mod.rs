@@ -43,3 +43,34 @@ pub use total_supply_collection::TotalSupplyCollection;
 pub use tower_list_collection::TowerListCollection;
 pub use user_transaction_collection::UserTransactionCollection;
 pub use vdf_difficulty_collection::VdfDifficultyCollection;
+
+use crate::collection::Collection;
+
+/// Every collection produced by the transformer. Adding a collection means
+/// adding a model file and registering it here.
+pub fn registry() -> Vec<Box<dyn Collection>> {
+    vec![
+        Box::new(EventCollection::new()),
+        Box::new(UserTransactionCollection::new()),
+        Box::new(GenesisTransactionCollection::new()),
+        Box::new(AncestryCollection::new()),
+        Box::new(BlockMetadataTransactionCollection::new()),
+        Box::new(StateCheckpointTransactionCollection::new()),
+        Box::new(TotalSupplyCollection::new()),
+        Box::new(CoinBalanceCollection::new()),
+        Box::new(ScriptCollection::new()),
+        Box::new(BeneficiaryPolicyCollection::new()),
+        Box::new(TowerListCollection::new()),
+        Box::new(BurnTrackerCollection::new()),
+        Box::new(BurnCounterCollection::new()),
+        Box::new(SlowWalletCollection::new()),
+        Box::new(EpochFeeMakerRegistryCollection::new()),
+        Box::new(SlowWalletListCollection::new()),
+        Box::new(VdfDifficultyCollection::new()),
+        Box::new(ConsensusRewardCollection::new()),
+        Box::new(BoundaryStatusCollection::new()),
+        Box::new(MultisigAccountOwnersCollection::new()),
+        Box::new(MultiActionCollection::new()),
+        Box::new(DonorVoiceRegistryCollection::new()),
+    ]
+}
@@ -1,9 +1,13 @@
-use std::{fs::File, sync::Arc};
+use anyhow::Result;
+use std::sync::Arc;
 
 use arrow_array::{ArrayRef, FixedSizeBinaryArray, RecordBatch};
-use diem_api_types::MoveStructTag;
-use parquet::{arrow::arrow_writer::ArrowWriter, file::properties::WriterProperties};
+use arrow_schema::{DataType, Field, Schema};
+use diem_api_types::{MoveStructTag, MoveType, WriteSetChange};
 
+use crate::{collection::Collection, constants::ROOT_ACCOUNT_ADDRESS};
+
+#[derive(Default)]
 pub struct MultiActionCollection {
     version: Vec<u64>,
     change_index: Vec<u64>,
@@ -62,22 +66,78 @@ impl MultiActionCollection {
 
         self.change.push(change);
     }
+}
+
+impl Collection for MultiActionCollection {
+    fn name(&self) -> &'static str {
+        "multi_action"
+    }
+
+    fn schema(&self) -> Schema {
+        Schema::new(vec![
+            Field::new("version", DataType::UInt64, false),
+            Field::new("change_index", DataType::UInt64, false),
+            Field::new("address", DataType::FixedSizeBinary(32), false),
+            Field::new(
+                "tally_type_module_address",
+                DataType::FixedSizeBinary(32),
+                false,
+            ),
+            Field::new("tally_type_module_name", DataType::Utf8, false),
+            Field::new("tally_type_struct_name", DataType::Utf8, false),
+            Field::new("change", DataType::Utf8, false),
+        ])
+    }
+
+    fn on_write_set_change(
+        &mut self,
+        version: u64,
+        change_index: u64,
+        change: &WriteSetChange,
+    ) -> Result<()> {
+        if let WriteSetChange::WriteResource(change) = change {
+            let address = change.address.inner().to_vec();
+            if !self.extracts_resource(&change.data.typ) {
+                return Ok(());
+            }
+
+            if let MoveType::Struct(tally_type) = change.data.typ.generic_type_params[0].clone() {
+                self.push(
+                    version,
+                    change_index,
+                    address,
+                    tally_type,
+                    serde_json::to_string(&change.data).unwrap(),
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn extracts_resource(&self, typ: &MoveStructTag) -> bool {
+        // 0x1::multi_action::Action<0x1::donor_voice_txs::Payment>
+        typ.address.inner().to_vec() == ROOT_ACCOUNT_ADDRESS.inner()
+            && typ.module.as_str() == "multi_action"
+            && typ.name.as_str() == "Action"
+            && typ.generic_type_params.len() == 1
+    }
 
-    pub fn to_parquet(&self, path: String) {
+    fn take_batch(&mut self) -> Result<Option<RecordBatch>> {
         if self.version.is_empty() {
-            return;
+            return Ok(None);
         }
 
-        let version = arrow_array::UInt64Array::from(self.version.clone());
-        let change_index = arrow_array::UInt64Array::from(self.change_index.clone());
-        let address = FixedSizeBinaryArray::try_from_iter(self.address.iter()).unwrap();
+        let rows = std::mem::replace(self, MultiActionCollection::new());
+
+        let version = arrow_array::UInt64Array::from(rows.version);
+        let change_index = arrow_array::UInt64Array::from(rows.change_index);
+        let address = FixedSizeBinaryArray::try_from_iter(rows.address.iter()).unwrap();
         let tally_type_module_address =
-            FixedSizeBinaryArray::try_from_iter(self.tally_type_module_address.iter()).unwrap();
-        let tally_type_module_name =
-            arrow_array::StringArray::from(self.tally_type_module_name.clone());
-        let tally_type_struct_name =
-            arrow_array::StringArray::from(self.tally_type_struct_name.clone());
-        let change = arrow_array::StringArray::from(self.change.clone());
+            FixedSizeBinaryArray::try_from_iter(rows.tally_type_module_address.iter()).unwrap();
+        let tally_type_module_name = arrow_array::StringArray::from(rows.tally_type_module_name);
+        let tally_type_struct_name = arrow_array::StringArray::from(rows.tally_type_struct_name);
+        let change = arrow_array::StringArray::from(rows.change);
 
         let batch = RecordBatch::try_from_iter(vec![
             ("version", Arc::new(version) as ArrayRef),
@@ -96,13 +156,8 @@ impl MultiActionCollection {
                 Arc::new(tally_type_struct_name) as ArrayRef,
             ),
             ("change", Arc::new(change) as ArrayRef),
-        ])
-        .unwrap();
+        ])?;
 
-        let parquet_file = File::create(path).unwrap();
-        let props = WriterProperties::builder().build();
-        let mut writer = ArrowWriter::try_new(parquet_file, batch.schema(), Some(props)).unwrap();
-        writer.write(&batch).expect("Writing batch");
-        writer.close().unwrap();
+        Ok(Some(batch))
     }
 }
@@ -1,7 +1,15 @@
+use anyhow::Result;
 use arrow_array::{ArrayRef, FixedSizeBinaryArray, RecordBatch};
-use parquet::{arrow::arrow_writer::ArrowWriter, file::properties::WriterProperties};
-use std::{fs::File, sync::Arc};
+use arrow_schema::{DataType, Field, Schema};
+use diem_api_types::WriteSetChange;
+use std::sync::Arc;
 
+use crate::{
+    collection::Collection,
+    constants::{LIBRA_COIN_HANDLE, LIBRA_COIN_KEY},
+};
+
+#[derive(Default)]
 pub struct TotalSupplyCollection {
     amount: Vec<Vec<u8>>,
     version: Vec<u64>,
@@ -22,28 +30,54 @@ impl TotalSupplyCollection {
         self.amount.push(amount);
         self.change_index.push(change_index);
     }
+}
+
+impl Collection for TotalSupplyCollection {
+    fn name(&self) -> &'static str {
+        "total_supply"
+    }
+
+    fn schema(&self) -> Schema {
+        Schema::new(vec![
+            Field::new("version", DataType::UInt64, false),
+            Field::new("amount", DataType::FixedSizeBinary(16), false),
+            Field::new("change_index", DataType::UInt64, false),
+        ])
+    }
 
-    pub fn to_parquet(&self, path: String) {
+    fn on_write_set_change(
+        &mut self,
+        version: u64,
+        change_index: u64,
+        change: &WriteSetChange,
+    ) -> Result<()> {
+        if let WriteSetChange::WriteTableItem(change) = change {
+            if change.handle.eq(&LIBRA_COIN_HANDLE) && change.key.eq(&LIBRA_COIN_KEY) {
+                // let value: u128 = from_bytes(&change.value.0).unwrap();
+                self.push(version, change.value.0.clone(), change_index);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn take_batch(&mut self) -> Result<Option<RecordBatch>> {
         if self.version.is_empty() {
-            return;
+            return Ok(None);
         }
 
-        let version = arrow_array::UInt64Array::from(self.version.clone());
-        let amount = FixedSizeBinaryArray::try_from_iter(self.amount.iter()).unwrap();
-        let change_index = arrow_array::UInt64Array::from(self.change_index.clone());
+        let rows = std::mem::replace(self, TotalSupplyCollection::new());
+
+        let version = arrow_array::UInt64Array::from(rows.version);
+        let amount = FixedSizeBinaryArray::try_from_iter(rows.amount.iter()).unwrap();
+        let change_index = arrow_array::UInt64Array::from(rows.change_index);
 
         let batch = RecordBatch::try_from_iter(vec![
             ("version", Arc::new(version) as ArrayRef),
             ("amount", Arc::new(amount) as ArrayRef),
             ("change_index", Arc::new(change_index) as ArrayRef),
-        ])
-        .unwrap();
-
-        let parquet_file = File::create(path).unwrap();
-        let props = WriterProperties::builder().build();
+        ])?;
 
-        let mut writer = ArrowWriter::try_new(parquet_file, batch.schema(), Some(props)).unwrap();
-        writer.write(&batch).expect("Writing batch");
-        writer.close().unwrap();
+        Ok(Some(batch))
     }
 }
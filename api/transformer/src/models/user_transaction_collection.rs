@@ -1,9 +1,13 @@
-use std::{fs::File, sync::Arc};
+use anyhow::{bail, Result};
+use std::sync::Arc;
 
 use arrow_array::{ArrayRef, FixedSizeBinaryArray, RecordBatch};
-use diem_api_types::UserTransaction;
-use parquet::{arrow::arrow_writer::ArrowWriter, file::properties::WriterProperties};
+use arrow_schema::{DataType, Field, Schema};
+use diem_api_types::{Transaction, TransactionPayload, UserTransaction};
 
+use crate::collection::Collection;
+
+#[derive(Default)]
 pub struct UserTransactionCollection {
     version: Vec<u64>,
     hash: Vec<Vec<u8>>,
@@ -51,7 +55,7 @@ impl UserTransactionCollection {
         }
     }
 
-    pub fn push(&mut self, user_transaction: &UserTransaction) {
+    pub fn push(&mut self, user_transaction: &UserTransaction) -> Result<()> {
         let info = &user_transaction.info;
         let request = &user_transaction.request;
         let payload = &request.payload;
@@ -100,41 +104,88 @@ impl UserTransactionCollection {
             let type_arguments =
                 serde_json::to_string(&entry_function_payload.type_arguments).unwrap();
             self.type_arguments.push(type_arguments);
+
+            Ok(())
         } else {
-            panic!("payload must be EntryFunctionPayload")
+            bail!("payload must be EntryFunctionPayload");
+        }
+    }
+}
+
+impl Collection for UserTransactionCollection {
+    fn name(&self) -> &'static str {
+        "user_transaction"
+    }
+
+    fn schema(&self) -> Schema {
+        Schema::new(vec![
+            Field::new("version", DataType::UInt64, false),
+            Field::new("hash", DataType::FixedSizeBinary(32), false),
+            Field::new("state_change_hash", DataType::FixedSizeBinary(32), false),
+            Field::new("event_root_hash", DataType::FixedSizeBinary(32), false),
+            Field::new("gas_used", DataType::UInt64, false),
+            Field::new("success", DataType::Boolean, false),
+            Field::new("vm_status", DataType::Utf8, false),
+            Field::new(
+                "accumulator_root_hash",
+                DataType::FixedSizeBinary(32),
+                false,
+            ),
+            Field::new("sender", DataType::FixedSizeBinary(32), false),
+            Field::new("sequence_number", DataType::UInt64, false),
+            Field::new("max_gas_amount", DataType::UInt64, false),
+            Field::new("gas_unit_price", DataType::UInt64, false),
+            Field::new("expiration_timestamp", DataType::UInt64, false),
+            Field::new("module_address", DataType::FixedSizeBinary(32), false),
+            Field::new("module_name", DataType::Utf8, false),
+            Field::new("function_name", DataType::Utf8, false),
+            Field::new("arguments", DataType::Utf8, false),
+            Field::new("type_arguments", DataType::Utf8, false),
+            Field::new("timestamp", DataType::UInt64, false),
+        ])
+    }
+
+    fn on_transaction(&mut self, transaction: &Transaction) -> Result<()> {
+        if let Transaction::UserTransaction(user_transaction) = transaction {
+            if let TransactionPayload::EntryFunctionPayload(_) = user_transaction.request.payload {
+                self.push(user_transaction)?;
+            }
         }
+
+        Ok(())
     }
 
-    pub fn to_parquet(&self, path: String) {
+    fn take_batch(&mut self) -> Result<Option<RecordBatch>> {
         if self.version.is_empty() {
-            return;
+            return Ok(None);
         }
 
-        let version = arrow_array::UInt64Array::from(self.version.clone());
-        let hash = FixedSizeBinaryArray::try_from_iter(self.hash.iter()).unwrap();
+        let rows = std::mem::replace(self, UserTransactionCollection::new());
+
+        let version = arrow_array::UInt64Array::from(rows.version);
+        let hash = FixedSizeBinaryArray::try_from_iter(rows.hash.iter()).unwrap();
         let state_change_hash =
-            FixedSizeBinaryArray::try_from_iter(self.state_change_hash.iter()).unwrap();
+            FixedSizeBinaryArray::try_from_iter(rows.state_change_hash.iter()).unwrap();
         let event_root_hash =
-            FixedSizeBinaryArray::try_from_iter(self.event_root_hash.iter()).unwrap();
-        let gas_used = arrow_array::UInt64Array::from(self.gas_used.clone());
-        let success = arrow_array::BooleanArray::from(self.success.clone());
-        let vm_status = arrow_array::StringArray::from(self.vm_status.clone());
-        // let accumulator_root_hash = BinaryArray::from(self.accumulator_root_hash.to_array_data());
+            FixedSizeBinaryArray::try_from_iter(rows.event_root_hash.iter()).unwrap();
+        let gas_used = arrow_array::UInt64Array::from(rows.gas_used);
+        let success = arrow_array::BooleanArray::from(rows.success);
+        let vm_status = arrow_array::StringArray::from(rows.vm_status);
+        // let accumulator_root_hash = BinaryArray::from(rows.accumulator_root_hash.to_array_data());
         let accumulator_root_hash =
-            FixedSizeBinaryArray::try_from_iter(self.accumulator_root_hash.iter()).unwrap();
-        let sender = FixedSizeBinaryArray::try_from_iter(self.sender.iter()).unwrap();
-        let sequence_number = arrow_array::UInt64Array::from(self.sequence_number.clone());
-        let max_gas_amount = arrow_array::UInt64Array::from(self.max_gas_amount.clone());
-        let gas_unit_price = arrow_array::UInt64Array::from(self.gas_unit_price.clone());
-        let expiration_timestamp =
-            arrow_array::UInt64Array::from(self.expiration_timestamp.clone());
+            FixedSizeBinaryArray::try_from_iter(rows.accumulator_root_hash.iter()).unwrap();
+        let sender = FixedSizeBinaryArray::try_from_iter(rows.sender.iter()).unwrap();
+        let sequence_number = arrow_array::UInt64Array::from(rows.sequence_number);
+        let max_gas_amount = arrow_array::UInt64Array::from(rows.max_gas_amount);
+        let gas_unit_price = arrow_array::UInt64Array::from(rows.gas_unit_price);
+        let expiration_timestamp = arrow_array::UInt64Array::from(rows.expiration_timestamp);
         let module_address =
-            FixedSizeBinaryArray::try_from_iter(self.module_address.iter()).unwrap();
-        let module_name = arrow_array::StringArray::from(self.module_name.clone());
-        let function_name = arrow_array::StringArray::from(self.function_name.clone());
-        let arguments = arrow_array::StringArray::from(self.arguments.clone());
-        let type_arguments = arrow_array::StringArray::from(self.type_arguments.clone());
-        let timestamp = arrow_array::UInt64Array::from(self.timestamp.clone());
+            FixedSizeBinaryArray::try_from_iter(rows.module_address.iter()).unwrap();
+        let module_name = arrow_array::StringArray::from(rows.module_name);
+        let function_name = arrow_array::StringArray::from(rows.function_name);
+        let arguments = arrow_array::StringArray::from(rows.arguments);
+        let type_arguments = arrow_array::StringArray::from(rows.type_arguments);
+        let timestamp = arrow_array::UInt64Array::from(rows.timestamp);
 
         let batch = RecordBatch::try_from_iter(vec![
             ("version", Arc::new(version) as ArrayRef),
@@ -162,13 +213,8 @@ impl UserTransactionCollection {
             ("arguments", Arc::new(arguments) as ArrayRef),
             ("type_arguments", Arc::new(type_arguments) as ArrayRef),
             ("timestamp", Arc::new(timestamp) as ArrayRef),
-        ])
-        .unwrap();
+        ])?;
 
-        let parquet_file = File::create(path).unwrap();
-        let props = WriterProperties::builder().build();
-        let mut writer = ArrowWriter::try_new(parquet_file, batch.schema(), Some(props)).unwrap();
-        writer.write(&batch).expect("Writing batch");
-        writer.close().unwrap();
+        Ok(Some(batch))
     }
 }
@@ -0,0 +1,388 @@
+use std::{str::FromStr, sync::Arc};
+
+use anyhow::{anyhow, bail, Context, Result};
+use arrow_array::{ArrayRef, FixedSizeBinaryArray, ListArray, RecordBatch};
+use arrow_schema::{DataType, Field, Schema};
+use diem_api_types::{IdentifierWrapper, MoveStructTag, MoveStructValue, WriteSetChange};
+use serde_json::Value;
+
+use crate::{
+    collection::Collection, constants::ROOT_ACCOUNT_ADDRESS, to_array_data::create_array_data_2d,
+};
+
+/// A Move resource that maps to one row of a collection.
+///
+/// Implemented with `#[derive(MoveResource)]` from `transformer-derive`, which
+/// also generates the struct-of-arrays `Columns` type.
+pub trait MoveResource: Sized + 'static {
+    const MOVE_TYPE: &'static str;
+    const COLLECTION_NAME: &'static str;
+    const WITH_ADDRESS: bool;
+    const ROOT_ONLY: bool;
+    const SCHEMA_VERSION: u32;
+
+    type Columns: ResourceColumns<Row = Self> + Default;
+
+    fn matches(typ: &MoveStructTag) -> bool;
+
+    fn from_move_value(data: &MoveStructValue) -> Result<Self>;
+}
+
+pub trait ResourceColumns {
+    type Row;
+
+    fn push(&mut self, row: Self::Row);
+
+    fn to_arrays(&self) -> Result<Vec<(&'static str, ArrayRef)>>;
+
+    /// Fields of the arrays returned by `to_arrays`.
+    fn fields() -> Vec<Field>;
+}
+
+/// Conversion from the JSON representation of a Move value.
+///
+/// `u64` and `u128` are encoded as strings by the API. `Vec<u8>` is read as
+/// an address and `Vec<Vec<u8>>` as a vector of addresses. Addresses are
+/// always 32 bytes, short ones like `0x1` are zero padded, and their bytes are
+/// reversed like everywhere else in the transformer.
+pub trait FromMoveValue: Sized {
+    fn from_move_value(value: &Value) -> Result<Self>;
+}
+
+impl FromMoveValue for u64 {
+    fn from_move_value(value: &Value) -> Result<Self> {
+        let value = value.as_str().ok_or_else(|| anyhow!("expected a string"))?;
+        Ok(value.parse::<u64>()?)
+    }
+}
+
+impl FromMoveValue for u128 {
+    fn from_move_value(value: &Value) -> Result<Self> {
+        let value = value.as_str().ok_or_else(|| anyhow!("expected a string"))?;
+        Ok(value.parse::<u128>()?)
+    }
+}
+
+impl FromMoveValue for bool {
+    fn from_move_value(value: &Value) -> Result<Self> {
+        value.as_bool().ok_or_else(|| anyhow!("expected a bool"))
+    }
+}
+
+impl FromMoveValue for String {
+    fn from_move_value(value: &Value) -> Result<Self> {
+        let value = value.as_str().ok_or_else(|| anyhow!("expected a string"))?;
+        Ok(value.to_string())
+    }
+}
+
+impl FromMoveValue for Vec<u8> {
+    fn from_move_value(value: &Value) -> Result<Self> {
+        let value = value
+            .as_str()
+            .ok_or_else(|| anyhow!("expected an address"))?;
+        let mut address = parse_address(value)?;
+        address.reverse();
+        Ok(address)
+    }
+}
+
+impl FromMoveValue for Vec<u64> {
+    fn from_move_value(value: &Value) -> Result<Self> {
+        let values = value
+            .as_array()
+            .ok_or_else(|| anyhow!("expected an array"))?;
+        values.iter().map(u64::from_move_value).collect()
+    }
+}
+
+impl FromMoveValue for Vec<Vec<u8>> {
+    fn from_move_value(value: &Value) -> Result<Self> {
+        let values = value
+            .as_array()
+            .ok_or_else(|| anyhow!("expected an array"))?;
+        values.iter().map(Vec::<u8>::from_move_value).collect()
+    }
+}
+
+/// Parses `0x...` into the 32 bytes of an account address.
+pub fn parse_address(value: &str) -> Result<Vec<u8>> {
+    let digits = value
+        .strip_prefix("0x")
+        .ok_or_else(|| anyhow!("expected an address starting with 0x, got {}", value))?;
+    if digits.is_empty() || digits.len() > 64 {
+        bail!("address {} must have 1 to 64 hex digits", value);
+    }
+    hex::decode(format!("{:0>64}", digits)).with_context(|| format!("invalid address {}", value))
+}
+
+fn lookup<'a>(data: &'a MoveStructValue, path: &[&str]) -> Result<&'a Value> {
+    let (first, rest) = path
+        .split_first()
+        .ok_or_else(|| anyhow!("empty field path"))?;
+
+    let mut value = data
+        .0
+        .get(&IdentifierWrapper::from_str(first)?)
+        .ok_or_else(|| anyhow!("missing field {}", first))?;
+
+    for segment in rest {
+        value = value
+            .get(segment)
+            .ok_or_else(|| anyhow!("missing field {}", path.join(".")))?;
+    }
+
+    Ok(value)
+}
+
+/// Reads the field at `path` (nested struct fields are separate segments).
+pub fn field<T: FromMoveValue>(data: &MoveStructValue, path: &[&str]) -> Result<T> {
+    let value = lookup(data, path)?;
+    T::from_move_value(value).with_context(|| format!("invalid field {}", path.join(".")))
+}
+
+/// Reads the length of the vector at `path`.
+pub fn field_len(data: &MoveStructValue, path: &[&str]) -> Result<u64> {
+    let value = lookup(data, path)?;
+    let values = value
+        .as_array()
+        .ok_or_else(|| anyhow!("invalid field {}: expected an array", path.join(".")))?;
+    Ok(values.len() as u64)
+}
+
+/// Builds the arrow array for a column of values.
+pub trait Column {
+    fn to_array(&self, name: &str) -> Result<ArrayRef>;
+
+    /// Type of the arrays built by `to_array`.
+    fn data_type(name: &str) -> DataType;
+}
+
+impl Column for Vec<u64> {
+    fn to_array(&self, _name: &str) -> Result<ArrayRef> {
+        Ok(Arc::new(arrow_array::UInt64Array::from(self.clone())))
+    }
+
+    fn data_type(_name: &str) -> DataType {
+        DataType::UInt64
+    }
+}
+
+impl Column for Vec<u128> {
+    fn to_array(&self, _name: &str) -> Result<ArrayRef> {
+        let values = self.iter().map(|it| it.to_le_bytes());
+        Ok(Arc::new(FixedSizeBinaryArray::try_from_iter(values)?))
+    }
+
+    fn data_type(_name: &str) -> DataType {
+        DataType::FixedSizeBinary(16)
+    }
+}
+
+impl Column for Vec<bool> {
+    fn to_array(&self, _name: &str) -> Result<ArrayRef> {
+        Ok(Arc::new(arrow_array::BooleanArray::from(self.clone())))
+    }
+
+    fn data_type(_name: &str) -> DataType {
+        DataType::Boolean
+    }
+}
+
+impl Column for Vec<String> {
+    fn to_array(&self, _name: &str) -> Result<ArrayRef> {
+        Ok(Arc::new(arrow_array::StringArray::from(self.clone())))
+    }
+
+    fn data_type(_name: &str) -> DataType {
+        DataType::Utf8
+    }
+}
+
+impl Column for Vec<Vec<u8>> {
+    fn to_array(&self, name: &str) -> Result<ArrayRef> {
+        // Sized explicitly, so that an address of another width is an error.
+        let array = FixedSizeBinaryArray::try_from_sparse_iter_with_size(self.iter().map(Some), 32)
+            .with_context(|| format!("invalid addresses in column {}", name))?;
+        Ok(Arc::new(array))
+    }
+
+    fn data_type(_name: &str) -> DataType {
+        DataType::FixedSizeBinary(32)
+    }
+}
+
+/// Vectors of numbers are stored as a JSON string.
+impl Column for Vec<Vec<u64>> {
+    fn to_array(&self, _name: &str) -> Result<ArrayRef> {
+        let values = self
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Arc::new(arrow_array::StringArray::from(values)))
+    }
+
+    fn data_type(_name: &str) -> DataType {
+        DataType::Utf8
+    }
+}
+
+impl Column for Vec<Vec<Vec<u8>>> {
+    fn to_array(&self, name: &str) -> Result<ArrayRef> {
+        Ok(Arc::new(ListArray::from(create_array_data_2d(self, name))))
+    }
+
+    fn data_type(name: &str) -> DataType {
+        DataType::List(Arc::new(Field::new(name, DataType::LargeBinary, false)))
+    }
+}
+
+/// Collection of a `MoveResource`, keyed by version and change index.
+pub struct ResourceCollection<R: MoveResource> {
+    version: Vec<u64>,
+    change_index: Vec<u64>,
+    address: Vec<Vec<u8>>,
+    columns: R::Columns,
+}
+
+impl<R: MoveResource> ResourceCollection<R> {
+    pub fn new() -> ResourceCollection<R> {
+        ResourceCollection {
+            version: Vec::new(),
+            change_index: Vec::new(),
+            address: Vec::new(),
+            columns: R::Columns::default(),
+        }
+    }
+}
+
+impl<R: MoveResource> Default for ResourceCollection<R> {
+    fn default() -> ResourceCollection<R> {
+        ResourceCollection::new()
+    }
+}
+
+impl<R: MoveResource> Collection for ResourceCollection<R> {
+    fn name(&self) -> &'static str {
+        R::COLLECTION_NAME
+    }
+
+    fn schema_version(&self) -> u32 {
+        R::SCHEMA_VERSION
+    }
+
+    fn schema(&self) -> Schema {
+        let mut fields = vec![
+            Field::new("version", DataType::UInt64, false),
+            Field::new("change_index", DataType::UInt64, false),
+        ];
+        if R::WITH_ADDRESS {
+            fields.push(Field::new(
+                "address",
+                <Vec<Vec<u8>> as Column>::data_type("address"),
+                false,
+            ));
+        }
+        fields.extend(R::Columns::fields());
+
+        Schema::new(fields)
+    }
+
+    fn on_write_set_change(
+        &mut self,
+        version: u64,
+        change_index: u64,
+        change: &WriteSetChange,
+    ) -> Result<()> {
+        if let WriteSetChange::WriteResource(change) = change {
+            if !self.extracts_resource(&change.data.typ) {
+                return Ok(());
+            }
+
+            let mut address = change.address.inner().to_vec();
+            if R::ROOT_ONLY && address != ROOT_ACCOUNT_ADDRESS.inner() {
+                return Ok(());
+            }
+
+            let row = R::from_move_value(&change.data.data)
+                .with_context(|| format!("failed to parse {}", R::MOVE_TYPE))?;
+
+            self.version.push(version);
+            self.change_index.push(change_index);
+
+            if R::WITH_ADDRESS {
+                address.reverse();
+                self.address.push(address);
+            }
+
+            self.columns.push(row);
+        }
+
+        Ok(())
+    }
+
+    fn extracts_resource(&self, typ: &MoveStructTag) -> bool {
+        R::matches(typ)
+    }
+
+    fn take_batch(&mut self) -> Result<Option<RecordBatch>> {
+        if self.version.is_empty() {
+            return Ok(None);
+        }
+
+        let rows = std::mem::take(self);
+
+        let version = arrow_array::UInt64Array::from(rows.version);
+        let change_index = arrow_array::UInt64Array::from(rows.change_index);
+
+        let mut columns = vec![
+            ("version", Arc::new(version) as ArrayRef),
+            ("change_index", Arc::new(change_index) as ArrayRef),
+        ];
+        if R::WITH_ADDRESS {
+            columns.push(("address", rows.address.to_array("address")?));
+        }
+        columns.extend(rows.columns.to_arrays()?);
+
+        Ok(Some(RecordBatch::try_from_iter(columns)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_addresses_are_padded_to_32_bytes() {
+        let mut expected = vec![0; 32];
+        expected[31] = 1;
+        assert_eq!(parse_address("0x1").unwrap(), expected);
+
+        let long = format!("0x{}", "ab".repeat(32));
+        assert_eq!(parse_address(&long).unwrap(), vec![0xab; 32]);
+
+        assert!(parse_address("1").is_err());
+        assert!(parse_address("0x").is_err());
+        assert!(parse_address(&format!("{}00", long)).is_err());
+    }
+
+    #[test]
+    fn address_columns_mix_short_and_long_addresses() {
+        let values = ["0x1", "0xdead", &format!("0x{}", "12".repeat(32))];
+        let column = values
+            .iter()
+            .map(|it| Vec::<u8>::from_move_value(&Value::from(*it)))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+
+        let array = column.to_array("address").unwrap();
+        assert_eq!(array.len(), 3);
+        assert_eq!(array.data_type(), &DataType::FixedSizeBinary(32));
+    }
+
+    #[test]
+    fn address_columns_reject_other_sizes() {
+        let column: Vec<Vec<u8>> = vec![vec![0; 32], vec![0; 16]];
+        assert!(column.to_array("address").is_err());
+    }
+}
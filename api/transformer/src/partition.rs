@@ -0,0 +1,142 @@
+//! Splits collections into Hive-style partitions, e.g.
+//! `coin_balance/epoch=123/part-0.parquet`, so data lake tools and the `s3()`
+//! table function of ClickHouse can skip the files they don't need.
+//!
+//! Epochs and days are those of the block a version belongs to, from the
+//! `BlockMetadataTransaction`s read so far. Inputs are expected in version
+//! order: rows before the first block of the run can't be placed and go to
+//! the `__HIVE_DEFAULT_PARTITION__` partition.
+
+use std::{collections::BTreeMap, str::FromStr};
+
+use anyhow::{anyhow, bail, Result};
+use arrow::compute::take;
+use arrow_array::{cast::AsArray, types::UInt64Type, RecordBatch, UInt32Array};
+use chrono::DateTime;
+
+/// Hive's name for rows without a value for the partition key.
+pub const DEFAULT_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+
+/// How `--partition-by` splits the collections.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartitionBy {
+    /// `epoch={epoch}`.
+    Epoch,
+
+    /// `version_start={start}`, ranges of the given number of versions.
+    /// Named after the start of the range so it doesn't shadow the `version`
+    /// column.
+    Version(u64),
+
+    /// `day={yyyy-mm-dd}`, in UTC.
+    Day,
+}
+
+impl FromStr for PartitionBy {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<PartitionBy> {
+        match value {
+            "epoch" => Ok(PartitionBy::Epoch),
+            "day" => Ok(PartitionBy::Day),
+            _ => {
+                let Some(size) = value.strip_prefix("version:") else {
+                    bail!("expected epoch, version:<N> or day, got {}", value);
+                };
+                match size.parse::<u64>() {
+                    Ok(size) if size > 0 => Ok(PartitionBy::Version(size)),
+                    _ => bail!("invalid version range size {}", size),
+                }
+            }
+        }
+    }
+}
+
+/// A `BlockMetadataTransaction`: where a block starts, its epoch and its
+/// timestamp in microseconds.
+#[derive(Clone, Copy, Debug)]
+pub struct Block {
+    pub version: u64,
+    pub epoch: u64,
+    pub timestamp: u64,
+}
+
+pub struct Partitioner {
+    by: PartitionBy,
+
+    /// Epoch and timestamp of the blocks seen so far, by first version.
+    blocks: BTreeMap<u64, (u64, u64)>,
+}
+
+impl Partitioner {
+    pub fn new(by: PartitionBy) -> Partitioner {
+        Partitioner {
+            by,
+            blocks: BTreeMap::new(),
+        }
+    }
+
+    pub fn add_block(&mut self, block: &Block) {
+        if !matches!(self.by, PartitionBy::Version(_)) {
+            self.blocks
+                .insert(block.version, (block.epoch, block.timestamp));
+        }
+    }
+
+    /// Partition of the rows of `version`, e.g. `epoch=123`.
+    pub fn partition(&self, version: u64) -> String {
+        let block = self.blocks.range(..=version).next_back().map(|(_, it)| it);
+
+        match (self.by, block) {
+            (PartitionBy::Version(size), _) => {
+                format!("version_start={}", version / size * size)
+            }
+            (PartitionBy::Epoch, Some((epoch, _))) => format!("epoch={}", epoch),
+            (PartitionBy::Day, Some((_, timestamp))) => {
+                match DateTime::from_timestamp_micros(*timestamp as i64) {
+                    Some(time) => format!("day={}", time.format("%Y-%m-%d")),
+                    None => format!("day={}", DEFAULT_PARTITION),
+                }
+            }
+            (PartitionBy::Epoch, None) => format!("epoch={}", DEFAULT_PARTITION),
+            (PartitionBy::Day, None) => format!("day={}", DEFAULT_PARTITION),
+        }
+    }
+
+    /// Splits a batch by the partitions of its `version` column, keeping the
+    /// order of the rows. `None` for collections without versions, which
+    /// aren't partitioned.
+    pub fn split(&self, batch: &RecordBatch) -> Result<Option<Vec<(String, RecordBatch)>>> {
+        let Some(versions) = batch.column_by_name("version") else {
+            return Ok(None);
+        };
+        let versions = versions
+            .as_primitive_opt::<UInt64Type>()
+            .ok_or_else(|| anyhow!("version isn't a UInt64 column"))?;
+
+        let mut rows: Vec<(String, Vec<u32>)> = Vec::new();
+        for (index, version) in versions.values().iter().enumerate() {
+            let partition = self.partition(*version);
+            match rows.last_mut() {
+                Some((last, indices)) if *last == partition => indices.push(index as u32),
+                _ => match rows.iter_mut().find(|(it, _)| *it == partition) {
+                    Some((_, indices)) => indices.push(index as u32),
+                    None => rows.push((partition, vec![index as u32])),
+                },
+            }
+        }
+
+        let mut partitions = Vec::with_capacity(rows.len());
+        for (partition, indices) in rows {
+            let indices = UInt32Array::from(indices);
+            let columns = batch
+                .columns()
+                .iter()
+                .map(|column| take(column, &indices, None))
+                .collect::<Result<Vec<_>, _>>()?;
+            partitions.push((partition, RecordBatch::try_new(batch.schema(), columns)?));
+        }
+
+        Ok(Some(partitions))
+    }
+}
@@ -0,0 +1,493 @@
+//! Extraction: hands every transaction, and every write set change of it, to
+//! a set of collections.
+
+use diem_api_types::{MoveStructTag, Transaction, WriteSetChange};
+
+use crate::{
+    collection::Collection, constants::ROOT_ACCOUNT_ADDRESS, error::TransformError, metrics,
+};
+
+/*
+ * 0x1::tower_state::TowerList : XX
+ * 0x1::ol_account::BurnTracker: XX
+ * 0x1::burn::BurnCounter: XX
+ * 0x1::slow_wallet::SlowWallet: XX
+ * 0x1::pledge_accounts::BeneficiaryPolicy: XX
+ * 0x1::fee_maker::EpochFeeMakerRegistry: X
+ * 0x1::slow_wallet::SlowWalletList: X
+ * 0x1::tower_state::VDFDifficulty: X
+ * 0x1::proof_of_fee::ConsensusReward: X
+ * 0x1::epoch_boundary::BoundaryStatus: X
+ *
+ */
+
+/// Feeds the write set changes of the transaction at `version` to every
+/// collection. Errors are appended to `errors`, the other changes and
+/// collections aren't affected. Resources no collection extracts are counted
+/// in `metrics::UNKNOWN_RESOURCES`.
+pub fn process_changes(
+    collections: &mut [Box<dyn Collection>],
+    version: u64,
+    changes: &[WriteSetChange],
+    errors: &mut Vec<TransformError>,
+) {
+    for (change_index, change) in changes.iter().enumerate() {
+        let change_index = change_index as u64;
+
+        for collection in collections.iter_mut() {
+            if let Err(err) = collection.on_write_set_change(version, change_index, change) {
+                errors.push(
+                    TransformError::change(version, change_index, change, format!("{:#}", err))
+                        .with_collection(collection.name()),
+                );
+            }
+        }
+
+        if let WriteSetChange::WriteResource(resource) = change {
+            let typ = &resource.data.typ;
+            if !collections.iter().any(|it| it.extracts_resource(typ)) {
+                metrics::UNKNOWN_RESOURCES
+                    .with_label_values(&[&unknown_resource_module(typ)])
+                    .inc();
+            }
+        }
+    }
+}
+
+/// Label of `metrics::UNKNOWN_RESOURCES`: `0x1::module` for the modules of
+/// the framework, `other` for the modules published by accounts, which
+/// aren't a bounded set.
+fn unknown_resource_module(typ: &MoveStructTag) -> String {
+    if typ.address.inner().to_vec() == ROOT_ACCOUNT_ADDRESS.inner() {
+        format!("0x1::{}", typ.module)
+    } else {
+        "other".to_string()
+    }
+}
+
+/// Checks the transaction can be transformed at all. A failure here skips the
+/// transaction for every collection.
+pub fn check_transaction(transaction: &Transaction) -> Result<(), String> {
+    match transaction {
+        Transaction::PendingTransaction(_) => {}
+        Transaction::UserTransaction(user_transaction) => {
+            match &user_transaction.request.payload {
+                diem_api_types::TransactionPayload::EntryFunctionPayload(_) => {}
+                diem_api_types::TransactionPayload::ScriptPayload(_) => {}
+
+                // Deprecated. Will be removed in the future.
+                diem_api_types::TransactionPayload::ModuleBundlePayload(_) => {
+                    return Err("unsupported payload ModuleBundlePayload".to_string());
+                }
+                diem_api_types::TransactionPayload::MultisigPayload(_) => {
+                    return Err("unsupported payload MultisigPayload".to_string());
+                }
+            }
+        }
+        Transaction::GenesisTransaction(_) => {}
+        Transaction::BlockMetadataTransaction(block_metadata_transaction) => {
+            check_system_transaction(&block_metadata_transaction.info)?;
+        }
+        Transaction::StateCheckpointTransaction(state_checkpoint_transaction) => {
+            let info = &state_checkpoint_transaction.info;
+
+            check_system_transaction(info)?;
+            if !info.changes.is_empty() {
+                return Err(format!("unexpected {} changes", info.changes.len()));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn check_system_transaction(info: &diem_api_types::TransactionInfo) -> Result<(), String> {
+    if info.vm_status != "Executed successfully" {
+        return Err(format!("unexpected vm_status {:?}", info.vm_status));
+    }
+    if info.gas_used != diem_api_types::U64(0) {
+        return Err(format!("unexpected gas_used {}", info.gas_used.0));
+    }
+    if !info.success {
+        return Err("unexpected success false".to_string());
+    }
+    Ok(())
+}
+
+/// Version of a transaction, `None` for pending transactions.
+pub fn transaction_version(transaction: &Transaction) -> Option<u64> {
+    transaction_info(transaction).map(|info| info.version.into())
+}
+
+fn transaction_info(transaction: &Transaction) -> Option<&diem_api_types::TransactionInfo> {
+    match transaction {
+        Transaction::UserTransaction(user_transaction) => Some(&user_transaction.info),
+        Transaction::GenesisTransaction(genesis_transaction) => Some(&genesis_transaction.info),
+        Transaction::BlockMetadataTransaction(block_metadata_transaction) => {
+            Some(&block_metadata_transaction.info)
+        }
+        Transaction::StateCheckpointTransaction(state_checkpoint_transaction) => {
+            Some(&state_checkpoint_transaction.info)
+        }
+        Transaction::PendingTransaction(_) => None,
+    }
+}
+
+/// Runs one transaction through every collection. Pending transactions are
+/// ignored.
+pub fn process_transaction(
+    collections: &mut [Box<dyn Collection>],
+    transaction: &Transaction,
+    errors: &mut Vec<TransformError>,
+) {
+    let Some(info) = transaction_info(transaction) else {
+        return;
+    };
+    let version: u64 = info.version.into();
+    let _span = tracing::debug_span!("transaction", version).entered();
+
+    if let Err(reason) = check_transaction(transaction) {
+        errors.push(TransformError::transaction(version, reason));
+        return;
+    }
+
+    for collection in collections.iter_mut() {
+        if let Err(err) = collection.on_transaction(transaction) {
+            errors.push(
+                TransformError::transaction(version, format!("{:#}", err))
+                    .with_collection(collection.name()),
+            );
+        }
+    }
+
+    if let Transaction::StateCheckpointTransaction(_) = transaction {
+        return;
+    }
+
+    process_changes(collections, version, &info.changes, errors);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use arrow_array::{cast::AsArray, types::UInt64Type, RecordBatch};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{
+        constants::{LIBRA_COIN_HANDLE, LIBRA_COIN_KEY},
+        models, test_utils,
+    };
+
+    const ACCOUNT: &str = "0x8b8c0f7b8fa33edc1a8d5d0e53e8b0a3c7c8b8fd1d3d1e8f2f4e6a3c9e5d1a2b";
+
+    fn write_resource(address: &str, typ: &str, data: Value) -> WriteSetChange {
+        serde_json::from_value(test_utils::write_resource(address, typ, data)).unwrap()
+    }
+
+    fn delete_resource(address: &str, typ: &str) -> WriteSetChange {
+        serde_json::from_value(json!({
+            "type": "delete_resource",
+            "address": address,
+            "state_key_hash": "0x00",
+            "resource": typ,
+        }))
+        .unwrap()
+    }
+
+    /// Runs `process_changes` over the registry, returns the batch of every
+    /// collection that got rows.
+    fn process(
+        version: u64,
+        changes: Vec<WriteSetChange>,
+    ) -> (HashMap<String, RecordBatch>, Vec<TransformError>) {
+        let mut collections = models::registry();
+        let mut errors = Vec::new();
+        process_changes(&mut collections, version, &changes, &mut errors);
+
+        let batches = collections
+            .iter_mut()
+            .filter_map(|it| Some((it.name().to_string(), it.take_batch().unwrap()?)))
+            .collect();
+        (batches, errors)
+    }
+
+    fn u64_column(batch: &RecordBatch, name: &str) -> Vec<u64> {
+        let column = batch.column_by_name(name).unwrap();
+        column.as_primitive::<UInt64Type>().values().to_vec()
+    }
+
+    fn address_column(batch: &RecordBatch, name: &str) -> Vec<String> {
+        let column = batch.column_by_name(name).unwrap().as_fixed_size_binary();
+        column.iter().map(|it| hex::encode(it.unwrap())).collect()
+    }
+
+    #[test]
+    fn resources_go_to_their_collections() {
+        let changes = vec![
+            write_resource(
+                ACCOUNT,
+                "0x1::coin::CoinStore<0x1::libra_coin::LibraCoin>",
+                json!({ "coin": { "value": "1500" } }),
+            ),
+            write_resource(
+                ACCOUNT,
+                "0x1::slow_wallet::SlowWallet",
+                json!({ "unlocked": "10", "transferred": "3" }),
+            ),
+            write_resource(
+                "0x1",
+                "0x1::burn::BurnCounter",
+                json!({ "lifetime_burned": "7", "lifetime_recycled": "2" }),
+            ),
+        ];
+        let (batches, errors) = process(42, changes);
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        let mut names: Vec<&str> = batches.keys().map(|it| it.as_str()).collect();
+        names.sort();
+        assert_eq!(names, ["burn_counter", "coin_balance", "slow_wallet"]);
+
+        let coin_balance = &batches["coin_balance"];
+        assert_eq!(coin_balance.num_rows(), 1);
+        assert_eq!(u64_column(coin_balance, "version"), [42]);
+        assert_eq!(u64_column(coin_balance, "change_index"), [0]);
+        assert_eq!(u64_column(coin_balance, "balance"), [1500]);
+        let coin_name = coin_balance.column_by_name("coin_name").unwrap();
+        assert_eq!(coin_name.as_string::<i32>().value(0), "LibraCoin");
+
+        let slow_wallet = &batches["slow_wallet"];
+        assert_eq!(u64_column(slow_wallet, "change_index"), [1]);
+        assert_eq!(u64_column(slow_wallet, "unlocked"), [10]);
+        assert_eq!(u64_column(slow_wallet, "transferred"), [3]);
+        // Address bytes are stored reversed.
+        let mut address = hex::decode(ACCOUNT.trim_start_matches("0x")).unwrap();
+        address.reverse();
+        assert_eq!(
+            address_column(slow_wallet, "address"),
+            [hex::encode(address)]
+        );
+
+        assert_eq!(u64_column(&batches["burn_counter"], "change_index"), [2]);
+    }
+
+    #[test]
+    fn root_only_resources_ignore_other_accounts() {
+        let changes = vec![write_resource(
+            ACCOUNT,
+            "0x1::burn::BurnCounter",
+            json!({ "lifetime_burned": "7", "lifetime_recycled": "2" }),
+        )];
+        let (batches, errors) = process(1, changes);
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert!(batches.is_empty());
+    }
+
+    #[test]
+    fn invalid_changes_are_reported_without_affecting_the_others() {
+        let changes = vec![
+            write_resource(
+                ACCOUNT,
+                "0x1::slow_wallet::SlowWallet",
+                json!({ "unlocked": 10, "transferred": "3" }),
+            ),
+            delete_resource(ACCOUNT, "0x1::slow_wallet::SlowWallet"),
+            write_resource(
+                "0x1",
+                "0x1::slow_wallet::SlowWallet",
+                json!({ "unlocked": "4", "transferred": "0" }),
+            ),
+        ];
+        let (batches, errors) = process(9, changes);
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].version, 9);
+        assert_eq!(errors[0].change_index, Some(0));
+        assert_eq!(errors[0].collection.as_deref(), Some("slow_wallet"));
+
+        let slow_wallet = &batches["slow_wallet"];
+        assert_eq!(u64_column(slow_wallet, "change_index"), [2]);
+        assert_eq!(u64_column(slow_wallet, "unlocked"), [4]);
+    }
+
+    /// A user transaction of `ACCOUNT` at `version`, with one event.
+    fn user_transaction(version: u64, payload: Value) -> Value {
+        json!({
+            "type": "user_transaction",
+            "version": version.to_string(),
+            "hash": test_utils::HASH,
+            "state_change_hash": test_utils::HASH,
+            "event_root_hash": test_utils::HASH,
+            "state_checkpoint_hash": null,
+            "gas_used": "12",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": test_utils::HASH,
+            "changes": [],
+            "sender": ACCOUNT,
+            "sequence_number": "3",
+            "max_gas_amount": "1000",
+            "gas_unit_price": "100",
+            "expiration_timestamp_secs": "1700000060",
+            "payload": payload,
+            "signature": null,
+            "events": [{
+                "guid": { "creation_number": "2", "account_address": ACCOUNT },
+                "sequence_number": "0",
+                "type": "0x1::coin::WithdrawEvent",
+                "data": { "amount": "1" },
+            }],
+            "timestamp": (1_700_000_000_000_000 + version).to_string(),
+        })
+    }
+
+    /// Transactions giving at least one row to every collection of the
+    /// registry.
+    fn every_collection() -> Vec<Transaction> {
+        let root = |typ: &str, data: Value| test_utils::write_resource("0x1", typ, data);
+        let account = |typ: &str, data: Value| test_utils::write_resource(ACCOUNT, typ, data);
+        let changes = vec![
+            account("0x1::ancestry::Ancestry", json!({ "tree": [ACCOUNT] })),
+            account(
+                "0x1::coin::CoinStore<0x1::libra_coin::LibraCoin>",
+                json!({ "coin": { "value": "1500" } }),
+            ),
+            account(
+                "0x1::ol_account::BurnTracker",
+                json!({
+                    "burn_at_last_calc": "1",
+                    "cumu_burn": "2",
+                    "prev_balance": "3",
+                    "prev_supply": "4",
+                }),
+            ),
+            account(
+                "0x1::multisig_account::MultisigAccount",
+                json!({ "owners": [ACCOUNT] }),
+            ),
+            test_utils::slow_wallet(ACCOUNT, 10),
+            account(
+                "0x1::multi_action::Action<0x1::donor_voice_txs::Payment>",
+                json!({ "can_withdraw": false }),
+            ),
+            root(
+                "0x1::pledge_accounts::BeneficiaryPolicy",
+                json!({
+                    "lifetime_pledged": "1",
+                    "lifetime_withdrawn": "2",
+                    "amount_available": "3",
+                    "pledgers": [ACCOUNT],
+                }),
+            ),
+            root(
+                "0x1::epoch_boundary::BoundaryStatus",
+                json!({
+                    "incoming_fees": "1",
+                    "outgoing_nominal_reward_to_vals": "2",
+                    "outgoing_total_reward": "3",
+                    "system_fees_collected": "4",
+                }),
+            ),
+            root(
+                "0x1::burn::BurnCounter",
+                json!({ "lifetime_burned": "7", "lifetime_recycled": "2" }),
+            ),
+            root(
+                "0x1::proof_of_fee::ConsensusReward",
+                json!({
+                    "nominal_reward": "1",
+                    "net_reward": "2",
+                    "entry_fee": "3",
+                    "clearing_bid": "4",
+                    "median_win_bid": "5",
+                    "median_history": ["6", "7"],
+                }),
+            ),
+            root(
+                "0x1::fee_maker::EpochFeeMakerRegistry",
+                json!({ "epoch_fees_made": "1" }),
+            ),
+            root(
+                "0x1::slow_wallet::SlowWalletList",
+                json!({ "list": [ACCOUNT] }),
+            ),
+            root("0x1::tower_state::TowerList", json!({ "list": [ACCOUNT] })),
+            root(
+                "0x1::tower_state::VDFDifficulty",
+                json!({ "difficulty": "3000000" }),
+            ),
+            root("0x1::donor_voice::Registry", json!({ "list": [ACCOUNT] })),
+            json!({
+                "type": "write_table_item",
+                "state_key_hash": "0x00",
+                "handle": format!("0x{}", hex::encode(LIBRA_COIN_HANDLE.0.clone())),
+                "key": format!("0x{}", hex::encode(LIBRA_COIN_KEY.0.clone())),
+                "value": format!("0x{:032x}", 1_000_000_u128),
+            }),
+        ];
+
+        let mut genesis = test_utils::state_checkpoint(0);
+        genesis["type"] = json!("genesis_transaction");
+        genesis["payload"] = json!({
+            "type": "write_set_payload",
+            "write_set": { "type": "direct_write_set", "changes": [], "events": [] },
+        });
+        genesis["events"] = json!([]);
+
+        let transactions = vec![
+            genesis,
+            test_utils::block_metadata(1, changes),
+            user_transaction(
+                2,
+                json!({
+                    "type": "entry_function_payload",
+                    "function": "0x1::ol_account::transfer",
+                    "type_arguments": [],
+                    "arguments": [ACCOUNT, "1"],
+                }),
+            ),
+            user_transaction(
+                3,
+                json!({
+                    "type": "script_payload",
+                    "code": { "bytecode": "0xa11ceb0b" },
+                    "type_arguments": [],
+                    "arguments": [],
+                }),
+            ),
+            test_utils::state_checkpoint(4),
+        ];
+        transactions
+            .into_iter()
+            .map(|it| serde_json::from_value(it).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn batches_have_the_schema_of_their_collection() {
+        let mut collections = models::registry();
+        let mut errors = Vec::new();
+        for transaction in every_collection() {
+            process_transaction(&mut collections, &transaction, &mut errors);
+        }
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        for collection in collections.iter_mut() {
+            let name = collection.name().to_string();
+            let batch = collection.take_batch().unwrap();
+            let batch = batch.unwrap_or_else(|| panic!("no rows in {}", name));
+            assert_eq!(*batch.schema(), collection.schema(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn unknown_resources_are_labelled_by_framework_module() {
+        let framework: MoveStructTag = "0x1::stake::ValidatorConfig".parse().unwrap();
+        assert_eq!(unknown_resource_module(&framework), "0x1::stake");
+
+        let published: MoveStructTag = format!("{}::game::Score", ACCOUNT).parse().unwrap();
+        assert_eq!(unknown_resource_module(&published), "other");
+    }
+}
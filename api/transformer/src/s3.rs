@@ -0,0 +1,433 @@
+//! Uploads the output of a run to an S3-compatible bucket (AWS, MinIO...).
+//!
+//! The collection files are packed in a `{start}-{end}.tar.gz` archive, the
+//! layout `ol-parquet-producer.processor.ts` uploads and the ClickHouse
+//! ingestor reads: `{prefix}/{start}-{end}.tar.gz`, `parquets/` by default.
+
+use std::{
+    fs::File,
+    future::Future,
+    io::{BufWriter, Read},
+    path::Path,
+    sync::Arc,
+};
+
+use anyhow::{bail, Context, Result};
+use arrow_array::RecordBatch;
+use flate2::{write::GzEncoder, Compression};
+use object_store::{aws::AmazonS3Builder, path::Path as ObjectPath, ObjectStore, WriteMultipart};
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+use crate::{
+    error::TransformError,
+    fetch::{ARCHIVE_FILE_VERSIONS, ARCHIVE_VERSIONS},
+    manifest::Manifest,
+    sink::{FileSink, Sink, ERRORS, MANIFEST},
+};
+
+/// S3 requires every part of a multipart upload but the last to be at least
+/// 5 MiB.
+const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(about = None, long_about = None)]
+pub struct S3Options {
+    /// Upload the output to this bucket once written.
+    #[arg(long = "s3-bucket")]
+    pub bucket: Option<String>,
+
+    /// Endpoint of an S3-compatible service, e.g. `http://localhost:9000` for
+    /// MinIO. AWS when not set. Plain HTTP is only used for `http://`
+    /// endpoints.
+    #[arg(long = "s3-endpoint", env = "S3_ENDPOINT")]
+    pub endpoint: Option<String>,
+
+    #[arg(long = "s3-region", env = "S3_REGION", default_value = "us-east-1")]
+    pub region: String,
+
+    #[arg(long = "s3-access-key-id", env = "S3_ACCESS_KEY_ID")]
+    pub access_key_id: Option<String>,
+
+    #[arg(
+        long = "s3-secret-access-key",
+        env = "S3_SECRET_ACCESS_KEY",
+        hide_env_values = true
+    )]
+    pub secret_access_key: Option<String>,
+
+    /// Directory of the archives in the bucket.
+    #[arg(long = "s3-prefix", default_value = "parquets")]
+    pub prefix: String,
+
+    /// Name of the archive, `{start}-{end}`. Defaults to the name of the
+    /// input archive when there is only one (`transactions/{start}-{end}.tgz`),
+    /// otherwise to the versions requested with `--from-version` and
+    /// `--to-version`, or `--start` and `--limit` when fetching.
+    #[arg(long = "s3-name", value_name = "START-END")]
+    pub name: Option<String>,
+
+    /// Size of the parts of the multipart upload, in bytes.
+    #[arg(long = "s3-part-size", default_value_t = 16 * 1024 * 1024)]
+    pub part_size: usize,
+}
+
+impl S3Options {
+    /// Name of the archive produced from `inputs`: `--s3-name`, or the
+    /// `{start}-{end}` of the only input archive.
+    pub fn archive_name(&self, inputs: &[String]) -> Option<String> {
+        if self.name.is_some() {
+            return self.name.clone();
+        }
+
+        let [input] = inputs else {
+            return None;
+        };
+        let file_name = input.rsplit('/').next()?;
+        let stem = [".tgz", ".tar.gz", ".tzst", ".tar.zst", ".tar"]
+            .iter()
+            .find_map(|extension| file_name.strip_suffix(extension))?;
+
+        let (start, end) = stem.split_once('-')?;
+        if start.parse::<u64>().is_ok() && end.parse::<u64>().is_ok() {
+            Some(stem.to_string())
+        } else {
+            None
+        }
+    }
+
+    /// Name of the archive of the versions `first` to `last`: `--s3-name`,
+    /// `{first}-{first+9900}` when they're the 10 000 versions of a
+    /// `transactions/` archive, otherwise `{first}-{last}`.
+    pub fn range_archive_name(&self, first: u64, last: u64) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+
+        if first.is_multiple_of(ARCHIVE_VERSIONS) && last == first + ARCHIVE_VERSIONS - 1 {
+            format!("{}-{}", first, last + 1 - ARCHIVE_FILE_VERSIONS)
+        } else {
+            format!("{}-{}", first, last)
+        }
+    }
+}
+
+/// Writes the collections with a `FileSink`, then uploads them as a single
+/// archive with a multipart upload when the run finishes. The archive holds
+/// the collection files, `manifest.json` and `errors.ndjson` when items were
+/// skipped.
+pub struct S3Sink {
+    files: FileSink,
+    options: S3Options,
+    store: Arc<dyn ObjectStore>,
+    name: String,
+}
+
+impl S3Sink {
+    /// `name` is the `{start}-{end}` of the archive, see
+    /// `S3Options::archive_name`.
+    pub fn new(files: FileSink, options: &S3Options, name: String) -> Result<S3Sink> {
+        check_options(options)?;
+        let store = s3_builder(options).build()?;
+        S3Sink::with_store(files, options, name, Arc::new(store))
+    }
+
+    /// Uploads to `store` instead of the bucket of `options`.
+    pub fn with_store(
+        files: FileSink,
+        options: &S3Options,
+        name: String,
+        store: Arc<dyn ObjectStore>,
+    ) -> Result<S3Sink> {
+        check_options(options)?;
+        Ok(S3Sink {
+            files,
+            options: options.clone(),
+            store,
+            name,
+        })
+    }
+
+    /// See `FileSink::manifest`.
+    pub fn manifest(&self) -> Option<&Manifest> {
+        self.files.manifest()
+    }
+
+    /// Packs the collection files, the manifest and the skipped items in
+    /// `{dest}/{name}.tar.gz`.
+    fn archive(&self, name: &str) -> Result<String> {
+        let path = format!("{}/{}.tar.gz", self.files.dest(), name);
+        let file = File::create(&path).with_context(|| format!("failed to create {}", path))?;
+        let mut builder =
+            tar::Builder::new(GzEncoder::new(BufWriter::new(file), Compression::default()));
+
+        let mut file_names: Vec<String> = self.files.files().cloned().collect();
+        file_names.push(MANIFEST.to_string());
+        if Path::new(&format!("{}/{}", self.files.dest(), ERRORS)).exists() {
+            file_names.push(ERRORS.to_string());
+        }
+        for file_name in file_names {
+            builder
+                .append_path_with_name(format!("{}/{}", self.files.dest(), file_name), &file_name)
+                .with_context(|| format!("failed to add {} to {}", file_name, path))?;
+        }
+
+        builder
+            .into_inner()
+            .and_then(|encoder| encoder.finish())
+            .and_then(|mut writer| std::io::Write::flush(&mut writer))
+            .with_context(|| format!("failed to write {}", path))?;
+
+        Ok(path)
+    }
+
+    /// Streams `path` to `key` with a multipart upload.
+    fn upload(&self, path: &str, key: &str) -> Result<()> {
+        let part_size = self.options.part_size;
+        let key = ObjectPath::from(key);
+
+        block_on(async {
+            let mut file = File::open(path).with_context(|| format!("failed to open {}", path))?;
+            let mut writer = WriteMultipart::new_with_chunk_size(
+                self.store.put_multipart(&key).await?,
+                part_size,
+            );
+
+            let mut buffer = vec![0; part_size];
+            loop {
+                let read = file.read(&mut buffer)?;
+                if read == 0 {
+                    break;
+                }
+                writer.wait_for_capacity(4).await?;
+                writer.write(&buffer[..read]);
+            }
+
+            writer.finish().await?;
+            Ok(())
+        })
+    }
+}
+
+impl Sink for S3Sink {
+    fn start(&mut self, manifest: &Manifest) -> Result<()> {
+        self.files.start(manifest)
+    }
+
+    fn write(&mut self, collection: &str, batch: &RecordBatch) -> Result<()> {
+        self.files.write(collection, batch)
+    }
+
+    fn write_partition(
+        &mut self,
+        collection: &str,
+        partition: &str,
+        batch: &RecordBatch,
+    ) -> Result<()> {
+        self.files.write_partition(collection, partition, batch)
+    }
+
+    fn quarantine(&mut self, error: &TransformError) -> Result<()> {
+        self.files.quarantine(error)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.files.commit()
+    }
+
+    fn finish(&mut self, manifest: &Manifest) -> Result<()> {
+        self.files.finish(manifest)?;
+
+        let path = self.archive(&self.name)?;
+        let key = format!(
+            "{}/{}.tar.gz",
+            self.options.prefix.trim_end_matches('/'),
+            self.name
+        );
+        self.upload(&path, &key)
+            .with_context(|| format!("failed to upload {} to {}", path, key))?;
+        tracing::info!(
+            "uploaded s3://{}/{}",
+            self.options.bucket.as_deref().unwrap_or_default(),
+            key
+        );
+
+        Ok(())
+    }
+
+    /// Nothing is uploaded, the local files are closed like `FileSink` does.
+    fn abort(&mut self) -> Result<()> {
+        self.files.abort()
+    }
+}
+
+fn check_options(options: &S3Options) -> Result<()> {
+    if options.bucket.is_none() {
+        bail!("missing S3 bucket");
+    }
+    if options.part_size < MIN_PART_SIZE {
+        bail!("S3 parts must be at least {} bytes", MIN_PART_SIZE);
+    }
+    Ok(())
+}
+
+fn s3_builder(options: &S3Options) -> AmazonS3Builder {
+    let mut builder = AmazonS3Builder::new()
+        .with_bucket_name(options.bucket.clone().unwrap_or_default())
+        .with_region(&options.region);
+    if let Some(endpoint) = &options.endpoint {
+        builder = builder
+            .with_endpoint(endpoint)
+            .with_allow_http(endpoint.starts_with("http://"));
+    }
+    if let Some(access_key_id) = &options.access_key_id {
+        builder = builder.with_access_key_id(access_key_id);
+    }
+    if let Some(secret_access_key) = &options.secret_access_key {
+        builder = builder.with_secret_access_key(secret_access_key);
+    }
+    builder
+}
+
+/// Runs `future` from a sink, which is synchronous. The transformer calls
+/// sinks through `block_in_place`, so the handle of its runtime can block.
+fn block_on<T>(future: impl Future<Output = Result<T>>) -> Result<T> {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(|| handle.block_on(future))
+        }
+        Ok(_) => bail!("uploads need a multi-threaded runtime"),
+        Err(_) => tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(future),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use clap::Parser;
+    use flate2::read::GzDecoder;
+    use object_store::{aws::AmazonS3ConfigKey, memory::InMemory, ClientConfigKey};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{
+        test_utils::{
+            block, block_metadata, documents, parquet_options, slow_wallet, state_checkpoint,
+            temp_dir, write_resource,
+        },
+        writer::OutputFormat,
+        OnError, Transformer,
+    };
+
+    #[derive(Parser)]
+    struct Options {
+        #[command(flatten)]
+        s3: S3Options,
+    }
+
+    fn options(args: &[&str]) -> S3Options {
+        Options::parse_from(["test", "--s3-bucket", "bucket"].iter().chain(args)).s3
+    }
+
+    /// Runs `transactions`, versions 1 to 3, through an `S3Sink` uploading to
+    /// memory. Returns the names of the files in the archive.
+    async fn upload(test: &str, transactions: Vec<Value>, on_error: OnError) -> Vec<String> {
+        let store = Arc::new(InMemory::new());
+        let files =
+            FileSink::new(&temp_dir(test), OutputFormat::Parquet, &parquet_options()).unwrap();
+        let options = options(&[]);
+        let name = options.range_archive_name(1, 3);
+        let sink = S3Sink::with_store(files, &options, name, store.clone()).unwrap();
+
+        let (documents, input) = documents(vec![transactions]);
+        Transformer::builder()
+            .documents(documents, input)
+            .collections(["slow_wallet"])
+            .on_error(on_error)
+            .sink(sink)
+            .build()
+            .unwrap()
+            .run()
+            .await
+            .unwrap();
+
+        // Named after the versions requested, not the ones written: slow
+        // wallets are only at 1 and 2.
+        let archive = store
+            .get(&ObjectPath::from("parquets/1-3.tar.gz"))
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        let mut archive = tar::Archive::new(GzDecoder::new(archive.as_ref()));
+        let mut names = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            names.push(entry.path().unwrap().to_string_lossy().to_string());
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content).unwrap();
+            assert!(!content.is_empty());
+        }
+        names
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn archives_are_uploaded() {
+        let names = upload("s3-upload", block(1, 3), OnError::Fail).await;
+        assert_eq!(names, ["slow_wallet.parquet", MANIFEST]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn skipped_items_are_uploaded() {
+        let invalid = write_resource(
+            "0x1",
+            "0x1::slow_wallet::SlowWallet",
+            json!({ "unlocked": "many", "transferred": "0" }),
+        );
+        let transactions = vec![
+            block_metadata(1, vec![slow_wallet("0x101", 1)]),
+            block_metadata(2, vec![invalid]),
+            state_checkpoint(3),
+        ];
+        let names = upload("s3-skipped", transactions, OnError::Skip).await;
+        assert_eq!(names, ["slow_wallet.parquet", MANIFEST, ERRORS]);
+    }
+
+    #[test]
+    fn archives_are_named_after_the_requested_versions() {
+        let s3 = options(&[]);
+        let inputs = ["transactions/10000-19900.tgz".to_string()];
+        assert_eq!(s3.archive_name(&inputs).as_deref(), Some("10000-19900"));
+        assert_eq!(s3.archive_name(&["1.json".to_string()]), None);
+
+        // The layout of the `transactions/` archives, otherwise the range.
+        assert_eq!(s3.range_archive_name(10_000, 19_999), "10000-19900");
+        assert_eq!(s3.range_archive_name(10_000, 10_099), "10000-10099");
+        assert_eq!(s3.range_archive_name(5, 9_999), "5-9999");
+
+        let named = options(&["--s3-name", "0-9900"]);
+        assert_eq!(named.archive_name(&inputs).as_deref(), Some("0-9900"));
+        assert_eq!(named.range_archive_name(1, 3), "0-9900");
+    }
+
+    #[test]
+    fn plain_http_only_for_http_endpoints() {
+        let allow_http = |args: &[&str]| {
+            s3_builder(&options(args))
+                .get_config_value(&AmazonS3ConfigKey::Client(ClientConfigKey::AllowHttp))
+        };
+        assert_eq!(allow_http(&[]).as_deref(), Some("false"));
+        assert_eq!(
+            allow_http(&["--s3-endpoint", "https://s3.example.com"]).as_deref(),
+            Some("false")
+        );
+        assert_eq!(
+            allow_http(&["--s3-endpoint", "http://localhost:9000"]).as_deref(),
+            Some("true")
+        );
+    }
+}
@@ -0,0 +1,596 @@
+//! `transformer serve`: the transformer as an HTTP service, so the API can
+//! call it over localhost instead of spawning a process per file and reading
+//! its output from disk.
+//!
+//! - `POST /transform`: transactions in, a tar archive of what the command
+//!   line writes out (collection files, `manifest.json`, `errors.ndjson`).
+//! - `POST /transform/rows`: the same rows as JSON, by collection.
+//! - `GET /collections`: the collections and their columns.
+//! - `GET /health`
+//! - `GET /metrics`: see `metrics`, counted over every request.
+//!
+//! The OpenAPI spec is served at `/spec`, with a Swagger UI at `/docs`.
+//!
+//! Bodies over `--max-body-bytes` are refused with 413 before they're read.
+//! Transactions that can't be transformed get a 422, failures of the
+//! transformer itself a 500. Files and CPU bound work run on blocking threads,
+//! so a request doesn't hold up the others.
+
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use anyhow::{Context, Result};
+use arrow::json::ArrayWriter;
+use poem::{
+    get, http::Method, listener::TcpListener, middleware::SizeLimit, Endpoint, EndpointExt,
+    IntoResponse, Middleware, Route, Server,
+};
+use poem_openapi::{
+    param::Query,
+    payload::{Binary, Json, PlainText},
+    ApiRequest, ApiResponse, Enum, Object, OpenApi, OpenApiService,
+};
+
+use crate::{
+    collection::Collection,
+    ddl,
+    error::{OnError, TransformError},
+    input::Document,
+    manifest::ChecksumReader,
+    mapping::MappingConfig,
+    metrics, models,
+    sink::{FileSink, MemorySink, ERRORS, MANIFEST},
+    transformer::{Transformer, TransformerBuilder},
+    writer::{to_text, OutputFormat, ParquetOptions},
+};
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(about = None, long_about = None)]
+pub struct ServeOptions {
+    /// Address to listen on.
+    #[arg(long, default_value = "127.0.0.1:8090")]
+    pub listen: String,
+
+    /// Number of documents transformed concurrently, per request.
+    #[arg(long, default_value_t = 1)]
+    pub jobs: usize,
+
+    /// Largest request body accepted, in bytes.
+    #[arg(long, default_value_t = 64 * 1024 * 1024)]
+    pub max_body_bytes: usize,
+
+    #[command(flatten)]
+    pub parquet: ParquetOptions,
+}
+
+/// Serves the API until the process is stopped. `mapping` adds its
+/// collections to every request.
+pub async fn serve(options: ServeOptions, mapping: Option<MappingConfig>) -> Result<()> {
+    let api = Api::new(options.clone(), mapping)?;
+
+    tracing::info!("listening on http://{}", options.listen);
+    Server::new(TcpListener::bind(options.listen.as_str()))
+        .run(app(api))
+        .await
+        .context("server failed")
+}
+
+/// The routes of `serve`.
+fn app(api: Api) -> impl Endpoint {
+    let max_body_bytes = api.options.max_body_bytes;
+    let server = format!("http://{}", api.options.listen);
+    let service = OpenApiService::new(api, "transformer", env!("CARGO_PKG_VERSION")).server(server);
+    Route::new()
+        .nest("/docs", service.swagger_ui())
+        .at("/spec", service.spec_endpoint())
+        .at("/metrics", get(metrics::endpoint))
+        .nest("/", service)
+        .around(move |endpoint, request| async move {
+            if request.method() == Method::POST {
+                let limited = SizeLimit::new(max_body_bytes).transform(endpoint);
+                Ok(limited.call(request).await?.into_response())
+            } else {
+                Ok(endpoint.call(request).await?.into_response())
+            }
+        })
+}
+
+/// File format of the collections in the archive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Enum)]
+#[oai(rename_all = "lowercase")]
+enum Format {
+    Parquet,
+    Arrow,
+    Csv,
+    Ndjson,
+}
+
+impl From<Format> for OutputFormat {
+    fn from(format: Format) -> OutputFormat {
+        match format {
+            Format::Parquet => OutputFormat::Parquet,
+            Format::Arrow => OutputFormat::Arrow,
+            Format::Csv => OutputFormat::Csv,
+            Format::Ndjson => OutputFormat::Ndjson,
+        }
+    }
+}
+
+/// See `OnError`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Enum)]
+#[oai(rename_all = "lowercase")]
+enum ErrorMode {
+    Fail,
+    Skip,
+}
+
+/// Transactions, as returned by `/v1/transactions`. Kept as bytes, they are
+/// deserialized one at a time like the other inputs.
+#[derive(ApiRequest)]
+enum Transactions {
+    /// A transaction or an array of transactions.
+    #[oai(content_type = "application/json")]
+    Json(Binary<Vec<u8>>),
+}
+
+impl Transactions {
+    fn into_bytes(self) -> Vec<u8> {
+        match self {
+            Transactions::Json(body) => body.0,
+        }
+    }
+}
+
+#[derive(ApiResponse)]
+enum ArchiveResponse {
+    /// A tar archive of the collection files, `manifest.json` and
+    /// `errors.ndjson` when items were skipped.
+    #[oai(status = 200, content_type = "application/x-tar")]
+    Ok(Binary<Vec<u8>>),
+
+    /// Invalid parameters, e.g. an unknown collection.
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+
+    /// The transactions couldn't be transformed.
+    #[oai(status = 422)]
+    Unprocessable(PlainText<String>),
+
+    /// The transformer or its output failed.
+    #[oai(status = 500)]
+    InternalError(PlainText<String>),
+}
+
+impl ArchiveResponse {
+    fn error(err: anyhow::Error) -> ArchiveResponse {
+        let message = PlainText(format!("{:#}", err));
+        match is_invalid_input(&err) {
+            true => ArchiveResponse::Unprocessable(message),
+            false => ArchiveResponse::InternalError(message),
+        }
+    }
+}
+
+#[derive(ApiResponse)]
+enum RowsResponse {
+    #[oai(status = 200)]
+    Ok(Json<Rows>),
+
+    /// Invalid parameters, e.g. an unknown collection.
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+
+    /// The transactions couldn't be transformed.
+    #[oai(status = 422)]
+    Unprocessable(PlainText<String>),
+
+    /// The transformer or its output failed.
+    #[oai(status = 500)]
+    InternalError(PlainText<String>),
+}
+
+impl RowsResponse {
+    fn error(err: anyhow::Error) -> RowsResponse {
+        let message = PlainText(format!("{:#}", err));
+        match is_invalid_input(&err) {
+            true => RowsResponse::Unprocessable(message),
+            false => RowsResponse::InternalError(message),
+        }
+    }
+}
+
+#[derive(Object)]
+struct Rows {
+    /// Rows of every collection with rows, binary columns as `0x` hex.
+    collections: BTreeMap<String, Vec<serde_json::Value>>,
+
+    /// Items left out with `on_error=skip`.
+    errors: Vec<serde_json::Value>,
+
+    /// See `manifest.json`.
+    manifest: serde_json::Value,
+}
+
+#[derive(Clone, Object)]
+struct CollectionInfo {
+    name: String,
+    schema_version: u32,
+    columns: Vec<ColumnInfo>,
+}
+
+#[derive(Clone, Object)]
+struct ColumnInfo {
+    name: String,
+
+    /// Arrow type.
+    data_type: String,
+
+    /// Type of the column in the ClickHouse table, see `transformer schema`.
+    clickhouse_type: Option<String>,
+
+    nullable: bool,
+}
+
+#[derive(Object)]
+struct Health {
+    status: String,
+
+    /// Version of the transformer.
+    version: String,
+}
+
+struct Api {
+    options: ServeOptions,
+    mapping: Option<MappingConfig>,
+
+    /// Served by `/collections`, they don't change while the process runs.
+    collections: Vec<CollectionInfo>,
+}
+
+impl Api {
+    fn new(options: ServeOptions, mapping: Option<MappingConfig>) -> Result<Api> {
+        let mut collections = models::registry();
+        if let Some(mapping) = &mapping {
+            collections.extend(mapping.collections()?);
+        }
+        Ok(Api {
+            options,
+            mapping,
+            collections: collections
+                .iter()
+                .map(|it| collection_info(&**it))
+                .collect(),
+        })
+    }
+
+    /// A transformer for the transactions of a request body.
+    async fn builder(
+        &self,
+        body: Vec<u8>,
+        only: Option<String>,
+        on_error: Option<ErrorMode>,
+        verify: Option<bool>,
+    ) -> Result<TransformerBuilder<()>> {
+        let (body, input) = tokio::task::spawn_blocking(move || {
+            let input = ChecksumReader::new(body.as_slice()).finish("request")?;
+            anyhow::Ok((body, input))
+        })
+        .await??;
+        let mut builder = Transformer::builder()
+            .documents(
+                vec![Document::from_bytes("request".to_string(), body)],
+                input,
+            )
+            .jobs(self.options.jobs)
+            .on_error(match on_error {
+                Some(ErrorMode::Skip) => OnError::Skip,
+                Some(ErrorMode::Fail) | None => OnError::Fail,
+            })
+            .verify(verify.unwrap_or(false));
+
+        if let Some(mapping) = &self.mapping {
+            builder = builder.mapping(mapping.clone());
+        }
+        if let Some(only) = only {
+            builder = builder.collections(only.split(',').map(|it| it.trim().to_string()));
+        }
+        Ok(builder)
+    }
+}
+
+#[OpenApi]
+impl Api {
+    /// Transforms transactions into collection files
+    #[oai(path = "/transform", method = "post")]
+    async fn transform(
+        &self,
+        /// File format of the collections.
+        format: Query<Option<Format>>,
+        /// Only produce these collections, comma separated.
+        only: Query<Option<String>>,
+        /// What to do with transactions that can't be transformed.
+        on_error: Query<Option<ErrorMode>>,
+        /// Recompute the hashes of block metadata and state checkpoint
+        /// transactions, see `--verify`.
+        verify: Query<Option<bool>>,
+        body: Transactions,
+    ) -> ArchiveResponse {
+        let builder = self
+            .builder(body.into_bytes(), only.0, on_error.0, verify.0)
+            .await;
+        let dest = temporary_dir();
+        let response = self.archive(&dest, builder, format.0).await;
+
+        let removed = dest.clone();
+        let removed = tokio::task::spawn_blocking(move || std::fs::remove_dir_all(&removed)).await;
+        if let Ok(Err(err)) = removed {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!("failed to remove {}: {}", dest, err);
+            }
+        }
+        response
+    }
+
+    /// Transforms transactions into JSON rows
+    #[oai(path = "/transform/rows", method = "post")]
+    async fn transform_rows(
+        &self,
+        /// Only produce these collections, comma separated.
+        only: Query<Option<String>>,
+        /// What to do with transactions that can't be transformed.
+        on_error: Query<Option<ErrorMode>>,
+        /// Recompute the hashes of block metadata and state checkpoint
+        /// transactions, see `--verify`.
+        verify: Query<Option<bool>>,
+        body: Transactions,
+    ) -> RowsResponse {
+        let transformer = match self
+            .builder(body.into_bytes(), only.0, on_error.0, verify.0)
+            .await
+            .and_then(|it| it.sink(MemorySink::new()).build())
+        {
+            Ok(transformer) => transformer,
+            Err(err) => return RowsResponse::BadRequest(PlainText(format!("{:#}", err))),
+        };
+        let sink = match transformer.run().await {
+            Ok(sink) => sink,
+            Err(err) => return RowsResponse::error(err),
+        };
+
+        match tokio::task::spawn_blocking(move || rows(sink)).await {
+            Ok(Ok(rows)) => RowsResponse::Ok(Json(rows)),
+            Ok(Err(err)) => RowsResponse::InternalError(PlainText(format!("{:#}", err))),
+            Err(err) => RowsResponse::InternalError(PlainText(err.to_string())),
+        }
+    }
+
+    /// Lists the collections and their columns
+    #[oai(path = "/collections", method = "get")]
+    async fn collections(&self) -> Json<Vec<CollectionInfo>> {
+        Json(self.collections.clone())
+    }
+
+    /// Checks the service is up
+    #[oai(path = "/health", method = "get")]
+    async fn health(&self) -> Json<Health> {
+        Json(Health {
+            status: "ok".to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        })
+    }
+}
+
+impl Api {
+    /// Writes the collections to `dest` with a `FileSink`, then packs them.
+    async fn archive(
+        &self,
+        dest: &str,
+        builder: Result<TransformerBuilder<()>>,
+        format: Option<Format>,
+    ) -> ArchiveResponse {
+        let format = format
+            .map(OutputFormat::from)
+            .unwrap_or(OutputFormat::Parquet);
+        let transformer = match builder.and_then(|builder| {
+            let sink = FileSink::new(dest, format, &self.options.parquet)?;
+            builder.sink(sink).build()
+        }) {
+            Ok(transformer) => transformer,
+            Err(err) => return ArchiveResponse::BadRequest(PlainText(format!("{:#}", err))),
+        };
+
+        let sink = match transformer.run().await {
+            Ok(sink) => sink,
+            Err(err) => return ArchiveResponse::error(err),
+        };
+        match tokio::task::spawn_blocking(move || pack(&sink)).await {
+            Ok(Ok(archive)) => ArchiveResponse::Ok(Binary(archive)),
+            Ok(Err(err)) => ArchiveResponse::InternalError(PlainText(format!("{:#}", err))),
+            Err(err) => ArchiveResponse::InternalError(PlainText(err.to_string())),
+        }
+    }
+}
+
+/// Whether `err` comes from the transactions of the request, rather than
+/// from the transformer or its sink.
+fn is_invalid_input(err: &anyhow::Error) -> bool {
+    err.chain()
+        .any(|it| it.is::<TransformError>() || it.is::<serde_json::Error>())
+}
+
+/// A tar archive of the files written by `sink`.
+fn pack(sink: &FileSink) -> Result<Vec<u8>> {
+    let mut builder = tar::Builder::new(Vec::new());
+
+    let file_names = sink
+        .files()
+        .cloned()
+        .chain([MANIFEST.to_string(), ERRORS.to_string()]);
+    for file_name in file_names {
+        let path = format!("{}/{}", sink.dest(), file_name);
+        if !std::path::Path::new(&path).exists() {
+            continue;
+        }
+        builder
+            .append_path_with_name(&path, &file_name)
+            .with_context(|| format!("failed to add {} to the archive", file_name))?;
+    }
+
+    Ok(builder.into_inner()?)
+}
+
+/// The batches of `sink` as JSON rows.
+fn rows(sink: MemorySink) -> Result<Rows> {
+    let mut collections = BTreeMap::new();
+    for (name, batches) in sink.batches.iter() {
+        let mut writer = ArrayWriter::new(Vec::new());
+        for batch in batches.iter() {
+            writer.write(&to_text(batch, false)?)?;
+        }
+        writer.finish()?;
+        let rows: Vec<serde_json::Value> = serde_json::from_slice(&writer.into_inner())?;
+        collections.insert(name.clone(), rows);
+    }
+
+    Ok(Rows {
+        collections,
+        errors: sink
+            .errors
+            .iter()
+            .map(serde_json::to_value)
+            .collect::<Result<_, _>>()?,
+        manifest: serde_json::to_value(&sink.manifest)?,
+    })
+}
+
+/// A directory of its own for the files of a request.
+fn temporary_dir() -> String {
+    static REQUESTS: AtomicU64 = AtomicU64::new(0);
+    std::env::temp_dir()
+        .join(format!(
+            "transformer-{}-{}",
+            std::process::id(),
+            REQUESTS.fetch_add(1, Ordering::Relaxed)
+        ))
+        .to_string_lossy()
+        .to_string()
+}
+
+fn collection_info(collection: &dyn Collection) -> CollectionInfo {
+    CollectionInfo {
+        name: collection.name().to_string(),
+        schema_version: collection.schema_version(),
+        columns: collection
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| ColumnInfo {
+                name: field.name().to_string(),
+                data_type: field.data_type().to_string(),
+                clickhouse_type: ddl::clickhouse_type(field).ok(),
+                nullable: field.is_nullable(),
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use clap::Parser;
+    use poem::{http::StatusCode, test::TestClient};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::test_utils::block;
+
+    #[derive(Parser)]
+    struct Options {
+        #[command(flatten)]
+        serve: ServeOptions,
+    }
+
+    fn client(args: &[&str]) -> TestClient<impl Endpoint> {
+        let options = Options::parse_from(["test"].iter().chain(args)).serve;
+        TestClient::new(app(Api::new(options, None).unwrap()))
+    }
+
+    async fn post(client: &TestClient<impl Endpoint>, uri: &str, body: Vec<u8>) -> poem::Response {
+        client
+            .post(uri)
+            .content_type("application/json")
+            .header("content-length", body.len())
+            .body(body)
+            .send()
+            .await
+            .0
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn transactions_are_returned_as_rows() {
+        let client = client(&[]);
+        let body = serde_json::to_vec(&block(1, 3)).unwrap();
+        let response = post(&client, "/transform/rows?only=slow_wallet", body).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let rows: Value =
+            serde_json::from_slice(&response.into_body().into_vec().await.unwrap()).unwrap();
+        let versions: Vec<&Value> = rows["collections"]["slow_wallet"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|it| &it["version"])
+            .collect();
+        assert_eq!(versions, [&json!(1), &json!(2)]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn transactions_are_returned_as_an_archive() {
+        let client = client(&[]);
+        let body = serde_json::to_vec(&block(1, 3)).unwrap();
+        let response = post(&client, "/transform?only=slow_wallet", body).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let archive = response.into_body().into_vec().await.unwrap();
+        let mut archive = tar::Archive::new(archive.as_slice());
+        let names: Vec<String> = archive
+            .entries()
+            .unwrap()
+            .map(|it| it.unwrap().path().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, ["slow_wallet.parquet", MANIFEST]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn invalid_transactions_are_unprocessable() {
+        let client = client(&[]);
+        let body = br#"[{"type": "unknown_transaction"}]"#.to_vec();
+        let response = post(&client, "/transform/rows", body.clone()).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let response = post(&client, "/transform", body).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn large_bodies_are_refused() {
+        let client = client(&["--max-body-bytes", "16"]);
+        let body = serde_json::to_vec(&block(1, 3)).unwrap();
+        let response = post(&client, "/transform/rows", body).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        client.get("/health").send().await.assert_status_is_ok();
+    }
+
+    #[test]
+    fn only_input_errors_are_unprocessable() {
+        let invalid = anyhow::Error::new(TransformError::transaction(1, "bad".to_string()))
+            .context("failed to transform request");
+        assert!(is_invalid_input(&invalid));
+
+        let failed = anyhow!("No space left on device").context("failed to write slow_wallet");
+        assert!(!is_invalid_input(&failed));
+    }
+}
@@ -0,0 +1,525 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    path::Path,
+};
+
+use anyhow::{bail, Context, Result};
+use arrow_array::RecordBatch;
+use parquet::file::properties::WriterProperties;
+
+use crate::{
+    error::{Quarantine, TransformError},
+    manifest::{range, Manifest, ABORTED_KEY, SCHEMA_VERSION_KEY},
+    metrics,
+    writer::{read_file_metadata, CollectionWriter, OutputFormat, ParquetOptions},
+};
+
+/// Name of the manifest written by `FileSink`.
+pub const MANIFEST: &str = "manifest.json";
+
+/// Name of the file of skipped items, see `Quarantine`.
+pub const ERRORS: &str = "errors.ndjson";
+
+/// Where the transformer sends its output.
+///
+/// Each batch of a collection is sorted by `(version, change_index)`, batches
+/// arrive in the order of the inputs and aren't sorted across each other.
+/// `start` is called once before the first batch and `finish` once after the
+/// last one, with the manifest of the run, or `abort` when the run fails.
+///
+/// Implementations: `FileSink`, `MemorySink`, `ClickhouseSink` and `S3Sink`.
+pub trait Sink {
+    /// Called once before the first batch, with an empty manifest listing the
+    /// collections of the run.
+    fn start(&mut self, _manifest: &Manifest) -> Result<()> {
+        Ok(())
+    }
+
+    /// Appends rows to the collection `collection`.
+    fn write(&mut self, collection: &str, batch: &RecordBatch) -> Result<()>;
+
+    /// Appends rows to a partition of a collection (e.g. `epoch=123`), with
+    /// `--partition-by`. Sinks without a layout for partitions write them
+    /// like any other rows.
+    fn write_partition(
+        &mut self,
+        collection: &str,
+        _partition: &str,
+        batch: &RecordBatch,
+    ) -> Result<()> {
+        self.write(collection, batch)
+    }
+
+    /// Records an item left out of the output with `OnError::Skip`.
+    fn quarantine(&mut self, error: &TransformError) -> Result<()>;
+
+    /// Called once every batch of a chunk of an input document was written,
+    /// chunks holding whole transactions. Sinks that load a database can record
+    /// what was ingested so far.
+    fn commit(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn finish(&mut self, manifest: &Manifest) -> Result<()>;
+
+    /// Called instead of `finish` when the run fails after `start`. What was
+    /// written must be left readable, but not recorded as a complete run.
+    fn abort(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes every collection to `{dest}/{collection}.{extension}` in the chosen
+/// format, and skipped items to `{dest}/errors.ndjson`. Files are only created
+/// for collections with rows, `{dest}/manifest.json` lists all of them.
+///
+/// Partitions go to `{dest}/{collection}/{partition}/part-{n}.{extension}`,
+/// `n` being the first number not taken, so that runs writing to the same
+/// partition don't overwrite each other. Versions arrive in order, so only
+/// the last partition of a collection is kept open: the file of a partition
+/// is closed once the collection moves on to the next one (a partition seen
+/// again gets a new part).
+pub struct FileSink {
+    dest: String,
+    format: OutputFormat,
+    properties: WriterProperties,
+    max_row_group_bytes: usize,
+    require_schema: bool,
+
+    /// The manifest given to `start`, for the metadata of the files closed
+    /// before `finish`.
+    started: Option<Manifest>,
+
+    /// Open files, by collection file name or `{collection}/{partition}`.
+    writers: HashMap<String, OpenFile>,
+
+    /// Key in `writers` of the partition open for every collection.
+    open_partitions: HashMap<String, String>,
+
+    /// Files created for every collection, relative to `dest`.
+    files: BTreeMap<String, Vec<String>>,
+    quarantine: Option<Quarantine>,
+
+    /// The manifest written, once finished.
+    manifest: Option<Manifest>,
+}
+
+struct OpenFile {
+    collection: String,
+    writer: CollectionWriter,
+
+    /// First and last versions written to the file.
+    versions: Option<(u64, u64)>,
+}
+
+impl FileSink {
+    /// `options` are only used by `OutputFormat::Parquet`.
+    pub fn new(dest: &str, format: OutputFormat, options: &ParquetOptions) -> Result<FileSink> {
+        let properties = options.writer_properties()?;
+        std::fs::create_dir_all(dest).with_context(|| format!("failed to create {}", dest))?;
+
+        Ok(FileSink {
+            dest: dest.to_string(),
+            format,
+            properties,
+            max_row_group_bytes: options.row_group_bytes,
+            require_schema: false,
+            started: None,
+            writers: HashMap::new(),
+            open_partitions: HashMap::new(),
+            files: BTreeMap::new(),
+            quarantine: Some(Quarantine::new(format!("{}/{}", dest, ERRORS))),
+            manifest: None,
+        })
+    }
+
+    /// Refuses to start when `dest` holds parquet files of a collection
+    /// written with another schema version (or by a build that didn't record
+    /// it), so an output never mixes incompatible files. The check runs
+    /// before any file is created or truncated, `{collection}.parquet`
+    /// included.
+    pub fn require_schema(mut self, require_schema: bool) -> FileSink {
+        self.require_schema = require_schema;
+        self
+    }
+
+    pub fn dest(&self) -> &str {
+        &self.dest
+    }
+
+    /// Name of the file of a collection, in `dest`.
+    pub fn file_name(&self, collection: &str) -> String {
+        format!("{}.{}", collection, self.format.extension())
+    }
+
+    /// Files created so far, relative to `dest`.
+    pub fn files(&self) -> impl Iterator<Item = &String> {
+        self.files.values().flatten()
+    }
+
+    /// The manifest written to `{dest}/manifest.json`, once finished.
+    pub fn manifest(&self) -> Option<&Manifest> {
+        self.manifest.as_ref()
+    }
+
+    /// Parquet files of `collection` already in `dest`, partitioned or not.
+    fn existing_parquet_files(&self, collection: &str) -> Result<Vec<String>> {
+        let mut files = Vec::new();
+
+        let path = format!("{}/{}.parquet", self.dest, collection);
+        if Path::new(&path).exists() {
+            files.push(path);
+        }
+
+        let dir = format!("{}/{}", self.dest, collection);
+        if !Path::new(&dir).is_dir() {
+            return Ok(files);
+        }
+        for partition in std::fs::read_dir(&dir)? {
+            let partition = partition?.path();
+            if !partition.is_dir() {
+                continue;
+            }
+            for file in std::fs::read_dir(&partition)? {
+                let file = file?.path();
+                if file.extension().and_then(|it| it.to_str()) == Some("parquet") {
+                    files.push(file.to_string_lossy().to_string());
+                }
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Appends to the file `key` stands for, opening `path` (relative to
+    /// `dest`) if it isn't yet.
+    fn append(
+        &mut self,
+        collection: &str,
+        key: String,
+        path: impl FnOnce(&FileSink) -> Result<String>,
+        batch: &RecordBatch,
+    ) -> Result<()> {
+        if !self.writers.contains_key(&key) {
+            let path = path(self)?;
+            let writer = CollectionWriter::new(
+                format!("{}/{}", self.dest, path),
+                self.format,
+                self.properties.clone(),
+                self.max_row_group_bytes,
+            );
+            self.files
+                .entry(collection.to_string())
+                .or_default()
+                .push(path);
+            let file = OpenFile {
+                collection: collection.to_string(),
+                writer,
+                versions: None,
+            };
+            self.writers.insert(key.clone(), file);
+        }
+
+        let file = self.writers.get_mut(&key).unwrap();
+        file.writer.write(batch)?;
+        if let Some((low, high)) = range(batch, "version")? {
+            file.versions = Some(match file.versions {
+                Some((first, last)) => (first.min(low), last.max(high)),
+                None => (low, high),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Closes a file before `finish`, with the metadata of the manifest given
+    /// to `start`. Files of an aborted run are marked as such.
+    fn close(&self, file: OpenFile, aborted: bool) -> Result<()> {
+        let mut metadata = match &self.started {
+            Some(manifest) => manifest.file_metadata(&file.collection, file.versions),
+            None => Vec::new(),
+        };
+        if aborted {
+            metadata.push((ABORTED_KEY.to_string(), true.to_string()));
+        }
+        file.writer.close(metadata)
+    }
+
+    /// Fails when the parquet file `path` of `collection` doesn't have the
+    /// schema version of this run.
+    fn check_schema(&self, collection: &str, path: &str) -> Result<()> {
+        let expected = self
+            .started
+            .as_ref()
+            .and_then(|it| it.collections.get(collection))
+            .map(|it| it.schema_version.to_string())
+            .unwrap_or_default();
+        let metadata = read_file_metadata(path)?;
+        let found = metadata.get(SCHEMA_VERSION_KEY);
+        if found != Some(&expected) {
+            bail!(
+                "refusing to mix schema versions of {}: {} has schema version {}, this build writes {}",
+                collection,
+                path,
+                found.map(|it| it.as_str()).unwrap_or("none"),
+                expected
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Sink for FileSink {
+    fn start(&mut self, manifest: &Manifest) -> Result<()> {
+        self.started = Some(manifest.clone());
+        if !self.require_schema {
+            return Ok(());
+        }
+
+        for collection in manifest.collections.keys() {
+            for path in self.existing_parquet_files(collection)? {
+                self.check_schema(collection, &path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, collection: &str, batch: &RecordBatch) -> Result<()> {
+        let file_name = self.file_name(collection);
+        self.append(collection, file_name.clone(), |_| Ok(file_name), batch)
+    }
+
+    fn write_partition(
+        &mut self,
+        collection: &str,
+        partition: &str,
+        batch: &RecordBatch,
+    ) -> Result<()> {
+        let dir = format!("{}/{}", collection, partition);
+        let previous = self
+            .open_partitions
+            .insert(collection.to_string(), dir.clone());
+        if let Some(previous) = previous.filter(|it| *it != dir) {
+            if let Some(file) = self.writers.remove(&previous) {
+                self.close(file, false)?;
+            }
+        }
+
+        let path = |sink: &FileSink| {
+            let full = format!("{}/{}", sink.dest, dir);
+            std::fs::create_dir_all(&full).with_context(|| format!("failed to create {}", full))?;
+            let extension = sink.format.extension();
+            let part = (0..)
+                .find(|n| !Path::new(&format!("{}/part-{}.{}", full, n, extension)).exists())
+                .unwrap();
+            Ok(format!("{}/part-{}.{}", dir, part, extension))
+        };
+        self.append(collection, dir.clone(), path, batch)
+    }
+
+    fn quarantine(&mut self, error: &TransformError) -> Result<()> {
+        match &mut self.quarantine {
+            Some(quarantine) => quarantine.write(error),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(&mut self, manifest: &Manifest) -> Result<()> {
+        for (_, file) in self.writers.drain() {
+            let metadata = manifest.file_metadata(&file.collection, file.versions);
+            file.writer.close(metadata)?;
+        }
+
+        let mut manifest = manifest.clone();
+        for (collection, entry) in manifest.collections.iter_mut() {
+            let file_name = self.file_name(collection);
+            for file in self.files.get(collection).into_iter().flatten() {
+                let path = format!("{}/{}", self.dest, file);
+                let bytes = std::fs::metadata(&path)
+                    .with_context(|| format!("failed to read {}", path))?
+                    .len();
+                metrics::BYTES_WRITTEN
+                    .with_label_values(&[collection])
+                    .inc_by(bytes);
+
+                if *file == file_name {
+                    entry.file = Some(file.clone());
+                } else {
+                    entry.partitions.push(file.clone());
+                }
+            }
+        }
+        let path = format!("{}/{}", self.dest, MANIFEST);
+        let file = File::create(&path).with_context(|| format!("failed to create {}", path))?;
+        serde_json::to_writer_pretty(file, &manifest)
+            .with_context(|| format!("failed to write {}", path))?;
+
+        if let Some(quarantine) = self.quarantine.take() {
+            quarantine.close()?;
+        }
+        self.manifest = Some(manifest);
+
+        Ok(())
+    }
+
+    /// Closes the open files so they stay readable, without `manifest.json`
+    /// and marked with `ABORTED_KEY`.
+    fn abort(&mut self) -> Result<()> {
+        for (_, file) in std::mem::take(&mut self.writers) {
+            self.close(file, true)?;
+        }
+        if let Some(quarantine) = self.quarantine.take() {
+            quarantine.close()?;
+        }
+        Ok(())
+    }
+}
+
+/// Keeps everything in memory, for callers that use the rows in-process.
+#[derive(Default)]
+pub struct MemorySink {
+    pub batches: BTreeMap<String, Vec<RecordBatch>>,
+    pub errors: Vec<TransformError>,
+
+    /// Set once the run is finished.
+    pub manifest: Option<Manifest>,
+}
+
+impl MemorySink {
+    pub fn new() -> MemorySink {
+        MemorySink::default()
+    }
+}
+
+impl Sink for MemorySink {
+    fn write(&mut self, collection: &str, batch: &RecordBatch) -> Result<()> {
+        self.batches
+            .entry(collection.to_string())
+            .or_default()
+            .push(batch.clone());
+        Ok(())
+    }
+
+    fn quarantine(&mut self, error: &TransformError) -> Result<()> {
+        self.errors.push(error.clone());
+        Ok(())
+    }
+
+    fn finish(&mut self, manifest: &Manifest) -> Result<()> {
+        self.manifest = Some(manifest.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::UInt64Array;
+
+    use super::*;
+    use crate::test_utils::{parquet_options, temp_dir};
+
+    fn batch(versions: &[u64]) -> RecordBatch {
+        let version = Arc::new(UInt64Array::from(versions.to_vec()));
+        RecordBatch::try_from_iter([("version", version as _)]).unwrap()
+    }
+
+    fn file_sink(dest: &str) -> FileSink {
+        FileSink::new(dest, OutputFormat::Parquet, &parquet_options())
+            .unwrap()
+            .require_schema(true)
+    }
+
+    /// Writes `{dest}/slow_wallet.parquet` and a partition of it with
+    /// schema version 1.
+    fn write_version_1(dest: &str) {
+        let manifest = Manifest::new([("slow_wallet".to_string(), 1)]);
+        let mut sink = file_sink(dest);
+        sink.start(&manifest).unwrap();
+        sink.write("slow_wallet", &batch(&[1, 2])).unwrap();
+        sink.write_partition("slow_wallet", "epoch=1", &batch(&[3]))
+            .unwrap();
+        sink.finish(&manifest).unwrap();
+    }
+
+    #[test]
+    fn require_schema_refuses_other_versions_before_truncating() {
+        let dest = temp_dir("require_schema");
+        write_version_1(&dest);
+        let path = format!("{}/slow_wallet.parquet", dest);
+        let before = std::fs::read(&path).unwrap();
+
+        let err = file_sink(&dest)
+            .start(&Manifest::new([("slow_wallet".to_string(), 2)]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("slow_wallet"), "{}", err);
+        assert!(
+            err.contains("schema version 1, this build writes 2"),
+            "{}",
+            err
+        );
+        assert_eq!(std::fs::read(&path).unwrap(), before);
+
+        // The same version is fine, and the file gets replaced.
+        let manifest = Manifest::new([("slow_wallet".to_string(), 1)]);
+        let mut sink = file_sink(&dest);
+        sink.start(&manifest).unwrap();
+        sink.write("slow_wallet", &batch(&[9])).unwrap();
+        sink.finish(&manifest).unwrap();
+        let metadata = read_file_metadata(&path).unwrap();
+        assert_eq!(metadata[crate::manifest::MIN_VERSION_KEY], "9");
+    }
+
+    #[test]
+    fn partitions_are_closed_once_passed() {
+        let dest = temp_dir("partitions_closed");
+        let manifest = Manifest::new([("slow_wallet".to_string(), 1)]);
+        let mut sink = file_sink(&dest);
+        sink.start(&manifest).unwrap();
+        for (partition, versions) in [
+            ("epoch=1", [1, 2]),
+            ("epoch=1", [3, 4]),
+            ("epoch=2", [5, 6]),
+        ] {
+            sink.write_partition("slow_wallet", partition, &batch(&versions))
+                .unwrap();
+        }
+
+        // epoch=1 is complete and readable while epoch=2 is being written.
+        assert_eq!(sink.writers.len(), 1);
+        let path = format!("{}/slow_wallet/epoch=1/part-0.parquet", dest);
+        let metadata = read_file_metadata(&path).unwrap();
+        assert_eq!(metadata[crate::manifest::MAX_VERSION_KEY], "4");
+        assert!(!metadata.contains_key(ABORTED_KEY));
+
+        // Coming back to a closed partition adds a part.
+        sink.write_partition("slow_wallet", "epoch=1", &batch(&[7]))
+            .unwrap();
+        sink.finish(&manifest).unwrap();
+        let files: Vec<&String> = sink.files().collect();
+        assert_eq!(
+            files,
+            [
+                "slow_wallet/epoch=1/part-0.parquet",
+                "slow_wallet/epoch=2/part-0.parquet",
+                "slow_wallet/epoch=1/part-1.parquet",
+            ]
+        );
+    }
+
+    #[test]
+    fn require_schema_checks_partitions() {
+        let dest = temp_dir("require_schema_partitions");
+        write_version_1(&dest);
+        std::fs::remove_file(format!("{}/slow_wallet.parquet", dest)).unwrap();
+
+        let err = file_sink(&dest)
+            .start(&Manifest::new([("slow_wallet".to_string(), 2)]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("epoch=1/part-0.parquet"), "{}", err);
+    }
+}
@@ -0,0 +1,275 @@
+//! Fixtures shared by the unit tests: transactions in the JSON of the node
+//! API, scratch directories, and an HTTP server standing in for a node or for
+//! ClickHouse.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    sync::{Arc, Mutex},
+};
+
+use clap::Parser;
+use serde_json::{json, Value};
+
+use crate::{
+    input::Document,
+    manifest::{ChecksumReader, InputManifest},
+    writer::ParquetOptions,
+};
+
+pub const HASH: &str = "0x1111111111111111111111111111111111111111111111111111111111111111";
+
+pub fn write_resource(address: &str, typ: &str, data: Value) -> Value {
+    json!({
+        "type": "write_resource",
+        "address": address,
+        "state_key_hash": "0x00",
+        "data": { "type": typ, "data": data },
+    })
+}
+
+/// A `0x1::slow_wallet::SlowWallet` of `address`, one row of `slow_wallet`.
+pub fn slow_wallet(address: &str, unlocked: u64) -> Value {
+    write_resource(
+        address,
+        "0x1::slow_wallet::SlowWallet",
+        json!({ "unlocked": unlocked.to_string(), "transferred": "0" }),
+    )
+}
+
+pub fn block_metadata(version: u64, changes: Vec<Value>) -> Value {
+    json!({
+        "type": "block_metadata_transaction",
+        "version": version.to_string(),
+        "hash": HASH,
+        "state_change_hash": HASH,
+        "event_root_hash": HASH,
+        "state_checkpoint_hash": null,
+        "gas_used": "0",
+        "success": true,
+        "vm_status": "Executed successfully",
+        "accumulator_root_hash": HASH,
+        "changes": changes,
+        "id": format!("0x{:064x}", version),
+        "epoch": "1",
+        "round": version.to_string(),
+        "events": [],
+        "previous_block_votes_bitvec": [],
+        "proposer": "0x5",
+        "failed_proposer_indices": [],
+        "timestamp": (1_700_000_000_000_000 + version).to_string(),
+    })
+}
+
+pub fn state_checkpoint(version: u64) -> Value {
+    json!({
+        "type": "state_checkpoint_transaction",
+        "version": version.to_string(),
+        "hash": HASH,
+        "state_change_hash": HASH,
+        "event_root_hash": HASH,
+        "state_checkpoint_hash": HASH,
+        "gas_used": "0",
+        "success": true,
+        "vm_status": "Executed successfully",
+        "accumulator_root_hash": HASH,
+        "changes": [],
+        "timestamp": (1_700_000_000_000_000 + version).to_string(),
+    })
+}
+
+/// A block from `first` to `last`, writing a slow wallet at every version.
+pub fn block(first: u64, last: u64) -> Vec<Value> {
+    let mut transactions = vec![block_metadata(
+        first,
+        vec![slow_wallet(&format!("0x{:x}", first + 100), first)],
+    )];
+    for version in first + 1..last {
+        transactions.push(block_metadata(
+            version,
+            vec![slow_wallet(&format!("0x{:x}", version + 100), version)],
+        ));
+    }
+    transactions.push(state_checkpoint(last));
+    transactions
+}
+
+/// The documents of `Transformer::documents`, one per array of transactions.
+pub fn documents(transactions: Vec<Vec<Value>>) -> (Vec<Document>, InputManifest) {
+    let mut bytes = Vec::new();
+    let documents = transactions
+        .into_iter()
+        .enumerate()
+        .map(|(index, transactions)| {
+            let content = serde_json::to_vec(&transactions).unwrap();
+            bytes.extend_from_slice(&content);
+            Document::from_bytes(format!("document-{}", index), content)
+        })
+        .collect();
+    let manifest = ChecksumReader::new(bytes.as_slice())
+        .finish("test")
+        .unwrap();
+    (documents, manifest)
+}
+
+/// An empty directory of its own for `test`.
+pub fn temp_dir(test: &str) -> String {
+    let dir =
+        std::env::temp_dir().join(format!("transformer-test-{}-{}", std::process::id(), test));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.to_string_lossy().to_string()
+}
+
+#[derive(Parser)]
+struct Options {
+    #[command(flatten)]
+    parquet: ParquetOptions,
+}
+
+/// The defaults of the command line.
+pub fn parquet_options() -> ParquetOptions {
+    Options::parse_from(["test"]).parquet
+}
+
+/// A request received by `MockServer`.
+#[derive(Clone, Debug)]
+pub struct Request {
+    /// Path and query string, still encoded.
+    pub target: String,
+    pub body: String,
+}
+
+impl Request {
+    pub fn path(&self) -> &str {
+        self.target.split('?').next().unwrap_or_default()
+    }
+
+    /// A parameter of the query string, still encoded.
+    pub fn query(&self, name: &str) -> Option<&str> {
+        let (_, query) = self.target.split_once('?')?;
+        query
+            .split('&')
+            .filter_map(|it| it.split_once('='))
+            .find_map(|(key, value)| (key == name).then_some(value))
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Response {
+    pub fn new(status: u16, body: impl Into<String>) -> Response {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: impl ToString) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// An HTTP server on a thread of its own, answering every request with
+/// `handler`. Requests are kept to be checked by the test.
+pub struct MockServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl MockServer {
+    pub fn start(mut handler: impl FnMut(&Request) -> Response + Send + 'static) -> MockServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let received = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let target = line.split(' ').nth(1).unwrap_or_default().to_string();
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                let request = Request {
+                    target,
+                    body: String::from_utf8_lossy(&body).to_string(),
+                };
+                let response = handler(&request);
+                received.lock().unwrap().push(request);
+
+                let mut head = format!(
+                    "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n",
+                    response.status,
+                    response.body.len()
+                );
+                for (name, value) in response.headers.iter() {
+                    head.push_str(&format!("{}: {}\r\n", name, value));
+                }
+                let _ = write!(stream, "{}\r\n{}", head, response.body);
+            }
+        });
+
+        MockServer { url, requests }
+    }
+
+    /// Answers with `responses` in turn, then with 200.
+    pub fn with_responses(responses: Vec<(u16, &'static str)>) -> MockServer {
+        let mut responses = responses.into_iter();
+        MockServer::start(move |_| {
+            let (status, body) = responses.next().unwrap_or((200, ""));
+            Response::new(status, body)
+        })
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// A node at `ledger`, pruned before `oldest`, whose first `failures`
+/// pages are answered with 503. Transactions only hold their version.
+pub fn node(ledger: u64, oldest: u64, mut failures: u32) -> MockServer {
+    MockServer::start(move |request| {
+        if request.path() == "/v1" {
+            let info = json!({ "ledger_version": ledger.to_string() });
+            return Response::new(200, info.to_string());
+        }
+        if failures > 0 {
+            failures -= 1;
+            return Response::new(503, "busy").header("Retry-After", 0);
+        }
+
+        let start: u64 = request.query("start").unwrap().parse().unwrap();
+        let limit: u64 = request.query("limit").unwrap().parse().unwrap();
+        let transactions: Vec<Value> = (start..(start + limit).min(ledger + 1))
+            .map(|version| json!({ "version": version.to_string() }))
+            .collect();
+        Response::new(200, Value::from(transactions).to_string())
+            .header("X-Diem-Ledger-Version", ledger)
+            .header("X-Diem-Ledger-Oldest-Version", oldest)
+    })
+}
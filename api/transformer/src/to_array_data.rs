@@ -21,9 +21,9 @@ impl ToArrayData for Vec<Vec<u8>> {
         let mut i = 0;
         while i < self.len() {
             let len = self[i].len() as i32;
-            total_len = total_len + len;
+            total_len += len;
             offsets.push(total_len);
-            i = i + 1;
+            i += 1;
         }
 
         let mut values: Vec<u8> = Vec::with_capacity(total_len as usize);
@@ -32,17 +32,15 @@ impl ToArrayData for Vec<Vec<u8>> {
             let mut it = self[i].clone();
             it.reverse();
             values.extend(&it);
-            i = i + 1;
+            i += 1;
         }
 
-        let array_data = ArrayData::builder(DataType::Binary)
+        ArrayData::builder(DataType::Binary)
             .len(self.len())
             .add_buffer(Buffer::from_slice_ref(offsets))
             .add_buffer(Buffer::from_slice_ref(values))
             .build()
-            .unwrap();
-
-        return array_data;
+            .unwrap()
     }
 }
 
@@ -56,9 +54,9 @@ impl ToArrayData for Vec<Vec<u32>> {
         let mut i = 0;
         while i < self.len() {
             let len = self[i].len() as i32;
-            total_len = total_len + len;
+            total_len += len;
             offsets.push(total_len);
-            i = i + 1;
+            i += 1;
         }
 
         let mut values: Vec<u32> = Vec::with_capacity(total_len as usize);
@@ -67,17 +65,15 @@ impl ToArrayData for Vec<Vec<u32>> {
             let mut it = self[i].clone();
             it.reverse();
             values.extend(&it);
-            i = i + 1;
+            i += 1;
         }
 
-        let array_data = ArrayData::builder(DataType::Binary)
+        ArrayData::builder(DataType::Binary)
             .len(self.len())
             .add_buffer(Buffer::from_slice_ref(offsets))
             .add_buffer(Buffer::from_slice_ref(values))
             .build()
-            .unwrap();
-
-        return array_data;
+            .unwrap()
     }
 }
 
@@ -91,9 +87,9 @@ impl ToArrayData for Vec<Vec<u64>> {
         let mut i = 0;
         while i < self.len() {
             let len = self[i].len() as i32;
-            total_len = total_len + len;
+            total_len += len;
             offsets.push(total_len);
-            i = i + 1;
+            i += 1;
         }
 
         let mut values: Vec<u64> = Vec::with_capacity(total_len as usize);
@@ -102,21 +98,19 @@ impl ToArrayData for Vec<Vec<u64>> {
             let mut it = self[i].clone();
             it.reverse();
             values.extend(&it);
-            i = i + 1;
+            i += 1;
         }
 
-        let array_data = ArrayData::builder(DataType::Binary)
+        ArrayData::builder(DataType::Binary)
             .len(self.len())
             .add_buffer(Buffer::from_slice_ref(offsets))
             .add_buffer(Buffer::from_slice_ref(values))
             .build()
-            .unwrap();
-
-        return array_data;
+            .unwrap()
     }
 }
 
-pub fn create_array_data_2d(list: &Vec<Vec<Vec<u8>>>, field_name: &str) -> ArrayData {
+pub fn create_array_data_2d(list: &[Vec<Vec<u8>>], field_name: &str) -> ArrayData {
     let mut values = Vec::<Vec<u8>>::from([]);
     let mut offsets = Vec::from([0i32]);
     let mut offset = 0i32;
@@ -126,18 +120,18 @@ pub fn create_array_data_2d(list: &Vec<Vec<Vec<u8>>>, field_name: &str) -> Array
         let mut j = 0;
         while j < list[i].len() {
             values.push(list[i][j].clone());
-            j = j + 1;
+            j += 1;
         }
 
         offset += list[i].len() as i32;
         offsets.push(offset);
 
-        i = i + 1;
+        i += 1;
     }
 
     let child = LargeBinaryArray::from_iter_values(values.iter());
 
-    let array_data = ArrayDataBuilder::new(DataType::List(Arc::new(Field::new(
+    ArrayDataBuilder::new(DataType::List(Arc::new(Field::new(
         field_name,
         DataType::LargeBinary,
         false,
@@ -146,7 +140,5 @@ pub fn create_array_data_2d(list: &Vec<Vec<Vec<u8>>>, field_name: &str) -> Array
     .len(list.len())
     .add_buffer(Buffer::from(offsets.to_byte_slice()))
     .build()
-    .unwrap();
-
-    return array_data;
+    .unwrap()
 }
@@ -0,0 +1,829 @@
+use std::{ops::RangeInclusive, sync::Arc, time::Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use arrow_array::RecordBatch;
+use diem_api_types::Transaction;
+use futures::StreamExt;
+use tokio::{runtime::RuntimeFlavor, sync::mpsc::Sender};
+
+use crate::{
+    collection::Collection,
+    error::{OnError, TransformError},
+    fetch::{FetchOptions, Fetcher},
+    input::{read_documents, Document},
+    manifest::{InputManifest, Manifest},
+    mapping::{MappedCollection, MappingConfig},
+    metrics, models,
+    partition::{Block, PartitionBy, Partitioner},
+    process::{process_transaction, transaction_version},
+    sink::Sink,
+    verify::{Verifier, VerifyReport},
+    versions::{format_ranges, VersionCheck, VersionRange, VersionTracker},
+    writer::{sort_by_version, BATCH_SIZE},
+};
+
+/// Where the documents of a run come from.
+enum Source {
+    File(String),
+    Node(FetchOptions),
+
+    /// Documents already read, with the manifest of their input.
+    Documents(Vec<Document>, InputManifest),
+}
+
+impl Source {
+    /// Name of the source in the traces.
+    fn name(&self) -> &str {
+        match self {
+            Source::File(path) => path,
+            Source::Node(options) => &options.node,
+            Source::Documents(_, manifest) => &manifest.path,
+        }
+    }
+
+    /// Path of the source in the manifest, see `Manifest::input_paths`.
+    fn path(&self) -> String {
+        match self {
+            Source::Node(options) => Fetcher::new(options)
+                .map(|it| it.name())
+                .unwrap_or_else(|_| options.node.clone()),
+            _ => self.name().to_string(),
+        }
+    }
+}
+
+/// Builds a fresh set of collections. Every worker gets its own.
+type CollectionFactory = Arc<dyn Fn() -> Vec<Box<dyn Collection>> + Send + Sync>;
+
+/// Transforms transaction files into collections and sends them to a sink.
+/// Inputs can be JSON files, compressed or not, or tar archives of them (see
+/// `input`).
+///
+/// ```ignore
+/// let sink = Transformer::builder()
+///     .inputs(files)
+///     .collections(["coin_balance", "total_supply"])
+///     .sink(MemorySink::new())
+///     .build()?
+///     .run()
+///     .await?;
+/// ```
+pub struct Transformer<S> {
+    sources: Vec<Source>,
+    factory: CollectionFactory,
+    jobs: usize,
+    on_error: OnError,
+    partition_by: Option<PartitionBy>,
+    from_version: Option<u64>,
+    to_version: Option<u64>,
+    check_versions: VersionCheck,
+    verify: bool,
+    sink: S,
+}
+
+pub struct TransformerBuilder<S> {
+    sources: Vec<Source>,
+    mapping: Option<MappingConfig>,
+    collections: Option<Vec<String>>,
+    excluded: Vec<String>,
+    jobs: usize,
+    on_error: OnError,
+    partition_by: Option<PartitionBy>,
+    from_version: Option<u64>,
+    to_version: Option<u64>,
+    check_versions: VersionCheck,
+    verify: bool,
+    sink: S,
+}
+
+impl Transformer<()> {
+    pub fn builder() -> TransformerBuilder<()> {
+        TransformerBuilder {
+            sources: Vec::new(),
+            mapping: None,
+            collections: None,
+            excluded: Vec::new(),
+            jobs: default_jobs(),
+            on_error: OnError::Fail,
+            partition_by: None,
+            from_version: None,
+            to_version: None,
+            check_versions: VersionCheck::Warn,
+            verify: false,
+            sink: (),
+        }
+    }
+}
+
+/// Number of CPUs, the default for `TransformerBuilder::jobs`.
+pub fn default_jobs() -> usize {
+    std::thread::available_parallelism()
+        .map(|it| it.get())
+        .unwrap_or(1)
+}
+
+impl<S> TransformerBuilder<S> {
+    pub fn input(mut self, input: impl Into<String>) -> Self {
+        self.sources.push(Source::File(input.into()));
+        self
+    }
+
+    pub fn inputs<I: IntoIterator<Item = T>, T: Into<String>>(mut self, inputs: I) -> Self {
+        self.sources
+            .extend(inputs.into_iter().map(|it| Source::File(it.into())));
+        self
+    }
+
+    /// Fetches transactions from a node, see `fetch`.
+    pub fn fetch(mut self, options: FetchOptions) -> Self {
+        self.sources.push(Source::Node(options));
+        self
+    }
+
+    /// Transforms documents already read, e.g. by `follow::Follower`.
+    /// `manifest` describes their input.
+    pub fn documents(mut self, documents: Vec<Document>, manifest: InputManifest) -> Self {
+        self.sources.push(Source::Documents(documents, manifest));
+        self
+    }
+
+    /// Adds the collections described by a mapping file.
+    pub fn mapping(mut self, mapping: MappingConfig) -> Self {
+        self.mapping = Some(mapping);
+        self
+    }
+
+    /// Only produces the given collections. Every collection by default.
+    pub fn collections<I: IntoIterator<Item = T>, T: Into<String>>(mut self, names: I) -> Self {
+        self.collections = Some(names.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Leaves out the given collections, they never see a transaction.
+    pub fn exclude<I: IntoIterator<Item = T>, T: Into<String>>(mut self, names: I) -> Self {
+        self.excluded.extend(names.into_iter().map(Into::into));
+        self
+    }
+
+    /// Number of input files transformed concurrently.
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    pub fn on_error(mut self, on_error: OnError) -> Self {
+        self.on_error = on_error;
+        self
+    }
+
+    /// Splits the collections into partitions, see `partition`.
+    pub fn partition_by(mut self, partition_by: PartitionBy) -> Self {
+        self.partition_by = Some(partition_by);
+        self
+    }
+
+    /// Leaves out the transactions before this version.
+    pub fn from_version(mut self, version: u64) -> Self {
+        self.from_version = Some(version);
+        self
+    }
+
+    /// Leaves out the transactions after this version.
+    pub fn to_version(mut self, version: u64) -> Self {
+        self.to_version = Some(version);
+        self
+    }
+
+    /// Checks the versions transformed are contiguous and unique across the
+    /// inputs, see `versions`. Warns by default.
+    pub fn check_versions(mut self, check: VersionCheck) -> Self {
+        self.check_versions = check;
+        self
+    }
+
+    /// Recomputes the hashes of the transactions and fails on mismatches
+    /// like on any other error, see `verify`.
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    pub fn sink<T: Sink>(self, sink: T) -> TransformerBuilder<T> {
+        TransformerBuilder {
+            sources: self.sources,
+            mapping: self.mapping,
+            collections: self.collections,
+            excluded: self.excluded,
+            jobs: self.jobs,
+            on_error: self.on_error,
+            partition_by: self.partition_by,
+            from_version: self.from_version,
+            to_version: self.to_version,
+            check_versions: self.check_versions,
+            verify: self.verify,
+            sink,
+        }
+    }
+}
+
+impl<S: Sink> TransformerBuilder<S> {
+    pub fn build(self) -> Result<Transformer<S>> {
+        if let (Some(from), Some(to)) = (self.from_version, self.to_version) {
+            if from > to {
+                bail!("from version {} is after to version {}", from, to);
+            }
+        }
+
+        let mapped = match &self.mapping {
+            Some(mapping) => mapping.compile()?,
+            None => Vec::new(),
+        };
+        let build = move || {
+            let mut collections = models::registry();
+            collections.extend(mapped.iter().map(|mapping| {
+                Box::new(MappedCollection::new(mapping.clone())) as Box<dyn Collection>
+            }));
+            collections
+        };
+
+        let factory: CollectionFactory = if self.collections.is_none() && self.excluded.is_empty() {
+            Arc::new(build)
+        } else {
+            let available: Vec<String> = build().iter().map(|it| it.name().to_string()).collect();
+            for name in self
+                .collections
+                .iter()
+                .flatten()
+                .chain(self.excluded.iter())
+            {
+                if !available.contains(name) {
+                    bail!(
+                        "unknown collection {}, available: {}",
+                        name,
+                        available.join(", ")
+                    );
+                }
+            }
+
+            let selected = self.collections;
+            let excluded = self.excluded;
+            let is_selected = move |name: &str| {
+                let listed = match &selected {
+                    Some(selected) => selected.iter().any(|it| it == name),
+                    None => true,
+                };
+                listed && !excluded.iter().any(|it| it == name)
+            };
+            if !available.iter().any(|it| is_selected(it)) {
+                bail!("no collection left to produce");
+            }
+
+            Arc::new(move || {
+                build()
+                    .into_iter()
+                    .filter(|it| is_selected(it.name()))
+                    .collect()
+            })
+        };
+
+        Ok(Transformer {
+            sources: self.sources,
+            factory,
+            jobs: self.jobs,
+            on_error: self.on_error,
+            partition_by: self.partition_by,
+            from_version: self.from_version,
+            to_version: self.to_version,
+            check_versions: self.check_versions,
+            verify: self.verify,
+            sink: self.sink,
+        })
+    }
+}
+
+impl<S: Sink> Transformer<S> {
+    /// Names of the collections this transformer produces.
+    pub fn collection_names(&self) -> Vec<String> {
+        (self.factory)()
+            .iter()
+            .map(|it| it.name().to_string())
+            .collect()
+    }
+
+    /// Transforms every input and returns the sink once it's finished. On
+    /// failure the sink is aborted, so files already written stay readable.
+    ///
+    /// Sinks are blocking: on a multi-threaded runtime they're called through
+    /// `block_in_place`, so they may also `Handle::block_on` async work.
+    pub async fn run(mut self) -> Result<S> {
+        let mut manifest = Manifest::new(
+            (self.factory)()
+                .iter()
+                .map(|it| (it.name().to_string(), it.schema_version())),
+        );
+        manifest.input_paths = self.sources.iter().map(Source::path).collect();
+        blocking(|| self.sink.start(&manifest))?;
+
+        let gaps = match self.transform(&mut manifest).await {
+            Ok(gaps) => gaps,
+            Err(err) => {
+                if let Err(abort) = blocking(|| self.sink.abort()) {
+                    tracing::error!("failed to abort the sink: {:#}", abort);
+                }
+                return Err(err);
+            }
+        };
+        blocking(|| self.sink.finish(&manifest))?;
+
+        if self.check_versions == VersionCheck::Fail && !gaps.is_empty() {
+            bail!("missing versions {}", format_ranges(&gaps));
+        }
+
+        Ok(self.sink)
+    }
+
+    /// Sends every input to the sink and fills `manifest` in. Returns the
+    /// missing versions, which only fail the run once the sink is finished.
+    async fn transform(&mut self, manifest: &mut Manifest) -> Result<Vec<VersionRange>> {
+        let names = self.collection_names();
+
+        // Inputs are read (and decompressed), or fetched, one after the other
+        // by a single producer. The bounded channel keeps it at most `jobs`
+        // documents ahead of the workers.
+        let (sender, mut receiver) = tokio::sync::mpsc::channel::<Result<Document>>(self.jobs);
+        let sources = std::mem::take(&mut self.sources);
+        let producer = tokio::task::spawn_blocking(move || {
+            let send = |document| {
+                sender
+                    .blocking_send(Ok(document))
+                    .map_err(|_| anyhow!("transformer stopped"))
+            };
+
+            let mut manifests = Vec::with_capacity(sources.len());
+            for source in sources {
+                let _span = tracing::info_span!("source", name = source.name()).entered();
+                let read = match source {
+                    Source::File(path) => read_documents(&path, send),
+                    Source::Node(options) => Fetcher::new(&options).and_then(|it| it.fetch(send)),
+                    Source::Documents(documents, manifest) => {
+                        documents.into_iter().try_for_each(send).map(|_| manifest)
+                    }
+                };
+                match read {
+                    Ok(manifest) => manifests.push(manifest),
+                    Err(err) => {
+                        let _ = sender.blocking_send(Err(err));
+                        break;
+                    }
+                }
+            }
+            manifests
+        });
+
+        // Documents are transformed concurrently but their results come back in
+        // the order they were read, so the output doesn't depend on scheduling.
+        // Every worker hands its rows over in chunks of `BATCH_SIZE`
+        // transactions through a bounded channel, so a document is never held
+        // in memory as a whole.
+        let factory = self.factory.clone();
+        let on_error = self.on_error;
+        let verify = self.verify;
+        let versions = self.from_version.unwrap_or(0)..=self.to_version.unwrap_or(u64::MAX);
+        let mut workers = futures::stream::poll_fn(move |cx| receiver.poll_recv(cx))
+            .map(|document| {
+                let factory = factory.clone();
+                let versions = versions.clone();
+                let (sender, chunks) = tokio::sync::mpsc::channel(CHUNKS_AHEAD);
+                let worker = tokio::task::spawn_blocking(move || {
+                    let transformed = document.and_then(|document| {
+                        transform_document(&document, &factory, on_error, versions, verify, &sender)
+                    });
+                    if let Err(err) = transformed {
+                        let _ = sender.blocking_send(Err(err));
+                    }
+                });
+                futures::future::ready((chunks, worker))
+            })
+            .buffered(self.jobs);
+
+        let mut partitioner = self.partition_by.map(Partitioner::new);
+        let mut tracker = VersionTracker::new();
+        let mut verification = self.verify.then(VerifyReport::default);
+        while let Some((mut chunks, worker)) = workers.next().await {
+            while let Some(chunk) = chunks.recv().await {
+                let chunk = chunk?;
+                self.check_versions(&mut tracker, &chunk)?;
+                blocking(|| {
+                    self.write_chunk(
+                        &chunk,
+                        &names,
+                        &mut partitioner,
+                        manifest,
+                        &mut verification,
+                    )
+                })?;
+            }
+            worker.await?;
+        }
+        manifest.inputs = producer.await?;
+
+        let mut gaps = Vec::new();
+        if self.check_versions != VersionCheck::Off {
+            let report = tracker.report(self.from_version, self.to_version);
+            if !report.gaps.is_empty() {
+                gaps = report.gaps.clone();
+                if self.check_versions == VersionCheck::Warn {
+                    tracing::warn!("missing versions {}", format_ranges(&gaps));
+                }
+            }
+            manifest.versions = Some(report);
+        }
+
+        if let Some(report) = &verification {
+            tracing::info!(
+                "verified {} hashes, {} mismatched, {} unverifiable",
+                report.verified,
+                report.mismatched,
+                report.unverifiable
+            );
+            if let Some(warning) = report.coverage_warning() {
+                tracing::warn!("{}", warning);
+            }
+        }
+        manifest.verification = verification;
+
+        Ok(gaps)
+    }
+
+    /// Hands the rows of a chunk to the sink and adds them to `manifest`.
+    fn write_chunk(
+        &mut self,
+        chunk: &Chunk,
+        names: &[String],
+        partitioner: &mut Option<Partitioner>,
+        manifest: &mut Manifest,
+        verification: &mut Option<VerifyReport>,
+    ) -> Result<()> {
+        if let Some(partitioner) = partitioner {
+            for block in chunk.blocks.iter() {
+                partitioner.add_block(block);
+            }
+        }
+
+        for (batch, name) in chunk.batches.iter().zip(names.iter()) {
+            let Some(batch) = batch else {
+                continue;
+            };
+
+            let partitions = match partitioner {
+                Some(partitioner) => partitioner.split(batch)?,
+                None => None,
+            };
+            match partitions {
+                Some(partitions) => {
+                    for (partition, batch) in partitions.iter() {
+                        self.sink.write_partition(name, partition, batch)?;
+                    }
+                }
+                None => self.sink.write(name, batch)?,
+            }
+            manifest.add_batch(name, batch)?;
+            metrics::ROWS
+                .with_label_values(&[name])
+                .inc_by(batch.num_rows() as u64);
+        }
+        for error in chunk.errors.iter() {
+            self.sink.quarantine(error)?;
+        }
+        manifest.skipped += chunk.errors.len();
+        if let (Some(total), Some(report)) = (verification, &chunk.verification) {
+            total.add(report);
+        }
+        self.sink.commit()
+    }
+
+    /// Checks the versions of a chunk against the ones already written, before
+    /// any of its rows are.
+    fn check_versions(&self, tracker: &mut VersionTracker, chunk: &Chunk) -> Result<()> {
+        if self.check_versions == VersionCheck::Off {
+            return Ok(());
+        }
+
+        let sorted = tracker.is_sorted();
+        let duplicates = tracker.add(&chunk.versions);
+        let message = if !duplicates.is_empty() {
+            format!(
+                "{}: versions {} were already transformed",
+                chunk.name,
+                format_ranges(&duplicates)
+            )
+        } else if sorted && !tracker.is_sorted() {
+            format!(
+                "{}: versions from {} come after later ones, the output isn't sorted by version \
+                 across inputs (pass the inputs in version order)",
+                chunk.name,
+                chunk.versions.iter().min().copied().unwrap_or_default()
+            )
+        } else {
+            return Ok(());
+        };
+
+        if self.check_versions == VersionCheck::Fail {
+            bail!(message);
+        }
+        tracing::warn!("{}", message);
+        Ok(())
+    }
+}
+
+/// Chunks a worker may transform ahead of the one being written.
+const CHUNKS_AHEAD: usize = 1;
+
+/// What a worker extracted from `BATCH_SIZE` transactions (or the rest) of a
+/// document.
+struct Chunk {
+    /// See `Document::name`.
+    name: String,
+
+    /// One batch per collection (in factory order), sorted by version.
+    batches: Vec<Option<RecordBatch>>,
+
+    /// Items left out of the batches, with `OnError::Skip`.
+    errors: Vec<TransformError>,
+
+    /// Blocks started in the chunk, in order.
+    blocks: Vec<Block>,
+
+    /// Versions of the transactions transformed, in order.
+    versions: Vec<u64>,
+
+    /// Hashes checked, with `verify`.
+    verification: Option<VerifyReport>,
+}
+
+/// Extracts the rows of one document with a private set of collections, and
+/// sends them every `BATCH_SIZE` transactions.
+fn transform_document(
+    document: &Document,
+    factory: &CollectionFactory,
+    on_error: OnError,
+    range: RangeInclusive<u64>,
+    verify: bool,
+    sender: &Sender<Result<Chunk>>,
+) -> Result<()> {
+    let _span = tracing::info_span!("document", name = %document.name).entered();
+    let started = Instant::now();
+
+    let mut collections = factory();
+    let mut chunk = Chunk::new(document, collections.len());
+    let mut transactions = 0;
+    let mut verifier = verify.then(Verifier::new);
+    document.for_each_transaction(|transaction| {
+        // Blocks before `range` still tell the epoch of the versions in it.
+        if let Transaction::BlockMetadataTransaction(block) = &transaction {
+            chunk.blocks.push(Block {
+                version: block.info.version.into(),
+                epoch: block.epoch.into(),
+                timestamp: block.timestamp.into(),
+            });
+        }
+
+        let Some(version) = transaction_version(&transaction) else {
+            return Ok(());
+        };
+        if !range.contains(&version) {
+            return Ok(());
+        }
+        chunk.versions.push(version);
+        transactions += 1;
+
+        // A transaction with a hash mismatch is left out, like one that can't
+        // be transformed.
+        let mismatches = match &mut verifier {
+            Some(verifier) => verifier.verify(&transaction),
+            None => Vec::new(),
+        };
+        if mismatches.is_empty() {
+            process_transaction(&mut collections, &transaction, &mut chunk.errors);
+        }
+        for reason in mismatches {
+            chunk
+                .errors
+                .push(TransformError::transaction(version, reason));
+        }
+
+        if on_error == OnError::Fail && !chunk.errors.is_empty() {
+            count_errors(&chunk.errors[..1]);
+            return Err(chunk.errors.swap_remove(0))
+                .with_context(|| format!("failed to transform {}", document.name));
+        }
+
+        if chunk.versions.len() >= BATCH_SIZE {
+            let next = Chunk::new(document, collections.len());
+            std::mem::replace(&mut chunk, next).send(&mut collections, &mut verifier, sender)?;
+        }
+        Ok(())
+    })?;
+    chunk.send(&mut collections, &mut verifier, sender)?;
+
+    let seconds = started.elapsed().as_secs_f64();
+    metrics::DOCUMENTS.inc();
+    metrics::TRANSACTIONS.inc_by(transactions);
+    metrics::DOCUMENT_SECONDS.observe(seconds);
+    if seconds > 0.0 {
+        metrics::TRANSACTIONS_PER_SECOND.observe(transactions as f64 / seconds);
+    }
+    tracing::debug!(transactions, seconds, "transformed");
+
+    Ok(())
+}
+
+impl Chunk {
+    fn new(document: &Document, collections: usize) -> Chunk {
+        Chunk {
+            name: document.name.clone(),
+            batches: Vec::with_capacity(collections),
+            errors: Vec::new(),
+            blocks: Vec::new(),
+            versions: Vec::new(),
+            verification: None,
+        }
+    }
+
+    /// Drains the collections into the chunk and sends it to the writer.
+    fn send(
+        mut self,
+        collections: &mut [Box<dyn Collection>],
+        verifier: &mut Option<Verifier>,
+        sender: &Sender<Result<Chunk>>,
+    ) -> Result<()> {
+        self.batches = collections
+            .iter_mut()
+            .map(|collection| collection.take_batch()?.map(sort_by_version).transpose())
+            .collect::<Result<_>>()?;
+        self.verification = verifier.as_mut().map(Verifier::take_report);
+        count_errors(&self.errors);
+
+        sender
+            .blocking_send(Ok(self))
+            .map_err(|_| anyhow!("transformer stopped"))
+    }
+}
+
+fn count_errors(errors: &[TransformError]) {
+    for error in errors.iter() {
+        let collection = error.collection.as_deref().unwrap_or_default();
+        metrics::ERRORS.with_label_values(&[collection]).inc();
+    }
+}
+
+/// Runs `f`, which calls the sink, without holding up the other tasks of a
+/// multi-threaded runtime: sinks write files, insert over HTTP or upload.
+fn blocking<T>(f: impl FnOnce() -> T) -> T {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(f)
+        }
+        _ => f(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{cast::AsArray, types::UInt64Type};
+
+    use super::*;
+    use crate::{
+        manifest::{ABORTED_KEY, INPUTS_KEY, MAX_VERSION_KEY},
+        partition::PartitionBy,
+        sink::{FileSink, MemorySink, MANIFEST},
+        test_utils::{block, documents, parquet_options, temp_dir},
+        writer::{read_file_metadata, OutputFormat},
+    };
+
+    #[tokio::test]
+    async fn duplicates_abort_with_readable_files() {
+        let dest = temp_dir("duplicates_abort");
+        let (documents, input) = documents(vec![block(1, 3), block(2, 5)]);
+        let sink = FileSink::new(&dest, OutputFormat::Parquet, &parquet_options()).unwrap();
+
+        let err = Transformer::builder()
+            .documents(documents, input)
+            .collections(["slow_wallet"])
+            .check_versions(VersionCheck::Fail)
+            .jobs(1)
+            .sink(sink)
+            .build()
+            .unwrap()
+            .run()
+            .await
+            .err()
+            .unwrap();
+
+        assert!(err.to_string().contains("versions 2-3"), "{}", err);
+        // The rows of the first document have a footer, but the run isn't
+        // recorded as complete.
+        let metadata = read_file_metadata(&format!("{}/slow_wallet.parquet", dest)).unwrap();
+        assert_eq!(metadata[MAX_VERSION_KEY], "2");
+        assert_eq!(metadata[ABORTED_KEY], "true");
+        assert!(!std::path::Path::new(&format!("{}/{}", dest, MANIFEST)).exists());
+    }
+
+    #[tokio::test]
+    async fn partitions_closed_early_list_the_inputs() {
+        let dest = temp_dir("partition_inputs");
+        let (documents, input) = documents(vec![block(1, 5)]);
+        let sink = FileSink::new(&dest, OutputFormat::Parquet, &parquet_options()).unwrap();
+        Transformer::builder()
+            .documents(documents, input)
+            .collections(["slow_wallet"])
+            .partition_by("version:2".parse::<PartitionBy>().unwrap())
+            .sink(sink)
+            .build()
+            .unwrap()
+            .run()
+            .await
+            .unwrap();
+
+        // Closed before the inputs were read to the end, and at the end.
+        for partition in ["version_start=0", "version_start=4"] {
+            let path = format!("{}/slow_wallet/{}/part-0.parquet", dest, partition);
+            let metadata = read_file_metadata(&path).unwrap();
+            assert_eq!(metadata[INPUTS_KEY], r#"["test"]"#, "{}", partition);
+        }
+    }
+
+    async fn run(
+        transactions: Vec<Vec<serde_json::Value>>,
+        check: VersionCheck,
+    ) -> Result<MemorySink> {
+        let (documents, input) = documents(transactions);
+        Transformer::builder()
+            .documents(documents, input)
+            .collections(["slow_wallet"])
+            .check_versions(check)
+            .jobs(2)
+            .sink(MemorySink::new())
+            .build()?
+            .run()
+            .await
+    }
+
+    fn versions(sink: &MemorySink) -> Vec<u64> {
+        sink.batches["slow_wallet"]
+            .iter()
+            .flat_map(|batch| {
+                let column = batch.column_by_name("version").unwrap();
+                column.as_primitive::<UInt64Type>().values().to_vec()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn documents_are_written_in_chunks() {
+        let last = BATCH_SIZE as u64 + 5;
+        let sink = run(
+            vec![block(1, last), block(last + 1, last + 3)],
+            VersionCheck::Fail,
+        )
+        .await
+        .unwrap();
+
+        // Two chunks for the first document, one for the second.
+        assert_eq!(sink.batches["slow_wallet"].len(), 3);
+        let expected: Vec<u64> = (1..last).chain(last + 1..last + 3).collect();
+        assert_eq!(versions(&sink), expected);
+        let manifest = sink.manifest.unwrap();
+        assert!(manifest.versions.unwrap().sorted);
+        assert_eq!(manifest.collections["slow_wallet"].sorted, Some(true));
+    }
+
+    #[tokio::test]
+    async fn inputs_out_of_order_are_reported() {
+        let sink = run(vec![block(10, 12), block(1, 9)], VersionCheck::Warn)
+            .await
+            .unwrap();
+        assert_eq!(versions(&sink), [10, 11, 1, 2, 3, 4, 5, 6, 7, 8]);
+        let manifest = sink.manifest.unwrap();
+        assert!(!manifest.versions.unwrap().sorted);
+        assert_eq!(manifest.collections["slow_wallet"].sorted, Some(false));
+
+        // Only the rows of each chunk are sorted, even without checks.
+        let sink = run(vec![block(10, 12), block(1, 9)], VersionCheck::Off)
+            .await
+            .unwrap();
+        let manifest = sink.manifest.unwrap();
+        assert!(manifest.versions.is_none());
+        assert_eq!(manifest.collections["slow_wallet"].sorted, Some(false));
+
+        let err = run(vec![block(10, 12), block(1, 9)], VersionCheck::Fail)
+            .await
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string().contains("isn't sorted by version"),
+            "{}",
+            err
+        );
+    }
+}
@@ -22,7 +22,7 @@ pub fn parse_addr(input: &str) -> Result<HexEncodedBytes> {
     } else {
         addr = format!("{:0>64}", addr);
     }
-    return Ok(HexEncodedBytes::from_str(&addr).unwrap());
+    HexEncodedBytes::from_str(&addr).map_err(|_| UtilsError::InvalidHexValue.into())
 }
 
 // pub fn parse_hex(input: &str) -> Result<HexEncodedBytes> {
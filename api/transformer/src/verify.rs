@@ -0,0 +1,268 @@
+//! `--verify`: recomputes the hashes returned with every transaction, so that
+//! corrupted or tampered inputs are caught before they're loaded.
+//!
+//! Hashes follow the Diem scheme: `sha3_256(seed || bcs(value))`, the seed
+//! being `sha3_256("DIEM::" || type name)`, as `getTransactionHash` does in
+//! `api/src/utils.ts`. Only what the JSON of the node fully describes can be
+//! encoded again:
+//!
+//! - `hash` of block metadata transactions, and of state checkpoints whose
+//!   block (the id they hold) is earlier in the same document.
+//! - `event_root_hash` of transactions without events, the root of an empty
+//!   accumulator.
+//!
+//! The other hashes are counted as unverifiable: user and genesis transactions
+//! hold decoded arguments and write sets that need the Move ABIs to be encoded
+//! again, and so do the resources behind `state_change_hash` and the events
+//! behind `event_root_hash`. On a chain of mostly user transactions that's
+//! most hashes, so a run warns when fewer than half of them were checked (see
+//! `VerifyReport::coverage_warning`).
+
+use diem_api_types::{BlockMetadataTransaction, Transaction};
+use serde::Serialize;
+use sha3::{Digest, Sha3_256};
+
+use crate::metrics;
+
+/// Domain separation prefix of the seeds.
+const HASH_PREFIX: &[u8] = b"DIEM::";
+
+/// Root hash of an accumulator without leaves, `ACCUMULATOR_PLACEHOLDER_HASH`
+/// zero padded to 32 bytes.
+const ACCUMULATOR_PLACEHOLDER_HASH: &[u8] = b"ACCUMULATOR_PLACEHOLDER_HASH";
+
+/// `Transaction` as stored on chain. Variants are encoded by index, the ones
+/// that can't be rebuilt from JSON are never constructed.
+#[derive(Serialize)]
+#[allow(dead_code)]
+enum OnChainTransaction {
+    UserTransaction(()),
+    GenesisTransaction(()),
+    BlockMetadata(BlockMetadata),
+    StateCheckpoint(Vec<u8>),
+}
+
+/// `BlockMetadata` as stored on chain. Hashes are encoded as byte vectors.
+#[derive(Serialize)]
+struct BlockMetadata {
+    id: Vec<u8>,
+    epoch: u64,
+    round: u64,
+    proposer: [u8; 32],
+    previous_block_votes_bitvec: Vec<u8>,
+    failed_proposer_indices: Vec<u32>,
+    timestamp_usecs: u64,
+}
+
+/// Number of hashes checked.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct VerifyReport {
+    pub verified: u64,
+    pub mismatched: u64,
+
+    /// Hashes that can't be computed from the JSON of a transaction.
+    pub unverifiable: u64,
+}
+
+impl VerifyReport {
+    /// Set when most hashes couldn't be checked, since a run without
+    /// mismatches then says little about its inputs.
+    pub fn coverage_warning(&self) -> Option<String> {
+        let checked = self.verified + self.mismatched;
+        if self.unverifiable <= checked {
+            return None;
+        }
+        Some(format!(
+            "only {} of {} hashes could be checked, --verify doesn't cover user and genesis \
+             transactions, state_change_hash or events",
+            checked,
+            checked + self.unverifiable
+        ))
+    }
+
+    pub fn add(&mut self, other: &VerifyReport) {
+        self.verified += other.verified;
+        self.mismatched += other.mismatched;
+        self.unverifiable += other.unverifiable;
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Outcome {
+    Verified,
+    Mismatched,
+    Unverifiable,
+}
+
+impl Outcome {
+    /// Label of `metrics::HASHES`.
+    fn label(self) -> &'static str {
+        match self {
+            Outcome::Verified => "verified",
+            Outcome::Mismatched => "mismatched",
+            Outcome::Unverifiable => "unverifiable",
+        }
+    }
+}
+
+/// Checks the transactions of a document, in order.
+#[derive(Default)]
+pub struct Verifier {
+    /// Id of the last block seen, which the state checkpoint ending it holds.
+    block_id: Option<Vec<u8>>,
+    report: VerifyReport,
+}
+
+impl Verifier {
+    pub fn new() -> Verifier {
+        Verifier::default()
+    }
+
+    /// Hashes checked since the previous call.
+    pub fn take_report(&mut self) -> VerifyReport {
+        std::mem::take(&mut self.report)
+    }
+
+    /// Checks the hashes of `transaction`. Returns a reason for every
+    /// mismatch.
+    pub fn verify(&mut self, transaction: &Transaction) -> Vec<String> {
+        let mut mismatches = Vec::new();
+        let (info, computed, events) = match transaction {
+            Transaction::PendingTransaction(_) => return mismatches,
+            Transaction::UserTransaction(it) => (&it.info, None, it.events.len()),
+            Transaction::GenesisTransaction(it) => (&it.info, None, it.events.len()),
+            Transaction::BlockMetadataTransaction(it) => {
+                let block = block_metadata(it);
+                self.block_id = block.as_ref().map(|it| it.id.clone());
+                let computed = block.map(OnChainTransaction::BlockMetadata);
+                (&it.info, computed, it.events.len())
+            }
+            Transaction::StateCheckpointTransaction(it) => {
+                let computed = self
+                    .block_id
+                    .take()
+                    .map(OnChainTransaction::StateCheckpoint);
+                (&it.info, computed, 0)
+            }
+        };
+
+        match computed.and_then(|it| hash("Transaction", &it)) {
+            Some(computed) => {
+                self.check("hash", &info.hash.to_string(), &computed, &mut mismatches)
+            }
+            None => self.count(Outcome::Unverifiable),
+        }
+
+        // state_change_hash, see above.
+        self.count(Outcome::Unverifiable);
+
+        if events == 0 {
+            let mut placeholder = ACCUMULATOR_PLACEHOLDER_HASH.to_vec();
+            placeholder.resize(32, 0);
+            self.check(
+                "event_root_hash",
+                &info.event_root_hash.to_string(),
+                &placeholder,
+                &mut mismatches,
+            );
+        } else {
+            self.count(Outcome::Unverifiable);
+        }
+
+        mismatches
+    }
+
+    fn check(&mut self, name: &str, expected: &str, computed: &[u8], mismatches: &mut Vec<String>) {
+        let computed = format!("0x{}", hex::encode(computed));
+        if expected.eq_ignore_ascii_case(&computed) {
+            self.count(Outcome::Verified);
+        } else {
+            self.count(Outcome::Mismatched);
+            mismatches.push(format!(
+                "{} mismatch: {} in the input, computed {}",
+                name, expected, computed
+            ));
+        }
+    }
+
+    fn count(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Verified => self.report.verified += 1,
+            Outcome::Mismatched => self.report.mismatched += 1,
+            Outcome::Unverifiable => self.report.unverifiable += 1,
+        }
+        metrics::HASHES.with_label_values(&[outcome.label()]).inc();
+    }
+}
+
+fn block_metadata(block: &BlockMetadataTransaction) -> Option<BlockMetadata> {
+    Some(BlockMetadata {
+        id: hex::decode(block.id.to_string().trim_start_matches("0x")).ok()?,
+        epoch: block.epoch.into(),
+        round: block.round.into(),
+        proposer: block.proposer.inner().to_vec().try_into().ok()?,
+        previous_block_votes_bitvec: block.previous_block_votes_bitvec.clone(),
+        failed_proposer_indices: block.failed_proposer_indices.clone(),
+        timestamp_usecs: block.timestamp.into(),
+    })
+}
+
+/// Hash of `value` with the seed of `type_name`.
+fn hash(type_name: &str, value: &impl Serialize) -> Option<Vec<u8>> {
+    let bytes = bcs::to_bytes(value).ok()?;
+    let seed = Sha3_256::new()
+        .chain_update(HASH_PREFIX)
+        .chain_update(type_name)
+        .finalize();
+    let hash = Sha3_256::new()
+        .chain_update(seed)
+        .chain_update(bytes)
+        .finalize();
+    Some(hash.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{block_metadata, state_checkpoint};
+
+    #[test]
+    fn hashes_of_user_data_are_unverifiable() {
+        let mut placeholder = ACCUMULATOR_PLACEHOLDER_HASH.to_vec();
+        placeholder.resize(32, 0);
+        let mut block = block_metadata(1, vec![]);
+        block["event_root_hash"] = format!("0x{}", hex::encode(placeholder)).into();
+
+        let mut verifier = Verifier::new();
+        let mismatches = verifier.verify(&serde_json::from_value(block).unwrap());
+        assert_eq!(mismatches.len(), 1);
+        assert!(
+            mismatches[0].starts_with("hash mismatch"),
+            "{:?}",
+            mismatches
+        );
+
+        let report = verifier.take_report();
+        assert_eq!(
+            (report.verified, report.mismatched, report.unverifiable),
+            (1, 1, 1)
+        );
+        assert_eq!(report.coverage_warning(), None);
+
+        // A checkpoint without its block in the document can't be checked.
+        let mut verifier = Verifier::new();
+        verifier.verify(&serde_json::from_value(state_checkpoint(2)).unwrap());
+        assert_eq!(verifier.take_report().unverifiable, 2);
+    }
+
+    #[test]
+    fn mostly_unverifiable_runs_are_reported() {
+        let report = VerifyReport {
+            verified: 1,
+            mismatched: 1,
+            unverifiable: 3,
+        };
+        let warning = report.coverage_warning().unwrap();
+        assert!(warning.starts_with("only 2 of 5 hashes"), "{}", warning);
+    }
+}
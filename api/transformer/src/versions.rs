@@ -0,0 +1,198 @@
+//! Checks that the transformed versions are contiguous and unique across all
+//! the inputs, so overlapping or missing archives don't go unnoticed.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+/// What to do about gaps and duplicates in the versions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum VersionCheck {
+    Off,
+
+    /// Print them, and list them in the manifest.
+    Warn,
+
+    /// Stop before writing the rows of a document with versions already
+    /// transformed. Gaps fail the run once the output is written.
+    Fail,
+}
+
+/// Inclusive range of versions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct VersionRange {
+    pub first: u64,
+    pub last: u64,
+}
+
+/// Found by `VersionTracker`, recorded in the manifest.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct VersionReport {
+    /// First and last versions transformed.
+    pub first: Option<u64>,
+    pub last: Option<u64>,
+
+    /// Versions missing between `--from-version` (or the first version) and
+    /// `--to-version` (or the last one).
+    pub gaps: Vec<VersionRange>,
+
+    /// Versions transformed more than once.
+    pub duplicates: Vec<VersionRange>,
+
+    /// Whether the versions arrived in increasing order, which keeps every
+    /// collection sorted by version across inputs.
+    pub sorted: bool,
+}
+
+impl VersionReport {
+    pub fn is_clean(&self) -> bool {
+        self.gaps.is_empty() && self.duplicates.is_empty() && self.sorted
+    }
+}
+
+/// Versions seen so far, as ranges.
+pub struct VersionTracker {
+    seen: BTreeMap<u64, u64>,
+    duplicates: BTreeMap<u64, u64>,
+
+    /// Last version added, see `VersionReport::sorted`.
+    last: Option<u64>,
+    sorted: bool,
+}
+
+impl Default for VersionTracker {
+    fn default() -> VersionTracker {
+        VersionTracker {
+            seen: BTreeMap::new(),
+            duplicates: BTreeMap::new(),
+            last: None,
+            sorted: true,
+        }
+    }
+}
+
+impl VersionTracker {
+    pub fn new() -> VersionTracker {
+        VersionTracker::default()
+    }
+
+    /// Whether every version added so far came after the previous ones.
+    pub fn is_sorted(&self) -> bool {
+        self.sorted
+    }
+
+    /// Records the versions of a document, or of part of it. Returns the ones
+    /// already seen.
+    pub fn add(&mut self, versions: &[u64]) -> Vec<VersionRange> {
+        for version in versions.iter() {
+            if self.last.is_some_and(|last| *version <= last) {
+                self.sorted = false;
+            }
+            self.last = Some(*version);
+        }
+
+        let mut duplicates = BTreeMap::new();
+        for version in versions.iter() {
+            if !insert(&mut self.seen, *version) {
+                insert(&mut self.duplicates, *version);
+                insert(&mut duplicates, *version);
+            }
+        }
+        to_ranges(&duplicates)
+    }
+
+    /// Gaps and duplicates, between `from` and `to` when set, otherwise
+    /// between the first and last versions seen.
+    pub fn report(&self, from: Option<u64>, to: Option<u64>) -> VersionReport {
+        let first = self.seen.keys().next().copied();
+        let last = self.seen.values().next_back().copied();
+
+        let mut gaps = Vec::new();
+        if let (Some(from), Some(to)) = (from.or(first), to.or(last)) {
+            let mut next = Some(from);
+            for (start, end) in self.seen.iter() {
+                let Some(expected) = next else {
+                    break;
+                };
+                if *end < expected {
+                    continue;
+                }
+                if *start > to {
+                    break;
+                }
+                if *start > expected {
+                    gaps.push(VersionRange {
+                        first: expected,
+                        last: start - 1,
+                    });
+                }
+                next = end.checked_add(1);
+            }
+            if let Some(expected) = next.filter(|it| *it <= to) {
+                gaps.push(VersionRange {
+                    first: expected,
+                    last: to,
+                });
+            }
+        }
+
+        VersionReport {
+            first,
+            last,
+            gaps,
+            duplicates: to_ranges(&self.duplicates),
+            sorted: self.sorted,
+        }
+    }
+}
+
+/// Adds `version` to `ranges`, merging adjacent ranges. Returns false if it
+/// was already in.
+fn insert(ranges: &mut BTreeMap<u64, u64>, version: u64) -> bool {
+    let previous = ranges
+        .range(..=version)
+        .next_back()
+        .map(|(start, end)| (*start, *end));
+    if let Some((_, end)) = previous {
+        if end >= version {
+            return false;
+        }
+    }
+
+    let next_end = version.checked_add(1).and_then(|next| ranges.remove(&next));
+    match previous {
+        Some((start, end)) if end + 1 == version => {
+            ranges.insert(start, next_end.unwrap_or(version));
+        }
+        _ => {
+            ranges.insert(version, next_end.unwrap_or(version));
+        }
+    }
+
+    true
+}
+
+fn to_ranges(ranges: &BTreeMap<u64, u64>) -> Vec<VersionRange> {
+    ranges
+        .iter()
+        .map(|(first, last)| VersionRange {
+            first: *first,
+            last: *last,
+        })
+        .collect()
+}
+
+/// `a-b, c, ...`, for messages.
+pub fn format_ranges(ranges: &[VersionRange]) -> String {
+    ranges
+        .iter()
+        .map(|it| {
+            if it.first == it.last {
+                it.first.to_string()
+            } else {
+                format!("{}-{}", it.first, it.last)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,450 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use arrow::{
+    compute::{cast, lexsort_to_indices, take_record_batch, SortColumn},
+    csv, ipc, json,
+};
+use arrow_array::{
+    cast::AsArray, Array, ArrayRef, LargeListArray, ListArray, RecordBatch, StringArray,
+};
+use arrow_schema::{DataType, Field, Schema};
+use parquet::{
+    arrow::arrow_writer::ArrowWriter,
+    basic::{Compression, GzipLevel, ZstdLevel},
+    file::{
+        metadata::KeyValue,
+        properties::{EnabledStatistics, WriterProperties},
+        reader::{FileReader, SerializedFileReader},
+    },
+    schema::types::ColumnPath,
+};
+
+/// Transactions whose rows a worker may buffer in its collections before they
+/// are handed to the sink, which bounds the memory used per document.
+pub const BATCH_SIZE: usize = 8192;
+
+/// How parquet files are encoded. The same `WriterProperties` are shared by
+/// every collection, per column settings apply to the columns with that name
+/// in any collection.
+#[derive(Clone, Debug, clap::Args)]
+#[command(about = None, long_about = None)]
+pub struct ParquetOptions {
+    /// Compression codec of the column chunks.
+    #[arg(long, value_enum, default_value_t = Codec::Zstd)]
+    pub compression: Codec,
+
+    /// Compression level, for zstd (1-22) and gzip (0-10).
+    #[arg(long)]
+    pub compression_level: Option<i32>,
+
+    /// Disable dictionary encoding for every column.
+    #[arg(long)]
+    pub no_dictionary: bool,
+
+    /// Disable dictionary encoding for a column. Can be repeated.
+    #[arg(long = "no-dictionary-column", value_name = "COLUMN")]
+    pub no_dictionary_columns: Vec<String>,
+
+    /// Target size of a data page, in bytes.
+    #[arg(long, default_value_t = 1024 * 1024)]
+    pub data_page_size: usize,
+
+    /// Flush a row group once it holds this many rows.
+    #[arg(long, default_value_t = 1024 * 1024)]
+    pub row_group_rows: usize,
+
+    /// Flush a row group once its encoded size reaches this many bytes.
+    #[arg(long, default_value_t = 128 * 1024 * 1024)]
+    pub row_group_bytes: usize,
+
+    /// Level of the min/max statistics written for every column.
+    #[arg(long, value_enum, default_value_t = Statistics::Page)]
+    pub statistics: Statistics,
+
+    /// Write a bloom filter for a column. Can be repeated.
+    #[arg(
+        long = "bloom-filter",
+        value_name = "COLUMN",
+        default_values_t = ["address".to_string(), "sender".to_string(), "hash".to_string()]
+    )]
+    pub bloom_filter_columns: Vec<String>,
+
+    /// Don't write any bloom filter.
+    #[arg(long)]
+    pub no_bloom_filter: bool,
+
+    /// False positive probability of the bloom filters.
+    #[arg(long, default_value_t = 0.01)]
+    pub bloom_filter_fpp: f64,
+
+    /// Expected number of distinct values per row group, sizes the bloom
+    /// filters. The default fits the 9,900 versions of a transactions archive.
+    #[arg(long, default_value_t = 10_000)]
+    pub bloom_filter_ndv: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Codec {
+    None,
+    Snappy,
+    Gzip,
+    Lz4,
+    Zstd,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Statistics {
+    None,
+    Chunk,
+    Page,
+}
+
+impl Default for ParquetOptions {
+    fn default() -> ParquetOptions {
+        ParquetOptions {
+            compression: Codec::Zstd,
+            compression_level: None,
+            no_dictionary: false,
+            no_dictionary_columns: Vec::new(),
+            data_page_size: 1024 * 1024,
+            row_group_rows: 1024 * 1024,
+            row_group_bytes: 128 * 1024 * 1024,
+            statistics: Statistics::Page,
+            bloom_filter_columns: vec![
+                "address".to_string(),
+                "sender".to_string(),
+                "hash".to_string(),
+            ],
+            no_bloom_filter: false,
+            bloom_filter_fpp: 0.01,
+            bloom_filter_ndv: 10_000,
+        }
+    }
+}
+
+impl ParquetOptions {
+    pub fn writer_properties(&self) -> Result<WriterProperties> {
+        let compression = match (self.compression, self.compression_level) {
+            (Codec::None, None) => Compression::UNCOMPRESSED,
+            (Codec::Snappy, None) => Compression::SNAPPY,
+            (Codec::Lz4, None) => Compression::LZ4_RAW,
+            (Codec::Gzip, None) => Compression::GZIP(GzipLevel::default()),
+            (Codec::Gzip, Some(level)) => {
+                let level =
+                    u32::try_from(level).map_err(|_| anyhow!("invalid gzip level {}", level))?;
+                Compression::GZIP(GzipLevel::try_new(level)?)
+            }
+            (Codec::Zstd, None) => Compression::ZSTD(ZstdLevel::default()),
+            (Codec::Zstd, Some(level)) => Compression::ZSTD(ZstdLevel::try_new(level)?),
+            (codec, Some(_)) => bail!("{:?} doesn't take a compression level", codec),
+        };
+
+        let statistics = match self.statistics {
+            Statistics::None => EnabledStatistics::None,
+            Statistics::Chunk => EnabledStatistics::Chunk,
+            Statistics::Page => EnabledStatistics::Page,
+        };
+
+        if !(self.bloom_filter_fpp > 0.0 && self.bloom_filter_fpp < 1.0) {
+            bail!("bloom filter fpp must be between 0 and 1");
+        }
+
+        let mut builder = WriterProperties::builder()
+            .set_compression(compression)
+            .set_dictionary_enabled(!self.no_dictionary)
+            .set_data_page_size_limit(self.data_page_size)
+            .set_max_row_group_size(self.row_group_rows.max(1))
+            .set_statistics_enabled(statistics);
+
+        for column in self.no_dictionary_columns.iter() {
+            builder =
+                builder.set_column_dictionary_enabled(ColumnPath::from(column.as_str()), false);
+        }
+
+        if !self.no_bloom_filter {
+            for column in self.bloom_filter_columns.iter() {
+                let column = ColumnPath::from(column.as_str());
+                builder = builder
+                    .set_column_bloom_filter_enabled(column.clone(), true)
+                    .set_column_bloom_filter_fpp(column.clone(), self.bloom_filter_fpp)
+                    .set_column_bloom_filter_ndv(column, self.bloom_filter_ndv);
+            }
+        }
+
+        Ok(builder.build())
+    }
+}
+
+/// File format of the collections.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    Parquet,
+
+    /// Arrow IPC file.
+    Arrow,
+
+    /// CSV with a header line. Binary columns are hex encoded, lists are
+    /// written as `[a, b]`.
+    Csv,
+
+    /// One JSON object per row. Binary columns are hex encoded.
+    Ndjson,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Arrow => "arrow",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Ndjson => "ndjson",
+        }
+    }
+}
+
+enum FormatWriter {
+    Parquet(ArrowWriter<File>),
+    Arrow(ipc::writer::FileWriter<BufWriter<File>>),
+    Csv(Box<csv::Writer<BufWriter<File>>>),
+    Ndjson(json::LineDelimitedWriter<BufWriter<File>>),
+}
+
+/// Appends the batches of one collection to its file.
+///
+/// The file is only created with the first batch, so collections that never
+/// see a row don't produce an output file. For parquet, rows are kept in
+/// memory only until the row group holds `max_row_group_size` rows (see
+/// `WriterProperties`) or `max_row_group_bytes` bytes.
+pub struct CollectionWriter {
+    path: String,
+    format: OutputFormat,
+    properties: WriterProperties,
+    max_row_group_bytes: usize,
+    writer: Option<FormatWriter>,
+}
+
+impl CollectionWriter {
+    pub fn new(
+        path: String,
+        format: OutputFormat,
+        properties: WriterProperties,
+        max_row_group_bytes: usize,
+    ) -> CollectionWriter {
+        CollectionWriter {
+            path,
+            format,
+            properties,
+            max_row_group_bytes,
+            writer: None,
+        }
+    }
+
+    pub fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        let batch = match self.format {
+            OutputFormat::Parquet | OutputFormat::Arrow => batch.clone(),
+            OutputFormat::Csv => to_text(batch, true)?,
+            OutputFormat::Ndjson => to_text(batch, false)?,
+        };
+
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => {
+                let file = File::create(&self.path)
+                    .with_context(|| format!("failed to create {}", self.path))?;
+                let writer = match self.format {
+                    OutputFormat::Parquet => FormatWriter::Parquet(ArrowWriter::try_new(
+                        file,
+                        batch.schema(),
+                        Some(self.properties.clone()),
+                    )?),
+                    OutputFormat::Arrow => FormatWriter::Arrow(ipc::writer::FileWriter::try_new(
+                        BufWriter::new(file),
+                        &batch.schema(),
+                    )?),
+                    OutputFormat::Csv => {
+                        FormatWriter::Csv(Box::new(csv::Writer::new(BufWriter::new(file))))
+                    }
+                    OutputFormat::Ndjson => {
+                        FormatWriter::Ndjson(json::LineDelimitedWriter::new(BufWriter::new(file)))
+                    }
+                };
+                self.writer.insert(writer)
+            }
+        };
+
+        writer
+            .write(&batch, self.max_row_group_bytes)
+            .with_context(|| format!("failed to write to {}", self.path))
+    }
+
+    /// Writes the end of the file (the footer, for parquet and arrow).
+    /// `metadata` is added to the key-value metadata of parquet files.
+    pub fn close(self, metadata: Vec<(String, String)>) -> Result<()> {
+        if let Some(writer) = self.writer {
+            writer
+                .close(metadata)
+                .with_context(|| format!("failed to close {}", self.path))?;
+        }
+        Ok(())
+    }
+}
+
+impl FormatWriter {
+    fn write(&mut self, batch: &RecordBatch, max_row_group_bytes: usize) -> Result<()> {
+        match self {
+            FormatWriter::Parquet(writer) => {
+                writer.write(batch)?;
+                if writer.in_progress_size() >= max_row_group_bytes {
+                    writer.flush()?;
+                }
+            }
+            FormatWriter::Arrow(writer) => writer.write(batch)?,
+            FormatWriter::Csv(writer) => writer.write(batch)?,
+            FormatWriter::Ndjson(writer) => writer.write(batch)?,
+        }
+        Ok(())
+    }
+
+    fn close(self, metadata: Vec<(String, String)>) -> Result<()> {
+        match self {
+            FormatWriter::Parquet(mut writer) => {
+                for (key, value) in metadata {
+                    writer.append_key_value_metadata(KeyValue::new(key, value));
+                }
+                writer.close()?;
+            }
+            FormatWriter::Arrow(mut writer) => {
+                writer.finish()?;
+                writer.into_inner()?.flush()?;
+            }
+            FormatWriter::Csv(writer) => writer.into_inner().flush()?,
+            FormatWriter::Ndjson(mut writer) => {
+                writer.finish()?;
+                writer.into_inner().flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads the key-value metadata of a parquet file.
+pub fn read_file_metadata(path: &str) -> Result<HashMap<String, String>> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path))?;
+    let reader =
+        SerializedFileReader::new(file).with_context(|| format!("failed to read {}", path))?;
+
+    Ok(reader
+        .metadata()
+        .file_metadata()
+        .key_value_metadata()
+        .into_iter()
+        .flatten()
+        .filter_map(|it| Some((it.key.clone(), it.value.clone()?)))
+        .collect())
+}
+
+/// Prepares a batch for a text format: binary columns (addresses, hashes)
+/// become `0x` prefixed hex strings of the stored bytes. With `flatten`, lists
+/// are also turned into strings, CSV has no nested values.
+pub fn to_text(batch: &RecordBatch, flatten: bool) -> Result<RecordBatch> {
+    let mut fields = Vec::with_capacity(batch.num_columns());
+    let mut columns = Vec::with_capacity(batch.num_columns());
+
+    for (field, column) in batch.schema().fields().iter().zip(batch.columns()) {
+        let mut column = hex_encode(column)?;
+        if flatten
+            && matches!(
+                column.data_type(),
+                DataType::List(_) | DataType::LargeList(_)
+            )
+        {
+            column = cast(&column, &DataType::Utf8)?;
+        }
+
+        fields.push(Field::new(
+            field.name(),
+            column.data_type().clone(),
+            field.is_nullable(),
+        ));
+        columns.push(column);
+    }
+
+    Ok(RecordBatch::try_new(
+        Arc::new(Schema::new(fields)),
+        columns,
+    )?)
+}
+
+fn hex_encode(array: &ArrayRef) -> Result<ArrayRef> {
+    Ok(match array.data_type() {
+        DataType::Binary => hex_strings(array.as_binary::<i32>().iter()),
+        DataType::LargeBinary => hex_strings(array.as_binary::<i64>().iter()),
+        DataType::FixedSizeBinary(_) => hex_strings(array.as_fixed_size_binary().iter()),
+        DataType::List(field) => {
+            let list = array.as_list::<i32>();
+            let values = hex_encode(list.values())?;
+            let field = Field::new(
+                field.name(),
+                values.data_type().clone(),
+                field.is_nullable(),
+            );
+            Arc::new(ListArray::try_new(
+                Arc::new(field),
+                list.offsets().clone(),
+                values,
+                list.nulls().cloned(),
+            )?)
+        }
+        DataType::LargeList(field) => {
+            let list = array.as_list::<i64>();
+            let values = hex_encode(list.values())?;
+            let field = Field::new(
+                field.name(),
+                values.data_type().clone(),
+                field.is_nullable(),
+            );
+            Arc::new(LargeListArray::try_new(
+                Arc::new(field),
+                list.offsets().clone(),
+                values,
+                list.nulls().cloned(),
+            )?)
+        }
+        _ => array.clone(),
+    })
+}
+
+fn hex_strings<'a>(values: impl Iterator<Item = Option<&'a [u8]>>) -> ArrayRef {
+    Arc::new(
+        values
+            .map(|value| value.map(|bytes| format!("0x{}", hex::encode(bytes))))
+            .collect::<StringArray>(),
+    )
+}
+
+/// Sorts the rows of a batch by `version`, then by `change_index` (or the
+/// event `index`), the order the ClickHouse tables are stored in. Batches
+/// without a `version` column are returned as is.
+pub fn sort_by_version(batch: RecordBatch) -> Result<RecordBatch> {
+    let columns: Vec<SortColumn> = ["version", "change_index", "index"]
+        .iter()
+        .filter_map(|name| batch.column_by_name(name))
+        .map(|column| SortColumn {
+            values: column.clone(),
+            options: None,
+        })
+        .collect();
+
+    if batch.column_by_name("version").is_none() {
+        return Ok(batch);
+    }
+
+    let indices = lexsort_to_indices(&columns, None)?;
+    Ok(take_record_batch(&batch, &indices)?)
+}
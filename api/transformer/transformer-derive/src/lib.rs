@@ -0,0 +1,296 @@
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitInt, LitStr};
+
+/// Derives `MoveResource` for a row struct and generates the matching
+/// struct-of-arrays columns type.
+///
+/// ```ignore
+/// #[derive(MoveResource)]
+/// #[move_type("0x1::ol_account::BurnTracker")]
+/// #[collection(name = "burn_tracker", with_address)]
+/// pub struct BurnTracker {
+///     burn_at_last_calc: u64,
+///     cumu_burn: u64,
+///     prev_balance: u64,
+///     prev_supply: u64,
+/// }
+/// ```
+///
+/// Struct attributes:
+/// - `move_type("0xADDR::module::Name")`: the resource type to match.
+/// - `collection(name = "...")`: name of the output collection.
+/// - `collection(with_address)`: add an `address` column holding the account
+///   the resource is stored under.
+/// - `collection(root_only)`: only match resources stored under `0x1`.
+/// - `collection(schema_version = 2)`: revision of the columns, 1 by default.
+///
+/// Field attributes:
+/// - `move_field(rename = "...")`: the Move field name, if it differs.
+/// - `move_field(path = "a.b")`: read a field nested in a struct value.
+/// - `move_field(len)`: store the length of a vector instead of its content.
+#[proc_macro_derive(MoveResource, attributes(move_type, collection, move_field))]
+pub fn derive_move_resource(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+struct FieldSpec {
+    ident: syn::Ident,
+    ty: syn::Type,
+    path: Vec<String>,
+    len: bool,
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let ident = &input.ident;
+    let columns_ident = format_ident!("{}Columns", ident);
+
+    let mut move_type: Option<LitStr> = None;
+    let mut collection_name: Option<LitStr> = None;
+    let mut with_address = false;
+    let mut root_only = false;
+    let mut schema_version: Option<LitInt> = None;
+
+    for attr in &input.attrs {
+        if attr.path().is_ident("move_type") {
+            move_type = Some(attr.parse_args()?);
+        } else if attr.path().is_ident("collection") {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("name") {
+                    collection_name = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("with_address") {
+                    with_address = true;
+                } else if meta.path.is_ident("root_only") {
+                    root_only = true;
+                } else if meta.path.is_ident("schema_version") {
+                    schema_version = Some(meta.value()?.parse()?);
+                } else {
+                    return Err(meta.error("unsupported collection attribute"));
+                }
+                Ok(())
+            })?;
+        }
+    }
+
+    let move_type = move_type.ok_or_else(|| {
+        syn::Error::new(
+            Span::call_site(),
+            "missing #[move_type(\"0x1::module::Name\")]",
+        )
+    })?;
+    let collection_name = collection_name.ok_or_else(|| {
+        syn::Error::new(Span::call_site(), "missing #[collection(name = \"...\")]")
+    })?;
+    let schema_version = match schema_version {
+        Some(schema_version) => schema_version.base10_parse::<u32>()?,
+        None => 1,
+    };
+
+    let (type_address, type_module, type_name) = parse_move_type(&move_type)?;
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    ident,
+                    "MoveResource can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                ident,
+                "MoveResource can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut specs = Vec::new();
+    for field in fields {
+        let field_ident = field.ident.clone().unwrap();
+        let mut path = vec![field_ident.to_string()];
+        let mut len = false;
+
+        for attr in &field.attrs {
+            if !attr.path().is_ident("move_field") {
+                continue;
+            }
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    let value: LitStr = meta.value()?.parse()?;
+                    path = vec![value.value()];
+                } else if meta.path.is_ident("path") {
+                    let value: LitStr = meta.value()?.parse()?;
+                    path = value.value().split('.').map(|it| it.to_string()).collect();
+                } else if meta.path.is_ident("len") {
+                    len = true;
+                } else {
+                    return Err(meta.error("unsupported move_field attribute"));
+                }
+                Ok(())
+            })?;
+        }
+
+        specs.push(FieldSpec {
+            ident: field_ident,
+            ty: field.ty.clone(),
+            path,
+            len,
+        });
+    }
+
+    let field_idents: Vec<_> = specs.iter().map(|it| &it.ident).collect();
+    let field_names: Vec<_> = specs.iter().map(|it| it.ident.to_string()).collect();
+    let field_types: Vec<_> = specs.iter().map(|it| &it.ty).collect();
+    let field_parsers: Vec<_> = specs
+        .iter()
+        .map(|spec| {
+            let path = &spec.path;
+            if spec.len {
+                quote! { crate::move_resource::field_len(data, &[#(#path),*])? }
+            } else {
+                quote! { crate::move_resource::field(data, &[#(#path),*])? }
+            }
+        })
+        .collect();
+
+    Ok(quote! {
+        impl crate::move_resource::MoveResource for #ident {
+            const MOVE_TYPE: &'static str = #move_type;
+            const COLLECTION_NAME: &'static str = #collection_name;
+            const WITH_ADDRESS: bool = #with_address;
+            const ROOT_ONLY: bool = #root_only;
+            const SCHEMA_VERSION: u32 = #schema_version;
+
+            type Columns = #columns_ident;
+
+            fn matches(typ: &diem_api_types::MoveStructTag) -> bool {
+                typ.address.inner().to_vec() == [#(#type_address),*]
+                    && typ.module.as_str() == #type_module
+                    && typ.name.as_str() == #type_name
+                    && typ.generic_type_params.is_empty()
+            }
+
+            fn from_move_value(
+                data: &diem_api_types::MoveStructValue,
+            ) -> anyhow::Result<Self> {
+                Ok(#ident {
+                    #(#field_idents: #field_parsers,)*
+                })
+            }
+        }
+
+        #[derive(Default)]
+        pub struct #columns_ident {
+            #(#field_idents: Vec<#field_types>,)*
+        }
+
+        impl crate::move_resource::ResourceColumns for #columns_ident {
+            type Row = #ident;
+
+            fn push(&mut self, row: #ident) {
+                #(self.#field_idents.push(row.#field_idents);)*
+            }
+
+            fn to_arrays(&self) -> anyhow::Result<Vec<(&'static str, arrow_array::ArrayRef)>> {
+                Ok(vec![
+                    #((
+                        #field_names,
+                        crate::move_resource::Column::to_array(&self.#field_idents, #field_names)?,
+                    ),)*
+                ])
+            }
+
+            fn fields() -> Vec<arrow_schema::Field> {
+                vec![
+                    #(arrow_schema::Field::new(
+                        #field_names,
+                        <Vec<#field_types> as crate::move_resource::Column>::data_type(#field_names),
+                        false,
+                    ),)*
+                ]
+            }
+        }
+    })
+}
+
+/// Splits `0x1::module::Name` into the 32 address bytes, module and name.
+fn parse_move_type(move_type: &LitStr) -> syn::Result<(Vec<u8>, String, String)> {
+    let value = move_type.value();
+    let parts: Vec<&str> = value.split("::").collect();
+    if parts.len() != 3 {
+        return Err(syn::Error::new_spanned(
+            move_type,
+            "expected a struct tag like 0x1::module::Name",
+        ));
+    }
+
+    let address = parts[0]
+        .strip_prefix("0x")
+        .ok_or_else(|| syn::Error::new_spanned(move_type, "the address must start with 0x"))?;
+    // Only ASCII hex digits, so the address can be sliced by byte offsets.
+    if address.is_empty() || address.len() > 64 || !address.bytes().all(|it| it.is_ascii_hexdigit())
+    {
+        return Err(syn::Error::new_spanned(move_type, "invalid address"));
+    }
+    if !is_identifier(parts[1]) || !is_identifier(parts[2]) {
+        return Err(syn::Error::new_spanned(
+            move_type,
+            "the module and struct names must be Move identifiers",
+        ));
+    }
+    let address = format!("{:0>64}", address);
+
+    let mut bytes = Vec::with_capacity(32);
+    for i in (0..64).step_by(2) {
+        let byte = u8::from_str_radix(&address[i..i + 2], 16)
+            .map_err(|_| syn::Error::new_spanned(move_type, "invalid address"))?;
+        bytes.push(byte);
+    }
+
+    Ok((bytes, parts[1].to_string(), parts[2].to_string()))
+}
+
+/// Whether `name` is a valid Move identifier: ASCII letters, digits and `_`,
+/// not starting with a digit.
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|it| it.is_ascii_alphabetic() || it == '_')
+        && chars.all(|it| it.is_ascii_alphanumeric() || it == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(move_type: &str) -> syn::Result<(Vec<u8>, String, String)> {
+        parse_move_type(&LitStr::new(move_type, Span::call_site()))
+    }
+
+    #[test]
+    fn struct_tags_are_split() {
+        let (address, module, name) = parse("0x1::burn::BurnCounter").unwrap();
+        let mut expected = vec![0; 32];
+        expected[31] = 1;
+        assert_eq!(address, expected);
+        assert_eq!(module, "burn");
+        assert_eq!(name, "BurnCounter");
+    }
+
+    #[test]
+    fn non_ascii_struct_tags_are_errors() {
+        assert!(parse("0xé::burn::BurnCounter").is_err());
+        assert!(parse("0x1é1::burn::BurnCounter").is_err());
+        assert!(parse("0x1::brûlé::BurnCounter").is_err());
+        assert!(parse("0x1::burn::Compteur€").is_err());
+        assert!(parse("0x1::burn::").is_err());
+    }
+}